@@ -1,6 +1,6 @@
 use anyhow;
 use sha1::{Digest, Sha1};
-use std::io::{self, Read, Write};
+use std::io::{self, Cursor, Read, Write};
 
 const CHECKSUM_SIZE: usize = 20;
 
@@ -57,3 +57,10 @@ impl<R: Read + Write> Checksum<R> {
         Ok(())
     }
 }
+
+impl Checksum<Cursor<Vec<u8>>> {
+    /// Number of bytes left to read, including the trailing checksum.
+    pub fn remaining(&self) -> usize {
+        self.file.get_ref().len() - self.file.position() as usize
+    }
+}
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use clap::{command, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(about)]
@@ -15,6 +15,18 @@ pub enum Commands {
     Init {
         /// Root path
         root_path: Option<PathBuf>,
+
+        /// Create a bare repository
+        #[arg(long)]
+        bare: bool,
+
+        /// Name of the branch HEAD points at
+        #[arg(short = 'b', long)]
+        initial_branch: Option<String>,
+
+        /// Directory to copy templates from
+        #[arg(long)]
+        template: Option<PathBuf>,
     },
 
     /// Add
@@ -25,5 +37,695 @@ pub enum Commands {
     },
 
     /// Commit
-    Commit {},
+    Commit {
+        /// Bypass the pre-commit and commit-msg hooks
+        #[arg(short = 'n', long)]
+        no_verify: bool,
+    },
+
+    /// Print the object IDs of the given revisions
+    RevParse {
+        /// Revisions to resolve
+        #[arg(required = true)]
+        revisions: Vec<String>,
+    },
+
+    /// Update the object name stored in a ref safely
+    UpdateRef {
+        /// Reason recorded in the reflog
+        #[arg(short = 'm')]
+        message: Option<String>,
+
+        /// Delete the ref
+        #[arg(short = 'd')]
+        delete: bool,
+
+        /// Read update commands from standard input and apply them atomically
+        #[arg(long, conflicts_with_all = ["delete", "reference"])]
+        stdin: bool,
+
+        /// Ref to update
+        #[arg(required_unless_present = "stdin")]
+        reference: Option<String>,
+
+        /// New value, or the expected old value with -d
+        new_value: Option<String>,
+
+        /// Expected old value
+        old_value: Option<String>,
+    },
+
+    /// Pack refs into a single packed-refs file
+    PackRefs {
+        /// Pack all refs, not only tags and already packed refs
+        #[arg(long)]
+        all: bool,
+
+        /// Keep the loose ref files after packing them
+        #[arg(long)]
+        no_prune: bool,
+    },
+
+    /// Create, list or delete tags
+    Tag {
+        /// Create an annotated tag object
+        #[arg(short = 'a', long)]
+        annotate: bool,
+
+        /// Tag message; implies -a
+        #[arg(short = 'm', long)]
+        message: Option<String>,
+
+        /// Replace an existing tag
+        #[arg(short = 'f', long)]
+        force: bool,
+
+        /// Delete the given tags
+        #[arg(short = 'd', long, conflicts_with_all = ["annotate", "message", "list"])]
+        delete: bool,
+
+        /// List tags matching the given patterns
+        #[arg(short = 'l', long)]
+        list: bool,
+
+        /// Print <n> lines of each tag's annotation when listing
+        #[arg(short = 'n', num_args = 0..=1, default_missing_value = "1")]
+        lines: Option<usize>,
+
+        /// Sort key: refname or version:refname, '-' prefix reverses
+        #[arg(long)]
+        sort: Option<String>,
+
+        /// Tag name and object, patterns when listing, or tags to delete
+        args: Vec<String>,
+    },
+
+    /// Output information on each ref
+    ForEachRef {
+        /// Format string with %(atom) placeholders
+        #[arg(long)]
+        format: Option<String>,
+
+        /// Field to sort on, '-' prefix reverses; the last key is primary
+        #[arg(long)]
+        sort: Vec<String>,
+
+        /// Stop after showing <count> refs
+        #[arg(long)]
+        count: Option<usize>,
+
+        /// Only list refs whose commit contains the given commit
+        #[arg(long)]
+        contains: Option<String>,
+
+        /// Only list refs reachable from the given commit
+        #[arg(long)]
+        merged: Option<String>,
+
+        /// Only list refs not reachable from the given commit
+        #[arg(long)]
+        no_merged: Option<String>,
+
+        /// Only list refs matching these prefixes or globs
+        patterns: Vec<String>,
+    },
+
+    /// List refs in a local repository
+    ShowRef {
+        /// Only show branches
+        #[arg(long)]
+        heads: bool,
+
+        /// Only show tags
+        #[arg(long)]
+        tags: bool,
+
+        /// Also show the objects annotated tags point at
+        #[arg(short = 'd', long)]
+        dereference: bool,
+
+        /// Only show object names, abbreviated to <n> characters if given
+        #[arg(short = 's', long, num_args = 0..=1, require_equals = true, default_missing_value = "0")]
+        hash: Option<usize>,
+
+        /// Require exact ref names
+        #[arg(long)]
+        verify: bool,
+
+        /// Patterns matching trailing components of ref names
+        patterns: Vec<String>,
+    },
+
+    /// Compute object ID and optionally create an object from a file
+    HashObject {
+        /// Type of object to create
+        #[arg(short = 't', default_value = "blob")]
+        kind: String,
+
+        /// Write the object into the object database
+        #[arg(short = 'w')]
+        write: bool,
+
+        /// Read the object from standard input
+        #[arg(long)]
+        stdin: bool,
+
+        /// Files to hash
+        paths: Vec<PathBuf>,
+    },
+
+    /// Show the type, size or content of an object
+    #[command(group = clap::ArgGroup::new("mode").required(true))]
+    CatFile {
+        /// Show the object's type
+        #[arg(short = 't', group = "mode")]
+        kind: bool,
+
+        /// Show the object's size
+        #[arg(short = 's', group = "mode")]
+        size: bool,
+
+        /// Exit with status zero if the object exists, silently
+        #[arg(short = 'e', group = "mode")]
+        exists: bool,
+
+        /// Pretty-print the object's content
+        #[arg(short = 'p', group = "mode")]
+        pretty: bool,
+
+        /// The object to show
+        object: String,
+    },
+
+    /// Create a tree object from the current index
+    WriteTree,
+
+    /// Create a new commit object
+    CommitTree {
+        /// Tree the commit records
+        tree: String,
+
+        /// Parent commit; may be given more than once
+        #[arg(short = 'p')]
+        parents: Vec<String>,
+
+        /// Commit message paragraph; may be given more than once
+        #[arg(short = 'm')]
+        messages: Vec<String>,
+
+        /// Read the commit message from a file
+        #[arg(short = 'F', conflicts_with = "messages")]
+        file: Option<PathBuf>,
+    },
+
+    /// List the contents of a tree object
+    LsTree {
+        /// Recurse into subtrees
+        #[arg(short = 'r')]
+        recursive: bool,
+
+        /// Show tree entries even when recursing into them
+        #[arg(short = 't')]
+        show_trees: bool,
+
+        /// List only file names
+        #[arg(long)]
+        name_only: bool,
+
+        /// Tree-ish to list
+        treeish: String,
+
+        /// Only show entries at or below these paths
+        paths: Vec<String>,
+    },
+
+    /// Compare the content and mode of blobs found via two tree objects
+    DiffTree {
+        /// Recurse into subtrees
+        #[arg(short = 'r')]
+        recursive: bool,
+
+        /// Show a patch for each change (implies -r)
+        #[arg(short = 'p', long)]
+        patch: bool,
+
+        /// Show only the names of changed files
+        #[arg(long, conflicts_with_all = ["patch", "name_status"])]
+        name_only: bool,
+
+        /// Show the names and status of changed files
+        #[arg(long, conflicts_with = "patch")]
+        name_status: bool,
+
+        /// Detect renames, optionally only above a similarity threshold
+        #[arg(short = 'M', long, num_args = 0..=1, default_missing_value = "")]
+        find_renames: Option<String>,
+
+        /// Detect copies as well as renames; given twice, same as --find-copies-harder
+        #[arg(
+            short = 'C',
+            long,
+            num_args = 0..=1,
+            default_missing_value = "",
+            action = clap::ArgAction::Append
+        )]
+        find_copies: Vec<String>,
+
+        /// Consider unmodified files as copy sources too
+        #[arg(long)]
+        find_copies_harder: bool,
+
+        /// A commit to compare with its parent, or two trees
+        #[arg(num_args = 1..=2)]
+        trees: Vec<String>,
+    },
+
+    /// Show information about files in the index and the working tree
+    LsFiles {
+        /// Show cached files (the default)
+        #[arg(short = 'c', long)]
+        cached: bool,
+
+        /// Show files deleted from the working tree
+        #[arg(short = 'd', long)]
+        deleted: bool,
+
+        /// Show modified files
+        #[arg(short = 'm', long)]
+        modified: bool,
+
+        /// Show untracked files
+        #[arg(short = 'o', long)]
+        others: bool,
+
+        /// Show mode, object name and stage of each entry
+        #[arg(short = 's', long)]
+        stage: bool,
+
+        /// Show only the conflict stages of unmerged files
+        #[arg(short = 'u', long)]
+        unmerged: bool,
+
+        /// Only show files at or below these paths
+        paths: Vec<PathBuf>,
+    },
+
+    /// Register file contents in the working tree to the index
+    UpdateIndex {
+        /// Add files not yet in the index
+        #[arg(long)]
+        add: bool,
+
+        /// Remove files that no longer exist in the working tree
+        #[arg(long)]
+        remove: bool,
+
+        /// Remove files from the index even if they still exist
+        #[arg(long)]
+        force_remove: bool,
+
+        /// Refresh stat information of entries whose content is unchanged
+        #[arg(long)]
+        refresh: bool,
+
+        /// Set the executable bit of the given files: +x or -x
+        #[arg(long, allow_hyphen_values = true)]
+        chmod: Option<String>,
+
+        /// Insert <mode>,<object>,<path> directly into the index
+        #[arg(long)]
+        cacheinfo: Vec<String>,
+
+        /// Mark the given files as unchanged, so their stat data is not checked
+        #[arg(long, overrides_with = "no_assume_unchanged")]
+        assume_unchanged: bool,
+
+        /// Clear the assume-unchanged mark of the given files
+        #[arg(long, overrides_with = "assume_unchanged")]
+        no_assume_unchanged: bool,
+
+        /// Mark the given files as left out of the working tree
+        #[arg(long, overrides_with = "no_skip_worktree")]
+        skip_worktree: bool,
+
+        /// Clear the skip-worktree mark of the given files
+        #[arg(long, overrides_with = "skip_worktree")]
+        no_skip_worktree: bool,
+
+        /// Write the index in the given format version (2, 3 or 4)
+        #[arg(long)]
+        index_version: Option<u32>,
+
+        /// Files to update
+        paths: Vec<PathBuf>,
+    },
+
+    /// Join another line of history into the current branch
+    Merge {
+        /// Fast-forward when possible, otherwise create a merge commit (default)
+        #[arg(long, overrides_with_all = ["no_ff", "ff_only"])]
+        ff: bool,
+
+        /// Always create a merge commit, even when a fast-forward is possible
+        #[arg(long, overrides_with_all = ["ff", "ff_only"])]
+        no_ff: bool,
+
+        /// Refuse to merge unless HEAD can be fast-forwarded
+        #[arg(long, overrides_with_all = ["ff", "no_ff"])]
+        ff_only: bool,
+
+        /// Message for the merge commit
+        #[arg(short = 'm', long = "message")]
+        message: Option<String>,
+
+        /// Bypass the pre-commit and commit-msg hooks when concluding a merge
+        #[arg(short = 'n', long)]
+        no_verify: bool,
+
+        /// Conclude a merge once its conflicts are resolved
+        #[arg(long = "continue", conflicts_with_all = ["abort", "commit"])]
+        cont: bool,
+
+        /// Give up a conflicted merge and go back to the state before it
+        #[arg(long, conflicts_with = "commit")]
+        abort: bool,
+
+        /// Commit to merge into the current branch
+        #[arg(required_unless_present_any = ["cont", "abort"])]
+        commit: Option<String>,
+    },
+
+    /// Apply the changes introduced by existing commits
+    CherryPick {
+        /// Go on once the conflicts of the current commit are resolved
+        #[arg(long = "continue", conflicts_with_all = ["skip", "abort", "commits"])]
+        cont: bool,
+
+        /// Drop the current commit and go on with the rest
+        #[arg(long, conflicts_with_all = ["abort", "commits"])]
+        skip: bool,
+
+        /// Give up and go back to the state before the cherry-pick
+        #[arg(long, conflicts_with = "commits")]
+        abort: bool,
+
+        /// Bypass the pre-commit and commit-msg hooks when committing with --continue
+        #[arg(long)]
+        no_verify: bool,
+
+        /// Commits to apply, or <from>..<to> ranges of them
+        #[arg(required_unless_present_any = ["cont", "skip", "abort"])]
+        commits: Vec<String>,
+    },
+
+    /// Revert the changes introduced by existing commits
+    Revert {
+        /// Go on once the conflicts of the current commit are resolved
+        #[arg(long = "continue", conflicts_with_all = ["skip", "abort", "commits"])]
+        cont: bool,
+
+        /// Drop the current commit and go on with the rest
+        #[arg(long, conflicts_with_all = ["abort", "commits"])]
+        skip: bool,
+
+        /// Give up and go back to the state before the revert
+        #[arg(long, conflicts_with = "commits")]
+        abort: bool,
+
+        /// Bypass the pre-commit and commit-msg hooks when committing with --continue
+        #[arg(long)]
+        no_verify: bool,
+
+        /// Commits to revert, or <from>..<to> ranges of them
+        #[arg(required_unless_present_any = ["cont", "skip", "abort"])]
+        commits: Vec<String>,
+    },
+
+    /// Reapply the commits of a branch on top of another base
+    Rebase {
+        /// Edit the list of commits to rebase before starting
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// Rebase onto <newbase> instead of <upstream>
+        #[arg(long, value_name = "newbase")]
+        onto: Option<String>,
+
+        /// Move "fixup!" and "squash!" commits next to the commits they amend
+        #[arg(long)]
+        autosquash: bool,
+
+        /// Go on once the current commit is resolved or amended
+        #[arg(long = "continue", conflicts_with_all = ["skip", "abort", "upstream"])]
+        cont: bool,
+
+        /// Drop the current commit and go on with the rest
+        #[arg(long, conflicts_with_all = ["abort", "upstream"])]
+        skip: bool,
+
+        /// Give up and check out the branch as it was before the rebase
+        #[arg(long, conflicts_with = "upstream")]
+        abort: bool,
+
+        /// The branch to rebase against
+        upstream: Option<String>,
+
+        /// Check out this branch before rebasing it
+        branch: Option<String>,
+    },
+
+    /// Run a three-way file merge, writing the result over <current>
+    MergeFile {
+        /// Labels for the current, base and other file in conflict markers
+        #[arg(short = 'L', num_args = 1, action = clap::ArgAction::Append)]
+        labels: Vec<String>,
+
+        /// Write the result to standard output instead of the current file
+        #[arg(short = 'p', long = "stdout")]
+        stdout: bool,
+
+        /// Also show the base version in conflicts
+        #[arg(long, overrides_with = "zdiff3")]
+        diff3: bool,
+
+        /// Like --diff3, but move lines both sides agree on out of conflicts
+        #[arg(long, overrides_with = "diff3")]
+        zdiff3: bool,
+
+        /// Resolve conflicts by taking our side
+        #[arg(long, conflicts_with_all = ["theirs", "union"])]
+        ours: bool,
+
+        /// Resolve conflicts by taking their side
+        #[arg(long, conflicts_with = "union")]
+        theirs: bool,
+
+        /// Resolve conflicts by taking both sides
+        #[arg(long)]
+        union: bool,
+
+        current: PathBuf,
+        base: PathBuf,
+        other: PathBuf,
+    },
+
+    /// Find as good common ancestors as possible for a merge
+    MergeBase {
+        /// Output all merge bases instead of just one
+        #[arg(short = 'a', long)]
+        all: bool,
+
+        /// Find the common ancestors of all commits, for an octopus merge
+        #[arg(long, conflicts_with_all = ["is_ancestor", "fork_point"])]
+        octopus: bool,
+
+        /// Exit with status 0 if the first commit is an ancestor of the second, 1 if not
+        #[arg(long, conflicts_with_all = ["all", "fork_point"])]
+        is_ancestor: bool,
+
+        /// Find where a commit (HEAD by default) forked from the history of <ref>
+        #[arg(long, conflicts_with = "all")]
+        fork_point: bool,
+
+        /// Commits, or <ref> [<commit>] with --fork-point
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
+
+    /// Read tree information into the index
+    ReadTree {
+        /// Merge the trees into the index instead of replacing it
+        #[arg(short = 'm')]
+        merge: bool,
+
+        /// Update the working tree with the merge result
+        #[arg(short = 'u')]
+        update: bool,
+
+        /// Merge in the index only, ignoring the working tree
+        #[arg(short = 'i')]
+        index_only: bool,
+
+        /// Also resolve paths removed on one or both sides in a three-way merge
+        #[arg(long)]
+        aggressive: bool,
+
+        /// One tree, or up to three to merge
+        #[arg(required = true, num_args = 1..=3)]
+        trees: Vec<String>,
+    },
+
+    /// Copy files from the index to the working tree
+    CheckoutIndex {
+        /// Check out all files in the index
+        #[arg(short = 'a', long)]
+        all: bool,
+
+        /// Overwrite existing files
+        #[arg(short = 'f', long)]
+        force: bool,
+
+        /// Prepend <prefix> to the names of the files written
+        #[arg(long)]
+        prefix: Option<String>,
+
+        /// Files to check out
+        paths: Vec<PathBuf>,
+    },
+
+    /// Manage reflog information
+    Reflog {
+        #[command(subcommand)]
+        command: Option<ReflogCommands>,
+    },
+
+    /// Stash the changes in a dirty working directory away
+    Stash {
+        #[command(subcommand)]
+        command: Option<StashCommands>,
+    },
+
+    /// Remove untracked files from the working tree
+    Clean {
+        /// Only show what would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Remove files even if clean.requireForce is not false
+        #[arg(short = 'f', long)]
+        force: bool,
+
+        /// Remove untracked directories too
+        #[arg(short = 'd')]
+        dirs: bool,
+
+        /// Remove ignored files too
+        #[arg(short = 'x', conflicts_with = "only_ignored")]
+        ignored: bool,
+
+        /// Remove only ignored files
+        #[arg(short = 'X')]
+        only_ignored: bool,
+
+        /// Limit cleaning to these paths
+        paths: Vec<PathBuf>,
+    },
+
+    /// Restore working tree files
+    Restore {
+        /// Restore from this tree-ish instead of the index, or HEAD with --staged
+        #[arg(short = 's', long)]
+        source: Option<String>,
+
+        /// Restore the index
+        #[arg(short = 'S', long)]
+        staged: bool,
+
+        /// Restore the working tree, the default without --staged
+        #[arg(short = 'W', long)]
+        worktree: bool,
+
+        /// Paths to restore
+        paths: Vec<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ReflogCommands {
+    /// Show the entries of a reflog
+    Show {
+        /// Ref whose log to show, HEAD by default
+        reference: Option<String>,
+    },
+
+    /// Prune old reflog entries
+    Expire {
+        /// Prune entries older than this date
+        #[arg(long)]
+        expire: Option<String>,
+
+        /// Process the reflogs of all refs
+        #[arg(long)]
+        all: bool,
+
+        /// Only report what would be pruned
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Refs whose logs to prune
+        references: Vec<String>,
+    },
+
+    /// Delete single entries, given as <ref>@{<n>}
+    Delete {
+        #[arg(required = true)]
+        entries: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum StashCommands {
+    /// Save the local changes as a new stash entry and revert them
+    Push {
+        /// Stash untracked files too, and remove them
+        #[arg(short = 'u', long)]
+        include_untracked: bool,
+
+        /// Leave the changes already added to the index in place
+        #[arg(short, long)]
+        keep_index: bool,
+
+        /// Describe the entry with this message
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+
+    /// List the stash entries
+    List,
+
+    /// Apply a stash entry on top of the current working directory
+    Apply {
+        /// Restore the changes that were staged in the index as well
+        #[arg(long)]
+        index: bool,
+
+        /// The entry to apply, stash@{0} by default
+        stash: Option<String>,
+    },
+
+    /// Apply a stash entry and remove it from the stash
+    Pop {
+        /// Restore the changes that were staged in the index as well
+        #[arg(long)]
+        index: bool,
+
+        /// The entry to apply, stash@{0} by default
+        stash: Option<String>,
+    },
+
+    /// Remove a stash entry
+    Drop {
+        /// The entry to remove, stash@{0} by default
+        stash: Option<String>,
+    },
 }
@@ -0,0 +1,52 @@
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+fn unit_duration(unit: &str, count: i64) -> Option<Duration> {
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+
+    match unit {
+        "second" | "sec" => Some(Duration::seconds(count)),
+        "minute" | "min" => Some(Duration::minutes(count)),
+        "hour" => Some(Duration::hours(count)),
+        "day" => Some(Duration::days(count)),
+        "week" => Some(Duration::weeks(count)),
+        "month" => Some(Duration::days(count * 30)),
+        "year" => Some(Duration::days(count * 365)),
+        _ => None,
+    }
+}
+
+/// Parses the date forms Git users most commonly type: `now`,
+/// `yesterday`, `<n>.<unit>.ago` (or with spaces), `@<unix timestamp>`,
+/// `YYYY-MM-DD` and `YYYY-MM-DD HH:MM[:SS]` in local time.
+pub fn parse_approxidate(value: &str, now: DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>> {
+    let value = value.trim();
+    let normalized = value.replace(['.', '_'], " ").to_lowercase();
+    let words: Vec<&str> = normalized.split_whitespace().collect();
+
+    match words.as_slice() {
+        ["now"] => return Some(now),
+        ["yesterday"] => return Some(now - Duration::days(1)),
+        [count, unit, "ago"] => return unit_duration(unit, count.parse().ok()?).map(|d| now - d),
+        [unit, "ago"] => return unit_duration(unit, 1).map(|d| now - d),
+        _ => {}
+    }
+
+    if let Some(timestamp) = value.strip_prefix('@') {
+        return now
+            .timezone()
+            .timestamp_opt(timestamp.parse().ok()?, 0)
+            .single();
+    }
+
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|d| d.and_hms_opt(0, 0, 0).unwrap())
+        })
+        .ok()?;
+
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|date| date.fixed_offset())
+}
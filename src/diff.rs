@@ -0,0 +1,911 @@
+//! Line diffs computed the way Git's xdiff does, so that hunks, and the
+//! merges built from them, come out the same as Git's.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Edit cost after which the search gives up on a minimal diff and
+/// settles for the furthest reaching path, unless the inputs are large
+const MAX_COST_MIN: isize = 256;
+/// Edit cost after which long snakes are taken as split points
+const HEUR_MIN_COST: isize = 256;
+const SNAKE_CNT: isize = 20;
+const K_HEUR: isize = 4;
+/// Lines occurring this often in the other file may be left out of the
+/// search
+const MAX_EQLIMIT: usize = 1024;
+const SIMSCAN_WINDOW: usize = 100;
+const KPDIS_RUN: usize = 4;
+
+/// A run of `a[a_start..a_end]` that was replaced by `b[b_start..b_end]`.
+/// Either side may be empty, for a pure insertion or deletion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hunk {
+    pub a_start: usize,
+    pub a_end: usize,
+    pub b_start: usize,
+    pub b_end: usize,
+}
+
+/// Splits `data` into lines, each keeping its `\n`. The last line lacks
+/// one when the data does not end in a newline.
+pub fn lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Finds the hunks that turn `a` into `b` with Myers' algorithm, as
+/// xdiff implements it, and slides each hunk to where Git would place it.
+pub fn diff<T: Eq + Hash>(a: &[T], b: &[T]) -> Vec<Hunk> {
+    diff_sides(a, b, None)
+}
+
+/// `diff` for lines of text, sliding hunks that could go several places
+/// to where the indentation around them suggests a block starts and ends,
+/// as `git diff` does by default.
+pub fn diff_indented(a: &[&[u8]], b: &[&[u8]]) -> Vec<Hunk> {
+    let indents = |lines: &[&[u8]]| lines.iter().map(|line| indent(line)).collect();
+    diff_sides(a, b, Some([indents(a), indents(b)]))
+}
+
+fn diff_sides<T: Eq + Hash>(a: &[T], b: &[T], indents: Option<[Vec<isize>; 2]>) -> Vec<Hunk> {
+    // Lines are compared by class, equal lines sharing one
+    let mut classes: HashMap<&T, usize> = HashMap::new();
+    let mut counts: Vec<[usize; 2]> = Vec::new();
+    let mut classify = |line, side: usize| {
+        let next = classes.len();
+        let class = *classes.entry(line).or_insert(next);
+        if class == counts.len() {
+            counts.push([0, 0]);
+        }
+        counts[class][side] += 1;
+        class
+    };
+    let mut a = Side::new(a.iter().map(|line| classify(line, 0)).collect());
+    let mut b = Side::new(b.iter().map(|line| classify(line, 1)).collect());
+
+    let limit = a.len().min(b.len());
+    let start = (0..limit)
+        .take_while(|&i| a.classes[i] == b.classes[i])
+        .count();
+    let tail = (0..limit - start)
+        .take_while(|&i| a.classes[a.len() - 1 - i] == b.classes[b.len() - 1 - i])
+        .count();
+
+    a.keep_lines(start..a.len() - tail, &counts, 1);
+    b.keep_lines(start..b.len() - tail, &counts, 0);
+
+    let mut search = Search::new(&a.kept, &b.kept);
+    search.compare(0, a.kept.len() as isize, 0, b.kept.len() as isize, false);
+    let (changed_a, changed_b) = (search.changed_a, search.changed_b);
+    a.mark_changed(&changed_a);
+    b.mark_changed(&changed_b);
+
+    let [a_indents, b_indents] = match &indents {
+        Some([a, b]) => [Some(a.as_slice()), Some(b.as_slice())],
+        None => [None, None],
+    };
+    compact(&mut a, &b, a_indents);
+    compact(&mut b, &a, b_indents);
+
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if a.is_changed(i as isize) || b.is_changed(j as isize) {
+            let (a_start, b_start) = (i, j);
+            while a.is_changed(i as isize) {
+                i += 1;
+            }
+            while b.is_changed(j as isize) {
+                j += 1;
+            }
+            hunks.push(Hunk {
+                a_start,
+                a_end: i,
+                b_start,
+                b_end: j,
+            });
+        } else {
+            i += 1;
+            j += 1;
+        }
+    }
+    hunks
+}
+
+/// Writes the hunks turning `a` into `b` in unified format, with
+/// `context` lines around each change. Changes closer than twice the
+/// context share a hunk, and each hunk header names the nearest line above
+/// it that starts like a function, as Git's default does.
+pub fn unified(a: &[u8], b: &[u8], context: usize, out: &mut Vec<u8>) {
+    let (a, b) = (lines(a), lines(b));
+    let hunks = diff_indented(&a, &b);
+
+    let mut rest = &hunks[..];
+    while let [first, ..] = rest {
+        let count = rest
+            .windows(2)
+            .take_while(|pair| pair[1].a_start - pair[0].a_end <= 2 * context)
+            .count()
+            + 1;
+        let (group, next) = rest.split_at(count);
+        rest = next;
+        let last = group[count - 1];
+
+        let a_start = first.a_start.saturating_sub(context);
+        let b_start = first.b_start.saturating_sub(context);
+        let after = context.min(a.len() - last.a_end).min(b.len() - last.b_end);
+        let (a_end, b_end) = (last.a_end + after, last.b_end + after);
+
+        out.extend_from_slice(
+            format!(
+                "@@ -{} +{} @@",
+                hunk_range(a_start, a_end),
+                hunk_range(b_start, b_end)
+            )
+            .as_bytes(),
+        );
+        if let Some(name) = a[..a_start]
+            .iter()
+            .rev()
+            .find_map(|line| function_name(line))
+        {
+            out.push(b' ');
+            out.extend_from_slice(name);
+        }
+        out.push(b'\n');
+
+        let mut i = a_start;
+        for hunk in group {
+            push_lines(out, b' ', &a[i..hunk.a_start]);
+            push_lines(out, b'-', &a[hunk.a_start..hunk.a_end]);
+            push_lines(out, b'+', &b[hunk.b_start..hunk.b_end]);
+            i = hunk.a_end;
+        }
+        push_lines(out, b' ', &a[i..a_end]);
+    }
+}
+
+/// A hunk header range: the first line and the count, which is left out
+/// when it is one. Empty ranges name the line before them.
+fn hunk_range(start: usize, end: usize) -> String {
+    match end - start {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        count => format!("{},{}", start + 1, count),
+    }
+}
+
+/// The part of `line` shown in hunk headers, if it starts like a function
+/// definition: with a letter, `_` or `$`.
+fn function_name(line: &[u8]) -> Option<&[u8]> {
+    let first = *line.first()?;
+    if !first.is_ascii_alphabetic() && first != b'_' && first != b'$' {
+        return None;
+    }
+    let line = &line[..line.len().min(80)];
+    let end = line
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    Some(&line[..end])
+}
+
+fn push_lines(out: &mut Vec<u8>, prefix: u8, lines: &[&[u8]]) {
+    for line in lines {
+        out.push(prefix);
+        out.extend_from_slice(line);
+        if !line.ends_with(b"\n") {
+            out.extend_from_slice(b"\n\\ No newline at end of file\n");
+        }
+    }
+}
+
+/// xdiff's `xdl_bogosqrt`: a power of two near the square root of `n`.
+fn bogosqrt(mut n: usize) -> usize {
+    let mut i = 1;
+    while n > 0 {
+        i <<= 1;
+        n >>= 2;
+    }
+    i
+}
+
+/// One file being compared.
+struct Side {
+    classes: Vec<usize>,
+    /// Whether each line is changed, with an unchanged line before the
+    /// first and after the last
+    changed: Vec<bool>,
+    /// The classes of the lines taking part in the search, and their
+    /// line numbers
+    kept: Vec<usize>,
+    index: Vec<usize>,
+}
+
+impl Side {
+    fn new(classes: Vec<usize>) -> Self {
+        let changed = vec![false; classes.len() + 2];
+        Self {
+            classes,
+            changed,
+            kept: Vec::new(),
+            index: Vec::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.classes.len()
+    }
+
+    fn is_changed(&self, line: isize) -> bool {
+        self.changed[(line + 1) as usize]
+    }
+
+    fn set_changed(&mut self, line: usize, changed: bool) {
+        self.changed[line + 1] = changed;
+    }
+
+    /// Marks the kept lines the search found changed.
+    fn mark_changed(&mut self, changed: &[bool]) {
+        for (i, &changed) in changed.iter().enumerate() {
+            if changed {
+                self.set_changed(self.index[i], true);
+            }
+        }
+    }
+
+    /// Picks the lines in `range` the search has to consider. A line
+    /// missing from the other file is changed for sure. One found there
+    /// very often is left out too when it sits among such lines, which
+    /// keeps the search from matching up common lines like blank ones
+    /// across unrelated changes.
+    fn keep_lines(&mut self, range: std::ops::Range<usize>, counts: &[[usize; 2]], other: usize) {
+        let limit = bogosqrt(self.len()).min(MAX_EQLIMIT);
+        let discard: Vec<u8> = self.classes[range.clone()]
+            .iter()
+            .map(|&class| match counts[class][other] {
+                0 => 0,
+                n if n >= limit => 2,
+                _ => 1,
+            })
+            .collect();
+
+        for (i, line) in range.enumerate() {
+            if discard[i] == 1 || (discard[i] == 2 && !discard_multimatch(&discard, i)) {
+                self.kept.push(self.classes[line]);
+                self.index.push(line);
+            } else {
+                self.set_changed(line, true);
+            }
+        }
+    }
+}
+
+/// Whether the often repeated line `i` is surrounded by enough lines
+/// that have no match to be left out of the search (`xdl_clean_mmatch`).
+fn discard_multimatch(discard: &[u8], i: usize) -> bool {
+    let start = i.saturating_sub(SIMSCAN_WINDOW);
+    let end = (discard.len() - 1).min(i + SIMSCAN_WINDOW);
+
+    let run = |lines: &mut dyn Iterator<Item = usize>| {
+        let (mut unmatched, mut multimatch) = (0, 1);
+        for j in lines {
+            match discard[j] {
+                0 => unmatched += 1,
+                2 => multimatch += 1,
+                _ => break,
+            }
+        }
+        (unmatched, multimatch)
+    };
+
+    let (before, multi_before) = run(&mut (start..i).rev());
+    if before == 0 {
+        return false;
+    }
+    let (after, multi_after) = run(&mut (i + 1..=end));
+    if after == 0 {
+        return false;
+    }
+
+    let unmatched = before + after;
+    let multimatch = multi_before + multi_after;
+    multimatch * KPDIS_RUN < multimatch + unmatched
+}
+
+struct Split {
+    i1: isize,
+    i2: isize,
+    min_lo: bool,
+    min_hi: bool,
+}
+
+/// The divide and conquer search of `xdl_recs_cmp`, over the kept lines.
+struct Search<'a> {
+    a: &'a [usize],
+    b: &'a [usize],
+    changed_a: Vec<bool>,
+    changed_b: Vec<bool>,
+    /// Furthest reaching paths by diagonal, forward and backward
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    offset: isize,
+    max_cost: isize,
+}
+
+impl<'a> Search<'a> {
+    fn new(a: &'a [usize], b: &'a [usize]) -> Self {
+        let diagonals = a.len() + b.len() + 3;
+        Self {
+            a,
+            b,
+            changed_a: vec![false; a.len()],
+            changed_b: vec![false; b.len()],
+            forward: vec![0; diagonals],
+            backward: vec![0; diagonals],
+            offset: b.len() as isize + 1,
+            max_cost: (bogosqrt(diagonals) as isize).max(MAX_COST_MIN),
+        }
+    }
+
+    fn compare(
+        &mut self,
+        mut off1: isize,
+        mut lim1: isize,
+        mut off2: isize,
+        mut lim2: isize,
+        need_min: bool,
+    ) {
+        let (a, b) = (self.a, self.b);
+        while off1 < lim1 && off2 < lim2 && a[off1 as usize] == b[off2 as usize] {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1 && off2 < lim2 && a[lim1 as usize - 1] == b[lim2 as usize - 1] {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+
+        if off1 == lim1 {
+            for i in off2..lim2 {
+                self.changed_b[i as usize] = true;
+            }
+        } else if off2 == lim2 {
+            for i in off1..lim1 {
+                self.changed_a[i as usize] = true;
+            }
+        } else {
+            let split = self.split(off1, lim1, off2, lim2, need_min);
+            self.compare(off1, split.i1, off2, split.i2, split.min_lo);
+            self.compare(split.i1, lim1, split.i2, lim2, split.min_hi);
+        }
+    }
+
+    /// Finds where to split the box between (`off1`, `off2`) and
+    /// (`lim1`, `lim2`): the middle snake of a minimal path, or, when that
+    /// gets too expensive and `need_min` is unset, a good enough point.
+    fn split(
+        &mut self,
+        off1: isize,
+        lim1: isize,
+        off2: isize,
+        lim2: isize,
+        need_min: bool,
+    ) -> Split {
+        let (a, b) = (self.a, self.b);
+        let offset = self.offset;
+        let at = |d: isize| (d + offset) as usize;
+        let (kvdf, kvdb) = (&mut self.forward, &mut self.backward);
+
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+
+        kvdf[at(fmid)] = off1;
+        kvdb[at(bmid)] = lim1;
+
+        let mut ec = 1;
+        loop {
+            let mut got_snake = false;
+
+            if fmin > dmin {
+                fmin -= 1;
+                kvdf[at(fmin - 1)] = -1;
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                kvdf[at(fmax + 1)] = -1;
+            } else {
+                fmax -= 1;
+            }
+
+            let mut d = fmax;
+            while d >= fmin {
+                let mut i1 = if kvdf[at(d - 1)] >= kvdf[at(d + 1)] {
+                    kvdf[at(d - 1)] + 1
+                } else {
+                    kvdf[at(d + 1)]
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && a[i1 as usize] == b[i2 as usize] {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - prev1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                kvdf[at(d)] = i1;
+                if odd && bmin <= d && d <= bmax && kvdb[at(d)] <= i1 {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+                d -= 2;
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                kvdb[at(bmin - 1)] = isize::MAX;
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                kvdb[at(bmax + 1)] = isize::MAX;
+            } else {
+                bmax -= 1;
+            }
+
+            let mut d = bmax;
+            while d >= bmin {
+                let mut i1 = if kvdb[at(d - 1)] < kvdb[at(d + 1)] {
+                    kvdb[at(d - 1)]
+                } else {
+                    kvdb[at(d + 1)] - 1
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && a[i1 as usize - 1] == b[i2 as usize - 1] {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if prev1 - i1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                kvdb[at(d)] = i1;
+                if !odd && fmin <= d && d <= fmax && i1 <= kvdf[at(d)] {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+                d -= 2;
+            }
+
+            if need_min {
+                ec += 1;
+                continue;
+            }
+
+            // Past the heuristic threshold, a diagonal that got far from
+            // the corner through a long snake is good enough to split at
+            if got_snake && ec > HEUR_MIN_COST {
+                let mut best = 0;
+                let mut split = (0, 0);
+                let mut d = fmax;
+                while d >= fmin {
+                    let dd = (d - fmid).abs();
+                    let i1 = kvdf[at(d)];
+                    let i2 = i1 - d;
+                    let v = (i1 - off1) + (i2 - off2) - dd;
+
+                    if v > K_HEUR * ec
+                        && v > best
+                        && off1 + SNAKE_CNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_CNT <= i2
+                        && i2 < lim2
+                        && (1..=SNAKE_CNT).all(|k| a[(i1 - k) as usize] == b[(i2 - k) as usize])
+                    {
+                        best = v;
+                        split = (i1, i2);
+                    }
+                    d -= 2;
+                }
+                if best > 0 {
+                    return Split {
+                        i1: split.0,
+                        i2: split.1,
+                        min_lo: true,
+                        min_hi: false,
+                    };
+                }
+
+                let mut d = bmax;
+                while d >= bmin {
+                    let dd = (d - bmid).abs();
+                    let i1 = kvdb[at(d)];
+                    let i2 = i1 - d;
+                    let v = (lim1 - i1) + (lim2 - i2) - dd;
+
+                    if v > K_HEUR * ec
+                        && v > best
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_CNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_CNT
+                        && (0..SNAKE_CNT).all(|k| a[(i1 + k) as usize] == b[(i2 + k) as usize])
+                    {
+                        best = v;
+                        split = (i1, i2);
+                    }
+                    d -= 2;
+                }
+                if best > 0 {
+                    return Split {
+                        i1: split.0,
+                        i2: split.1,
+                        min_lo: false,
+                        min_hi: true,
+                    };
+                }
+            }
+
+            // Enough is enough: split at the furthest reaching path
+            if ec >= self.max_cost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                let mut d = fmax;
+                while d >= fmin {
+                    let mut i1 = kvdf[at(d)].min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if fbest < i1 + i2 {
+                        fbest = i1 + i2;
+                        fbest1 = i1;
+                    }
+                    d -= 2;
+                }
+
+                let (mut bbest, mut bbest1) = (isize::MAX, isize::MAX);
+                let mut d = bmax;
+                while d >= bmin {
+                    let mut i1 = kvdb[at(d)].max(off1);
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < bbest {
+                        bbest = i1 + i2;
+                        bbest1 = i1;
+                    }
+                    d -= 2;
+                }
+
+                return if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    Split {
+                        i1: fbest1,
+                        i2: fbest - fbest1,
+                        min_lo: true,
+                        min_hi: false,
+                    }
+                } else {
+                    Split {
+                        i1: bbest1,
+                        i2: bbest - bbest1,
+                        min_lo: false,
+                        min_hi: true,
+                    }
+                };
+            }
+
+            ec += 1;
+        }
+    }
+}
+
+/// A run of changed lines, which may be empty.
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(side: &Side) -> Self {
+        let mut end = 0;
+        while side.is_changed(end as isize) {
+            end += 1;
+        }
+        Self { start: 0, end }
+    }
+
+    fn next(&mut self, side: &Side) -> bool {
+        if self.end == side.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while side.is_changed(self.end as isize) {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, side: &Side) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while side.is_changed(self.start as isize - 1) {
+            self.start -= 1;
+        }
+        true
+    }
+
+    fn slide_down(&mut self, side: &mut Side) -> bool {
+        if self.end < side.len() && side.classes[self.start] == side.classes[self.end] {
+            side.set_changed(self.start, false);
+            side.set_changed(self.end, true);
+            self.start += 1;
+            self.end += 1;
+            while side.is_changed(self.end as isize) {
+                self.end += 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn slide_up(&mut self, side: &mut Side) -> bool {
+        if self.start > 0 && side.classes[self.start - 1] == side.classes[self.end - 1] {
+            self.start -= 1;
+            self.end -= 1;
+            side.set_changed(self.start, true);
+            side.set_changed(self.end, false);
+            while side.is_changed(self.start as isize - 1) {
+                self.start -= 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Slides each group of changed lines in `side` as far down as it goes,
+/// merging it with the groups it runs into, then back up to line up
+/// with a group of changes in `other` if it passed one
+/// (`xdl_change_compact`). Given the `indents` of the lines, a group that
+/// lines up with nothing goes where the indent heuristic scores best.
+fn compact(side: &mut Side, other: &Side, indents: Option<&[isize]>) {
+    let mut g = Group::first(side);
+    let mut go = Group::first(other);
+
+    loop {
+        if g.end != g.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+
+            loop {
+                let size = g.end - g.start;
+                end_matching_other = None;
+
+                while g.slide_up(side) {
+                    go.previous(other);
+                }
+                earliest_end = g.end;
+                if go.end > go.start {
+                    end_matching_other = Some(g.end);
+                }
+
+                while g.slide_down(side) {
+                    go.next(other);
+                    if go.end > go.start {
+                        end_matching_other = Some(g.end);
+                    }
+                }
+
+                if size == g.end - g.start {
+                    break;
+                }
+            }
+
+            if g.end == earliest_end {
+                // The group could not move
+            } else if end_matching_other.is_some() {
+                while go.end == go.start {
+                    g.slide_up(side);
+                    go.previous(other);
+                }
+            } else if let Some(indents) = indents {
+                let size = g.end - g.start;
+                let lowest = earliest_end
+                    .max((g.end + 1).saturating_sub(size + 2))
+                    .max(g.end.saturating_sub(INDENT_HEURISTIC_MAX_SLIDING));
+
+                let mut best: Option<(usize, SplitScore)> = None;
+                for shift in lowest..=g.end {
+                    let mut score = SplitScore::default();
+                    score.add(&SplitMeasure::measure(indents, shift));
+                    score.add(&SplitMeasure::measure(indents, shift - size));
+                    if best.is_none_or(|(_, best)| score.cmp(&best) <= 0) {
+                        best = Some((shift, score));
+                    }
+                }
+
+                let (best_shift, _) = best.unwrap();
+                while g.end > best_shift {
+                    g.slide_up(side);
+                    go.previous(other);
+                }
+            }
+        }
+
+        if !g.next(side) {
+            break;
+        }
+        go.next(other);
+    }
+}
+
+/// How far back the indent heuristic looks for a better place for a
+/// group.
+const INDENT_HEURISTIC_MAX_SLIDING: usize = 100;
+const MAX_INDENT: isize = 200;
+const MAX_BLANKS: isize = 20;
+
+const START_OF_FILE_PENALTY: isize = 1;
+const END_OF_FILE_PENALTY: isize = 21;
+const TOTAL_BLANK_WEIGHT: isize = -30;
+const POST_BLANK_WEIGHT: isize = 6;
+const RELATIVE_INDENT_PENALTY: isize = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: isize = 10;
+const RELATIVE_OUTDENT_PENALTY: isize = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: isize = 17;
+const RELATIVE_DEDENT_PENALTY: isize = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: isize = 17;
+const INDENT_WEIGHT: isize = 60;
+
+/// The width of the leading whitespace of `line`, with tabs to multiples
+/// of eight, or -1 for a blank line.
+fn indent(line: &[u8]) -> isize {
+    let mut width = 0;
+    for &c in line {
+        match c {
+            b' ' => width += 1,
+            b'\t' => width += 8 - width % 8,
+            b'\n' | b'\r' | 0x0b | 0x0c => {}
+            _ => return width,
+        }
+        if width >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+/// The surroundings of a place a group could start or end at.
+struct SplitMeasure {
+    end_of_file: bool,
+    indent: isize,
+    pre_blank: isize,
+    pre_indent: isize,
+    post_blank: isize,
+    post_indent: isize,
+}
+
+impl SplitMeasure {
+    /// Looks at the lines around the split before line `split`.
+    fn measure(indents: &[isize], split: usize) -> Self {
+        let mut m = SplitMeasure {
+            end_of_file: split >= indents.len(),
+            indent: indents.get(split).copied().unwrap_or(-1),
+            pre_blank: 0,
+            pre_indent: -1,
+            post_blank: 0,
+            post_indent: -1,
+        };
+
+        for &indent in indents[..split.min(indents.len())].iter().rev() {
+            m.pre_indent = indent;
+            if indent != -1 {
+                break;
+            }
+            m.pre_blank += 1;
+            if m.pre_blank == MAX_BLANKS {
+                m.pre_indent = 0;
+                break;
+            }
+        }
+
+        for &indent in indents.iter().skip(split + 1) {
+            m.post_indent = indent;
+            if indent != -1 {
+                break;
+            }
+            m.post_blank += 1;
+            if m.post_blank == MAX_BLANKS {
+                m.post_indent = 0;
+                break;
+            }
+        }
+
+        m
+    }
+}
+
+/// How bad the splits at both ends of a group look; lower is better.
+#[derive(Clone, Copy, Default)]
+struct SplitScore {
+    effective_indent: isize,
+    penalty: isize,
+}
+
+impl SplitScore {
+    fn add(&mut self, m: &SplitMeasure) {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += START_OF_FILE_PENALTY;
+        }
+        if m.end_of_file {
+            self.penalty += END_OF_FILE_PENALTY;
+        }
+
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+        self.penalty += POST_BLANK_WEIGHT * post_blank;
+
+        let indent = if m.indent != -1 {
+            m.indent
+        } else {
+            m.post_indent
+        };
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent;
+
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+            return;
+        }
+        self.penalty += if indent > m.pre_indent {
+            // Indented more than the line before
+            if any_blanks {
+                RELATIVE_INDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_INDENT_PENALTY
+            }
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            // Indented less, and likely starting a block
+            if any_blanks {
+                RELATIVE_OUTDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_OUTDENT_PENALTY
+            }
+        } else if any_blanks {
+            // Likely ending a block
+            RELATIVE_DEDENT_WITH_BLANK_PENALTY
+        } else {
+            RELATIVE_DEDENT_PENALTY
+        };
+    }
+
+    fn cmp(&self, other: &SplitScore) -> isize {
+        let indents = (self.effective_indent - other.effective_indent).signum();
+        INDENT_WEIGHT * indents + (self.penalty - other.penalty)
+    }
+}
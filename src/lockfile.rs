@@ -11,9 +11,12 @@ pub struct Lockfile {
 
 impl Lockfile {
     pub fn new(file_path: PathBuf) -> Self {
+        let mut lock_path = file_path.clone().into_os_string();
+        lock_path.push(".lock");
+
         Lockfile {
-            file_path: file_path.clone(),
-            lock_path: file_path.clone().with_extension("lock"),
+            file_path,
+            lock_path: PathBuf::from(lock_path),
             lock: None,
         }
     }
@@ -73,3 +76,13 @@ impl Lockfile {
         }
     }
 }
+
+impl Drop for Lockfile {
+    /// A lock that was neither committed nor rolled back, e.g. because the
+    /// operation failed halfway, is released so it does not block later runs.
+    fn drop(&mut self) {
+        if self.lock.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
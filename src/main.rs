@@ -1,40 +1,615 @@
-use std::env;
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+    process,
+};
 
 use anyhow::Context;
 use clap::Parser;
-use cmd::Commands;
-use repository::Repository;
+use cmd::{Commands, ReflogCommands, StashCommands};
+use merge_file::{is_binary, merge_file, ConflictStyle, Favor, MergeOptions};
+use repository::{
+    clean::Ignored,
+    diff_tree::DiffFormat,
+    discover::normalize,
+    merge::FastForward,
+    plumbing::CatFile,
+    rename::{Detect, RenameOptions},
+    sequencer::Action,
+    Repository,
+};
+use similarity::parse_score;
 
 pub mod checksum;
 mod cmd;
+pub mod date;
+pub mod diff;
 pub mod lockfile;
+pub mod merge_file;
 pub mod oid;
 mod repository;
+pub mod similarity;
+pub mod wildmatch;
+
+fn current_dir() -> Result<PathBuf, anyhow::Error> {
+    env::current_dir().with_context(|| "Can't get current working directory")
+}
 
 fn main() -> Result<(), anyhow::Error> {
     let cli = cmd::Cli::parse();
 
     match &cli.command {
-        Commands::Commit {} => {
-            Repository::open(
-                env::current_dir().with_context(|| "Can't get current working directory")?,
-            )
-            .commit()?;
+        Commands::Commit { no_verify } => {
+            Repository::discover(&current_dir()?)?.commit(*no_verify)?;
         }
         Commands::Add { paths } => {
-            Repository::open(
-                env::current_dir().with_context(|| "Can't get current working directory")?,
-            )
-            .add(paths)?;
+            let cwd = current_dir()?;
+            let mut repo = Repository::discover(&cwd)?;
+            repo.add(&cwd, paths)?;
         }
-        Commands::Init { root_path } => {
+        Commands::Init {
+            root_path,
+            bare,
+            initial_branch,
+            template,
+        } => {
+            let cwd = current_dir()?;
             let root = match root_path {
-                Some(root) => root.to_path_buf(),
+                Some(root) => normalize(&cwd.join(root)),
+                None => cwd,
+            };
+            let template = template
+                .clone()
+                .or_else(|| env::var_os("GIT_TEMPLATE_DIR").map(PathBuf::from));
+
+            Repository::init(root, *bare, initial_branch.as_deref(), template.as_deref())?;
+        }
+        Commands::RevParse { revisions } => {
+            Repository::discover(&current_dir()?)?.rev_parse(revisions)?;
+        }
+        Commands::UpdateRef {
+            message,
+            delete,
+            stdin,
+            reference,
+            new_value,
+            old_value,
+        } => {
+            let repo = Repository::discover(&current_dir()?)?;
+
+            if *stdin {
+                repo.update_ref_stdin(io::stdin().lock(), message.as_deref())?;
+            } else {
+                repo.update_ref(
+                    reference.as_deref().unwrap_or_default(),
+                    new_value.as_deref(),
+                    old_value.as_deref(),
+                    *delete,
+                    message.as_deref(),
+                )?;
+            }
+        }
+        Commands::PackRefs { all, no_prune } => {
+            Repository::discover(&current_dir()?)?.pack_refs(*all, !*no_prune)?;
+        }
+        Commands::Tag {
+            annotate,
+            message,
+            force,
+            delete,
+            list,
+            lines,
+            sort,
+            args,
+        } => {
+            let repo = Repository::discover(&current_dir()?)?;
+
+            if *delete {
+                repo.tag_delete(args)?;
+            } else if *list || (args.is_empty() && !*annotate && message.is_none()) {
+                repo.tag_list(args, sort.as_deref(), *lines)?;
+            } else {
+                let name = args
+                    .first()
+                    .ok_or_else(|| anyhow::anyhow!("tag name required"))?;
+                repo.tag_create(
+                    name,
+                    args.get(1).map(String::as_str),
+                    *annotate,
+                    message.as_deref(),
+                    *force,
+                )?;
+            }
+        }
+        Commands::ForEachRef {
+            format,
+            sort,
+            count,
+            contains,
+            merged,
+            no_merged,
+            patterns,
+        } => {
+            let repo = Repository::discover(&current_dir()?)?;
+            repo.for_each_ref(
+                patterns,
+                format.as_deref(),
+                sort,
+                *count,
+                contains.as_deref(),
+                merged.as_deref(),
+                no_merged.as_deref(),
+            )?;
+        }
+        Commands::ShowRef {
+            heads,
+            tags,
+            dereference,
+            hash,
+            verify,
+            patterns,
+        } => {
+            let repo = Repository::discover(&current_dir()?)?;
+            if !repo.show_ref(patterns, *heads, *tags, *dereference, *hash, *verify)? {
+                process::exit(1);
+            }
+        }
+        Commands::HashObject {
+            kind,
+            write,
+            stdin,
+            paths,
+        } => {
+            let repo = Repository::discover(&current_dir()?)?;
+            repo.hash_object(kind, *write, *stdin, paths)?;
+        }
+        Commands::CatFile {
+            kind,
+            size,
+            exists,
+            pretty: _,
+            object,
+        } => {
+            let repo = Repository::discover(&current_dir()?)?;
+            let show = if *kind {
+                CatFile::Type
+            } else if *size {
+                CatFile::Size
+            } else if *exists {
+                CatFile::Exists
+            } else {
+                CatFile::Pretty
+            };
+            if !repo.cat_file(object, show)? {
+                process::exit(1);
+            }
+        }
+        Commands::WriteTree => {
+            let mut repo = Repository::discover(&current_dir()?)?;
+            println!("{}", repo.write_tree()?);
+        }
+        Commands::CommitTree {
+            tree,
+            parents,
+            messages,
+            file,
+        } => {
+            let repo = Repository::discover(&current_dir()?)?;
+            repo.commit_tree(tree, parents, messages, file.as_deref())?;
+        }
+        Commands::LsTree {
+            recursive,
+            show_trees,
+            name_only,
+            treeish,
+            paths,
+        } => {
+            let repo = Repository::discover(&current_dir()?)?;
+            repo.ls_tree(treeish, *recursive, *show_trees, *name_only, paths)?;
+        }
+        Commands::DiffTree {
+            recursive,
+            patch,
+            name_only,
+            name_status,
+            find_renames,
+            find_copies,
+            find_copies_harder,
+            trees,
+        } => {
+            let repo = Repository::discover(&current_dir()?)?;
+
+            let mut renames = RenameOptions::default();
+            let mut trees = trees.clone();
+            let threshold = match (find_copies.last(), find_renames) {
+                (Some(value), _) => {
+                    renames.detect = Detect::Copies;
+                    value
+                }
+                (None, Some(value)) => {
+                    renames.detect = Detect::Renames;
+                    value
+                }
+                (None, None) => "",
+            };
+            // A bare -M or -C may have swallowed the first tree as its
+            // threshold
+            if !threshold.is_empty() {
+                match parse_score(threshold) {
+                    (score, "") => renames.min_score = score,
+                    _ => trees.insert(0, threshold.to_string()),
+                }
+            }
+            let find_copies_harder = *find_copies_harder || find_copies.len() > 1;
+            if find_copies_harder {
+                renames.detect = Detect::Copies;
+            }
+
+            let format = if *patch {
+                DiffFormat::Patch
+            } else if *name_only {
+                DiffFormat::NameOnly
+            } else if *name_status {
+                DiffFormat::NameStatus
+            } else {
+                DiffFormat::Raw
+            };
+            repo.diff_tree(&trees, *recursive, format, &renames, find_copies_harder)?;
+        }
+        Commands::LsFiles {
+            cached,
+            deleted,
+            modified,
+            others,
+            stage,
+            unmerged,
+            paths,
+        } => {
+            let cwd = current_dir()?;
+            let mut repo = Repository::discover(&cwd)?;
+            repo.ls_files(
+                &cwd, *cached, *deleted, *modified, *others, *stage, *unmerged, paths,
+            )?;
+        }
+        Commands::UpdateIndex {
+            add,
+            remove,
+            force_remove,
+            refresh,
+            chmod,
+            cacheinfo,
+            assume_unchanged,
+            no_assume_unchanged,
+            skip_worktree,
+            no_skip_worktree,
+            index_version,
+            paths,
+        } => {
+            let mark = |set: bool, clear: bool| (set || clear).then_some(set);
+            let cwd = current_dir()?;
+            let mut repo = Repository::discover(&cwd)?;
+            let clean = repo.update_index(
+                &cwd,
+                *add,
+                *remove,
+                *force_remove,
+                *refresh,
+                chmod.as_deref(),
+                cacheinfo,
+                mark(*assume_unchanged, *no_assume_unchanged),
+                mark(*skip_worktree, *no_skip_worktree),
+                *index_version,
+                paths,
+            )?;
+            if !clean {
+                process::exit(1);
+            }
+        }
+        Commands::Merge {
+            ff: _,
+            no_ff,
+            ff_only,
+            message,
+            no_verify,
+            cont,
+            abort,
+            commit,
+        } => {
+            let mut repo = Repository::discover(&current_dir()?)?;
+
+            if *cont {
+                repo.merge_continue(*no_verify)?;
+            } else if *abort {
+                repo.merge_abort()?;
+            } else if let Some(commit) = commit {
+                let fast_forward = match (no_ff, ff_only) {
+                    (true, _) => FastForward::Never,
+                    (_, true) => FastForward::Only,
+                    _ => FastForward::Allow,
+                };
+                if !repo.merge(commit, fast_forward, message.as_deref())? {
+                    process::exit(1);
+                }
+            }
+        }
+        Commands::CherryPick {
+            cont,
+            skip,
+            abort,
+            no_verify,
+            commits,
+        }
+        | Commands::Revert {
+            cont,
+            skip,
+            abort,
+            no_verify,
+            commits,
+        } => {
+            let action = match &cli.command {
+                Commands::Revert { .. } => Action::Revert,
+                _ => Action::Pick,
+            };
+            let mut repo = Repository::discover(&current_dir()?)?;
+
+            let done = if *cont {
+                repo.sequencer_continue(action, *no_verify)?
+            } else if *skip {
+                repo.sequencer_skip(action)?
+            } else if *abort {
+                repo.sequencer_abort(action)?;
+                true
+            } else {
+                repo.sequencer_start(action, commits)?
+            };
+            if !done {
+                process::exit(1);
+            }
+        }
+        Commands::Rebase {
+            interactive,
+            onto,
+            autosquash,
+            cont,
+            skip,
+            abort,
+            upstream,
+            branch,
+        } => {
+            let mut repo = Repository::discover(&current_dir()?)?;
+
+            let done = if *cont {
+                repo.rebase_continue()?
+            } else if *skip {
+                repo.rebase_skip()?
+            } else if *abort {
+                repo.rebase_abort()?;
+                true
+            } else {
+                repo.rebase(
+                    upstream.as_deref(),
+                    onto.as_deref(),
+                    branch.as_deref(),
+                    *interactive,
+                    *autosquash,
+                )?
+            };
+            if !done {
+                process::exit(1);
+            }
+        }
+        Commands::MergeFile {
+            labels,
+            stdout,
+            diff3,
+            zdiff3,
+            ours,
+            theirs,
+            union,
+            current,
+            base,
+            other,
+        } => {
+            if labels.len() > 3 {
+                return Err(anyhow::anyhow!("too many labels on the command line"));
+            }
+
+            let style = if *diff3 {
+                ConflictStyle::Diff3
+            } else if *zdiff3 {
+                ConflictStyle::Zdiff3
+            } else {
+                // Outside a repository there is no configured style
+                match Repository::discover(&current_dir()?) {
+                    Ok(repo) => repo.conflict_style()?,
+                    Err(_) => ConflictStyle::default(),
+                }
+            };
+
+            let paths = [current, base, other];
+            let mut contents = Vec::new();
+            for path in paths {
+                let data = fs::read(path)
+                    .with_context(|| format!("could not open '{}'", path.display()))?;
+                if is_binary(&data) {
+                    return Err(anyhow::anyhow!(
+                        "Cannot merge binary files: {}",
+                        path.display()
+                    ));
+                }
+                contents.push(data);
+            }
+
+            let names: Vec<String> = paths
+                .iter()
+                .enumerate()
+                .map(|(i, path)| {
+                    labels
+                        .get(i)
+                        .cloned()
+                        .unwrap_or_else(|| path.display().to_string())
+                })
+                .collect();
+            let options = MergeOptions {
+                style,
+                favor: match (ours, theirs, union) {
+                    (true, _, _) => Favor::Ours,
+                    (_, true, _) => Favor::Theirs,
+                    (_, _, true) => Favor::Union,
+                    _ => Favor::None,
+                },
+                ours_label: Some(&names[0]),
+                base_label: Some(&names[1]),
+                theirs_label: Some(&names[2]),
+                join_alnum_free: true,
+            };
+
+            let result = merge_file(&contents[1], &contents[0], &contents[2], &options);
+            if *stdout {
+                io::stdout().write_all(&result.data)?;
+            } else {
+                fs::write(current, &result.data)?;
+            }
+
+            // Like Git, the exit status is the number of conflicts
+            if result.conflicts > 0 {
+                process::exit(result.conflicts.min(127) as i32);
+            }
+        }
+        Commands::MergeBase {
+            all,
+            octopus,
+            is_ancestor,
+            fork_point,
+            commits,
+        } => {
+            let repo = Repository::discover(&current_dir()?)?;
+
+            let found = match (is_ancestor, fork_point, commits.as_slice()) {
+                (true, _, [ancestor, descendant]) => {
+                    repo.merge_base_is_ancestor(ancestor, descendant)?
+                }
+                (true, _, _) => {
+                    return Err(anyhow::anyhow!("--is-ancestor takes exactly two commits"))
+                }
+                (_, true, [reference]) => repo.merge_base_fork_point(reference, None)?,
+                (_, true, [reference, commit]) => {
+                    repo.merge_base_fork_point(reference, Some(commit))?
+                }
+                (_, true, _) => {
+                    return Err(anyhow::anyhow!(
+                        "--fork-point takes a ref and an optional commit"
+                    ))
+                }
+                _ => repo.merge_base(commits, *all, *octopus)?,
+            };
+            if !found {
+                process::exit(1);
+            }
+        }
+        Commands::ReadTree {
+            merge,
+            update,
+            index_only,
+            aggressive,
+            trees,
+        } => {
+            let mut repo = Repository::discover(&current_dir()?)?;
+            repo.read_tree(trees, *merge, *update, *index_only, *aggressive)?;
+        }
+        Commands::CheckoutIndex {
+            all,
+            force,
+            prefix,
+            paths,
+        } => {
+            let cwd = current_dir()?;
+            let mut repo = Repository::discover(&cwd)?;
+            repo.checkout_index(&cwd, *all, *force, prefix.as_deref(), paths)?;
+        }
+        Commands::Reflog { command } => {
+            let repo = Repository::discover(&current_dir()?)?;
+
+            match command {
+                None => repo.reflog_show(None)?,
+                Some(ReflogCommands::Show { reference }) => {
+                    repo.reflog_show(reference.as_deref())?
+                }
+                Some(ReflogCommands::Expire {
+                    expire,
+                    all,
+                    dry_run,
+                    references,
+                }) => repo.reflog_expire(expire.as_deref(), *all, *dry_run, references)?,
+                Some(ReflogCommands::Delete { entries }) => repo.reflog_delete(entries)?,
+            }
+        }
+        Commands::Stash { command } => {
+            let mut repo = Repository::discover(&current_dir()?)?;
+
+            let done = match command {
                 None => {
-                    env::current_dir().with_context(|| "Can't get current working directory")?
+                    repo.stash_push(None, false, false)?;
+                    true
                 }
+                Some(StashCommands::Push {
+                    include_untracked,
+                    keep_index,
+                    message,
+                }) => {
+                    repo.stash_push(message.as_deref(), *include_untracked, *keep_index)?;
+                    true
+                }
+                Some(StashCommands::List) => {
+                    repo.stash_list()?;
+                    true
+                }
+                Some(StashCommands::Apply { index, stash }) => {
+                    repo.stash_apply(stash.as_deref(), *index)?
+                }
+                Some(StashCommands::Pop { index, stash }) => {
+                    repo.stash_pop(stash.as_deref(), *index)?
+                }
+                Some(StashCommands::Drop { stash }) => {
+                    repo.stash_drop(stash.as_deref())?;
+                    true
+                }
+            };
+            if !done {
+                process::exit(1);
+            }
+        }
+        Commands::Clean {
+            dry_run,
+            force,
+            dirs,
+            ignored,
+            only_ignored,
+            paths,
+        } => {
+            let cwd = current_dir()?;
+            let mut repo = Repository::discover(&cwd)?;
+            let ignored = if *ignored {
+                Ignored::Remove
+            } else if *only_ignored {
+                Ignored::Only
+            } else {
+                Ignored::Keep
             };
-            Repository::open(root).init()?;
+            repo.clean(&cwd, *dry_run, *force, *dirs, ignored, paths)?;
+        }
+        Commands::Restore {
+            source,
+            staged,
+            worktree,
+            paths,
+        } => {
+            let cwd = current_dir()?;
+            let mut repo = Repository::discover(&cwd)?;
+            if !repo.restore(&cwd, source.as_deref(), *staged, *worktree, paths)? {
+                process::exit(1);
+            }
         }
     }
 
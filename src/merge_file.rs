@@ -0,0 +1,456 @@
+//! Three-way file merges producing the same results and conflict
+//! markers as Git's xdiff merge.
+
+use crate::diff::{diff, lines, Hunk};
+
+const MARKER_SIZE: usize = 7;
+
+/// How conflicting hunks are written out, as set by `merge.conflictStyle`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictStyle {
+    /// Our and their side of each conflict
+    #[default]
+    Merge,
+    /// Also the base side, between `|||||||` and `=======`
+    Diff3,
+    /// Like `Diff3`, with the lines both sides agree on at the start and
+    /// end of a conflict moved out of it
+    Zdiff3,
+}
+
+impl ConflictStyle {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "merge" => Some(Self::Merge),
+            "diff3" => Some(Self::Diff3),
+            "zdiff3" => Some(Self::Zdiff3),
+            _ => None,
+        }
+    }
+}
+
+/// Resolves conflicts by taking one side, or both, instead of writing
+/// conflict markers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Favor {
+    #[default]
+    None,
+    Ours,
+    Theirs,
+    Union,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions<'a> {
+    pub style: ConflictStyle,
+    pub favor: Favor,
+    /// Names shown after the `<<<<<<<`, `|||||||` and `>>>>>>>` markers
+    pub ours_label: Option<&'a str>,
+    pub base_label: Option<&'a str>,
+    pub theirs_label: Option<&'a str>,
+    /// Also joins conflicts separated only by lines without letters or
+    /// digits, as `git merge-file` does
+    pub join_alnum_free: bool,
+}
+
+pub struct MergeResult {
+    pub data: Vec<u8>,
+    pub conflicts: usize,
+}
+
+/// Whether `data` looks binary the way Git decides it: a NUL byte
+/// within its first 8000 bytes.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resolution {
+    Conflict,
+    Ours,
+    Theirs,
+    Union,
+    /// A conflict whose sides turned out to be the same
+    Identical,
+}
+
+/// A changed region: where it starts and how many lines it covers in the
+/// base, our and their file. As in xdiff, joining regions can leave a
+/// count below zero, which stands for no lines.
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    resolution: Resolution,
+    i0: isize,
+    chg0: isize,
+    i1: isize,
+    chg1: isize,
+    i2: isize,
+    chg2: isize,
+}
+
+/// The `count` lines from `start`, or none if `count` is not positive.
+fn span<'a>(lines: &'a [&'a [u8]], start: isize, count: isize) -> &'a [&'a [u8]] {
+    if count < 1 {
+        return &[];
+    }
+    &lines[start as usize..(start + count) as usize]
+}
+
+struct Files<'a> {
+    base: Vec<&'a [u8]>,
+    ours: Vec<&'a [u8]>,
+    theirs: Vec<&'a [u8]>,
+}
+
+/// Merges the changes from `base` to `ours` and from `base` to `theirs`,
+/// line by line. Changes to different parts of the file are combined;
+/// changes touching the same or adjacent lines of `base` conflict unless
+/// they are identical.
+pub fn merge_file(base: &[u8], ours: &[u8], theirs: &[u8], options: &MergeOptions) -> MergeResult {
+    let files = Files {
+        base: lines(base),
+        ours: lines(ours),
+        theirs: lines(theirs),
+    };
+
+    let ours_hunks = diff(&files.base, &files.ours);
+    let theirs_hunks = diff(&files.base, &files.theirs);
+    if ours_hunks.is_empty() {
+        return MergeResult {
+            data: theirs.to_vec(),
+            conflicts: 0,
+        };
+    }
+    if theirs_hunks.is_empty() {
+        return MergeResult {
+            data: ours.to_vec(),
+            conflicts: 0,
+        };
+    }
+
+    let mut regions = find_regions(&ours_hunks, &theirs_hunks, &files);
+
+    // The styles showing the base do not split conflicts, as the base
+    // would no longer line up with the sides
+    match options.style {
+        ConflictStyle::Merge => {
+            regions = refine_conflicts(regions, &files);
+            join_conflicts(&mut regions, &files, options.join_alnum_free);
+        }
+        ConflictStyle::Zdiff3 => trim_conflicts(&mut regions, &files),
+        ConflictStyle::Diff3 => {}
+    }
+
+    write_result(&mut regions, &files, options)
+}
+
+/// Adds a region, joining it with the previous one when they overlap or
+/// touch on either side; the joined region conflicts unless both were
+/// resolved the same way.
+fn append_region(regions: &mut Vec<Region>, region: Region) {
+    if let Some(last) = regions.last_mut() {
+        if region.i1 <= last.i1 + last.chg1 || region.i2 <= last.i2 + last.chg2 {
+            if region.resolution != last.resolution {
+                last.resolution = Resolution::Conflict;
+            }
+            last.chg0 = region.i0 + region.chg0 - last.i0;
+            last.chg1 = region.i1 + region.chg1 - last.i1;
+            last.chg2 = region.i2 + region.chg2 - last.i2;
+            return;
+        }
+    }
+    regions.push(region);
+}
+
+/// A region changed on one side only: `hunk`, with the other side's
+/// lines found by `shift`ing the base lines.
+fn one_sided(hunk: &Hunk, shift: isize, resolution: Resolution) -> Region {
+    let (a_start, a_end) = (hunk.a_start as isize, hunk.a_end as isize);
+    let (b_start, b_end) = (hunk.b_start as isize, hunk.b_end as isize);
+    let (changed, unchanged) = (
+        (b_start, b_end - b_start),
+        (a_start + shift, a_end - a_start),
+    );
+    let ((i1, chg1), (i2, chg2)) = match resolution {
+        Resolution::Ours => (changed, unchanged),
+        _ => (unchanged, changed),
+    };
+    Region {
+        resolution,
+        i0: a_start,
+        chg0: a_end - a_start,
+        i1,
+        chg1,
+        i2,
+        chg2,
+    }
+}
+
+/// How far `hunk` moves the lines after it.
+fn shift(hunk: &Hunk) -> isize {
+    hunk.b_start as isize - hunk.a_start as isize
+}
+
+/// Walks the hunks of both sides in base order (`xdl_do_merge`). A hunk
+/// only one side has is taken from that side; overlapping or touching
+/// hunks conflict unless both sides made the very same change, which
+/// needs no region at all.
+fn find_regions(ours: &[Hunk], theirs: &[Hunk], files: &Files) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while let (Some(x1), Some(x2)) = (ours.get(i), theirs.get(j)) {
+        if x1.a_end < x2.a_start {
+            append_region(&mut regions, one_sided(x1, shift(x2), Resolution::Ours));
+            i += 1;
+            continue;
+        }
+        if x2.a_end < x1.a_start {
+            append_region(&mut regions, one_sided(x2, shift(x1), Resolution::Theirs));
+            j += 1;
+            continue;
+        }
+
+        let same = x1.a_start == x2.a_start
+            && x1.a_end == x2.a_end
+            && files.ours[x1.b_start..x1.b_end] == files.theirs[x2.b_start..x2.b_end];
+        if !same {
+            // Cover both hunks on every side
+            let start = x1.a_start.min(x2.a_start) as isize;
+            let end = x1.a_end.max(x2.a_end) as isize;
+            let i1 = x1.b_start as isize - (x1.a_start as isize - start);
+            let i2 = x2.b_start as isize - (x2.a_start as isize - start);
+            append_region(
+                &mut regions,
+                Region {
+                    resolution: Resolution::Conflict,
+                    i0: start,
+                    chg0: end - start,
+                    i1,
+                    chg1: x1.b_end as isize + (end - x1.a_end as isize) - i1,
+                    i2,
+                    chg2: x2.b_end as isize + (end - x2.a_end as isize) - i2,
+                },
+            );
+        }
+
+        if x1.a_end >= x2.a_end {
+            j += 1;
+        }
+        if x2.a_end >= x1.a_end {
+            i += 1;
+        }
+    }
+
+    let ours_shift = files.ours.len() as isize - files.base.len() as isize;
+    let theirs_shift = files.theirs.len() as isize - files.base.len() as isize;
+    for x1 in &ours[i..] {
+        append_region(&mut regions, one_sided(x1, theirs_shift, Resolution::Ours));
+    }
+    for x2 in &theirs[j..] {
+        append_region(&mut regions, one_sided(x2, ours_shift, Resolution::Theirs));
+    }
+
+    regions
+}
+
+/// Splits each conflict into the hunks between our and their side, so
+/// lines both sides agree on are no longer part of it. A conflict where
+/// one side is empty is left alone.
+fn refine_conflicts(regions: Vec<Region>, files: &Files) -> Vec<Region> {
+    let mut refined = Vec::with_capacity(regions.len());
+
+    for region in regions {
+        if region.resolution != Resolution::Conflict || region.chg1 < 1 || region.chg2 < 1 {
+            refined.push(region);
+            continue;
+        }
+
+        let hunks = diff(
+            span(&files.ours, region.i1, region.chg1),
+            span(&files.theirs, region.i2, region.chg2),
+        );
+        if hunks.is_empty() {
+            refined.push(Region {
+                resolution: Resolution::Identical,
+                ..region
+            });
+            continue;
+        }
+        for hunk in hunks {
+            refined.push(Region {
+                i1: region.i1 + hunk.a_start as isize,
+                chg1: (hunk.a_end - hunk.a_start) as isize,
+                i2: region.i2 + hunk.b_start as isize,
+                chg2: (hunk.b_end - hunk.b_start) as isize,
+                ..region
+            });
+        }
+    }
+
+    refined
+}
+
+/// Joins conflicts that are at most three lines apart, or, with
+/// `alnum_free`, only apart by lines without letters or digits.
+fn join_conflicts(regions: &mut Vec<Region>, files: &Files, alnum_free: bool) {
+    let mut i = 0;
+    while i + 1 < regions.len() {
+        let (current, next) = (regions[i], regions[i + 1]);
+        let begin = current.i1 + current.chg1;
+        let between = span(&files.ours, begin, next.i1 - begin);
+
+        let separate = current.resolution != Resolution::Conflict
+            || next.resolution != Resolution::Conflict
+            || (next.i1 - begin > 3
+                && (!alnum_free
+                    || between
+                        .iter()
+                        .any(|line| line.iter().any(u8::is_ascii_alphanumeric))));
+
+        if separate {
+            i += 1;
+        } else {
+            regions[i].chg0 = next.i0 + next.chg0 - current.i0;
+            regions[i].chg1 = next.i1 + next.chg1 - current.i1;
+            regions[i].chg2 = next.i2 + next.chg2 - current.i2;
+            regions.remove(i + 1);
+        }
+    }
+}
+
+/// Moves the lines at the start and end of each conflict that are the
+/// same on both sides out of it.
+fn trim_conflicts(regions: &mut [Region], files: &Files) {
+    for region in regions {
+        if region.resolution != Resolution::Conflict {
+            continue;
+        }
+
+        while region.chg1 > 0
+            && region.chg2 > 0
+            && files.ours[region.i1 as usize] == files.theirs[region.i2 as usize]
+        {
+            region.i1 += 1;
+            region.i2 += 1;
+            region.chg1 -= 1;
+            region.chg2 -= 1;
+        }
+        while region.chg1 > 0
+            && region.chg2 > 0
+            && files.ours[(region.i1 + region.chg1 - 1) as usize]
+                == files.theirs[(region.i2 + region.chg2 - 1) as usize]
+        {
+            region.chg1 -= 1;
+            region.chg2 -= 1;
+        }
+    }
+}
+
+/// Whether the lines around `line` end in CRLF, or `None` when the file
+/// gives no hint.
+fn is_crlf(lines: &[&[u8]], line: usize) -> Option<bool> {
+    let last = lines.get(line)?;
+    if line + 1 < lines.len() || last.ends_with(b"\n") {
+        return Some(last.ends_with(b"\r\n"));
+    }
+    // A last line without a newline goes by the line before it
+    let before = lines.get(line.checked_sub(1)?)?;
+    Some(before.ends_with(b"\r\n"))
+}
+
+/// Whether conflict markers should end in CRLF: when the base does and
+/// neither side's lines before the conflict end in a bare LF.
+fn needs_cr(region: &Region, files: &Files) -> bool {
+    let before = |line: isize| (line - 1).max(0) as usize;
+    is_crlf(&files.ours, before(region.i1)) != Some(false)
+        && is_crlf(&files.theirs, before(region.i2)) != Some(false)
+        && is_crlf(&files.base, 0) == Some(true)
+}
+
+/// Writes lines; with `add_nl`, ends them with a newline if the last one
+/// lacks it.
+fn push_lines(out: &mut Vec<u8>, lines: &[&[u8]], add_nl: bool, cr: bool) {
+    for line in lines {
+        out.extend_from_slice(line);
+    }
+    if add_nl && lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+        if cr {
+            out.push(b'\r');
+        }
+        out.push(b'\n');
+    }
+}
+
+fn push_marker(out: &mut Vec<u8>, marker: u8, label: Option<&str>, cr: bool) {
+    out.extend(std::iter::repeat_n(marker, MARKER_SIZE));
+    if let Some(label) = label {
+        out.push(b' ');
+        out.extend_from_slice(label.as_bytes());
+    }
+    if cr {
+        out.push(b'\r');
+    }
+    out.push(b'\n');
+}
+
+/// Builds the merged file from our side, with each region replaced by
+/// the side it resolved to or by conflict markers.
+fn write_result(regions: &mut [Region], files: &Files, options: &MergeOptions) -> MergeResult {
+    let mut data = Vec::new();
+    let mut conflicts = 0;
+    let mut line = 0;
+
+    for region in regions {
+        if region.resolution == Resolution::Conflict {
+            region.resolution = match options.favor {
+                Favor::None => Resolution::Conflict,
+                Favor::Ours => Resolution::Ours,
+                Favor::Theirs => Resolution::Theirs,
+                Favor::Union => Resolution::Union,
+            };
+        }
+        if region.resolution == Resolution::Identical {
+            continue;
+        }
+
+        let ours = span(&files.ours, region.i1, region.chg1);
+        let theirs = span(&files.theirs, region.i2, region.chg2);
+        push_lines(
+            &mut data,
+            span(&files.ours, line, region.i1 - line),
+            false,
+            false,
+        );
+        line = region.i1 + region.chg1;
+
+        match region.resolution {
+            Resolution::Ours => push_lines(&mut data, ours, false, false),
+            Resolution::Theirs => push_lines(&mut data, theirs, false, false),
+            Resolution::Union => {
+                push_lines(&mut data, ours, true, needs_cr(region, files));
+                push_lines(&mut data, theirs, false, false);
+            }
+            Resolution::Conflict => {
+                let cr = needs_cr(region, files);
+                conflicts += 1;
+
+                push_marker(&mut data, b'<', options.ours_label, cr);
+                push_lines(&mut data, ours, true, cr);
+                if options.style != ConflictStyle::Merge {
+                    let base = span(&files.base, region.i0, region.chg0);
+                    push_marker(&mut data, b'|', options.base_label, cr);
+                    push_lines(&mut data, base, true, cr);
+                }
+                push_marker(&mut data, b'=', None, cr);
+                push_lines(&mut data, theirs, true, cr);
+                push_marker(&mut data, b'>', options.theirs_label, cr);
+            }
+            Resolution::Identical => {}
+        }
+    }
+    let rest = files.ours.len() as isize - line;
+    push_lines(&mut data, span(&files.ours, line, rest), false, false);
+
+    MergeResult { data, conflicts }
+}
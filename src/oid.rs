@@ -2,7 +2,7 @@ use hex;
 use sha1::{Digest, Sha1};
 use std::fmt::{Debug, Display};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Oid {
     hash: [u8; 20],
 }
@@ -15,9 +15,34 @@ impl Oid {
         Self { hash: hash.into() }
     }
 
+    /// The all-zero ID Git uses for "no object", e.g. the old value of a
+    /// newly created ref.
+    pub fn zero() -> Self {
+        Self { hash: [0; 20] }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.hash == [0; 20]
+    }
+
     pub fn as_bytes(&self) -> &[u8; 20] {
         &self.hash
     }
+
+    pub fn to_short(&self) -> String {
+        self.to_string()[..7].to_string()
+    }
+
+    /// Parses a full 40 character hex ID, returning `None` for anything else.
+    pub fn parse(hex: &str) -> Option<Self> {
+        if hex.len() != 40 {
+            return None;
+        }
+        let hash = hex::decode(hex).ok()?;
+        Some(Self {
+            hash: hash.try_into().ok()?,
+        })
+    }
 }
 
 impl Debug for Oid {
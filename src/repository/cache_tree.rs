@@ -0,0 +1,213 @@
+use std::path::PathBuf;
+
+use crate::oid::Oid;
+
+use super::db::Db;
+use super::index::IndexEntry;
+use super::object::tree::Tree;
+
+/// The cache-tree (`TREE`) index extension: the tree object ID of each
+/// directory in the index, so writing a tree only has to hash the
+/// directories whose entries changed since it was last written.
+#[derive(Debug, Clone)]
+pub struct CacheTree {
+    /// Number of index entries the tree covers, or -1 when it is invalid
+    entry_count: i32,
+    oid: Option<Oid>,
+    /// Subtrees ordered the way Git looks them up: by name length, then
+    /// by name.
+    subtrees: Vec<(Vec<u8>, CacheTree)>,
+}
+
+impl CacheTree {
+    pub fn new() -> Self {
+        Self {
+            entry_count: -1,
+            oid: None,
+            subtrees: Vec::new(),
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut rest = data;
+        let (_, tree) = Self::parse_node(&mut rest)?;
+        if !rest.is_empty() {
+            return Err(anyhow::anyhow!("corrupt cache-tree extension"));
+        }
+        Ok(tree)
+    }
+
+    /// Parses one node and, recursively, its subtrees: the name, the entry
+    /// and subtree counts in ASCII, then the object ID for a valid node.
+    fn parse_node(data: &mut &[u8]) -> Result<(Vec<u8>, Self), anyhow::Error> {
+        let corrupt = || anyhow::anyhow!("corrupt cache-tree extension");
+
+        let nul = data.iter().position(|&b| b == 0).ok_or_else(corrupt)?;
+        let name = data[..nul].to_vec();
+        *data = &data[nul + 1..];
+
+        let newline = data.iter().position(|&b| b == b'\n').ok_or_else(corrupt)?;
+        let counts = std::str::from_utf8(&data[..newline])?;
+        *data = &data[newline + 1..];
+
+        let (entry_count, subtree_count) = counts.split_once(' ').ok_or_else(corrupt)?;
+        let entry_count: i32 = entry_count.parse().map_err(|_| corrupt())?;
+        let subtree_count: usize = subtree_count.parse().map_err(|_| corrupt())?;
+
+        let oid = if entry_count >= 0 {
+            if data.len() < 20 {
+                return Err(corrupt());
+            }
+            let oid = Oid::from(&data[..20]);
+            *data = &data[20..];
+            Some(oid)
+        } else {
+            None
+        };
+
+        let mut subtrees = Vec::with_capacity(subtree_count);
+        for _ in 0..subtree_count {
+            subtrees.push(Self::parse_node(data)?);
+        }
+
+        Ok((
+            name,
+            Self {
+                entry_count,
+                oid,
+                subtrees,
+            },
+        ))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_node(b"", &mut bytes);
+        bytes
+    }
+
+    fn write_node(&self, name: &[u8], bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(name);
+        bytes.push(0);
+        bytes.extend_from_slice(
+            format!("{} {}\n", self.entry_count, self.subtrees.len()).as_bytes(),
+        );
+        if let (true, Some(oid)) = (self.entry_count >= 0, self.oid) {
+            bytes.extend_from_slice(oid.as_bytes());
+        }
+
+        for (name, subtree) in &self.subtrees {
+            subtree.write_node(name, bytes);
+        }
+    }
+
+    /// Marks the trees containing `path` as needing to be rewritten. When
+    /// `path` names a directory, that directory's subtree is dropped.
+    pub fn invalidate(&mut self, path: &[u8]) {
+        self.entry_count = -1;
+        self.oid = None;
+
+        match path.iter().position(|&b| b == b'/') {
+            Some(slash) => {
+                if let Some(subtree) = self.subtree(&path[..slash]) {
+                    subtree.invalidate(&path[slash + 1..]);
+                }
+            }
+            None => self.subtrees.retain(|(name, _)| name != path),
+        }
+    }
+
+    fn subtree(&mut self, name: &[u8]) -> Option<&mut CacheTree> {
+        self.subtrees
+            .iter_mut()
+            .find(|(subtree_name, _)| subtree_name == name)
+            .map(|(_, subtree)| subtree)
+    }
+
+    fn subtree_or_insert(&mut self, name: &[u8]) -> &mut CacheTree {
+        let position = self
+            .subtrees
+            .binary_search_by(|(subtree_name, _)| {
+                (subtree_name.len(), subtree_name.as_slice()).cmp(&(name.len(), name))
+            })
+            .unwrap_or_else(|position| {
+                self.subtrees
+                    .insert(position, (name.to_vec(), CacheTree::new()));
+                position
+            });
+        &mut self.subtrees[position].1
+    }
+
+    /// Stores the tree for `entries`, the sorted index entries under the
+    /// directory whose path is the first `base` bytes of their paths,
+    /// reusing the object IDs of subtrees that are still valid. Returns
+    /// the tree's ID and whether it came out empty because all its
+    /// entries are intent-to-add. A tree holding intent-to-add entries
+    /// stays invalid, as it does not describe everything in the index.
+    pub fn update(
+        &mut self,
+        entries: &[&IndexEntry],
+        base: usize,
+        db: &Db,
+    ) -> Result<(Oid, bool), anyhow::Error> {
+        if let (true, Some(oid)) = (self.entry_count >= 0, self.oid) {
+            return Ok((oid, false));
+        }
+
+        let mut tree = Tree::new();
+        let mut empty = true;
+        let mut has_intent_to_add = false;
+        let mut used = Vec::new();
+        let mut i = 0;
+
+        while i < entries.len() {
+            let path = entries[i].path.as_bytes();
+            let name = &path[base..];
+
+            if let Some(slash) = name.iter().position(|&b| b == b'/') {
+                let prefix = &path[..base + slash + 1];
+                let end = i + entries[i..]
+                    .iter()
+                    .take_while(|entry| entry.path.as_bytes().starts_with(prefix))
+                    .count();
+
+                let name = &name[..slash];
+                let subtree = self.subtree_or_insert(name);
+                let (oid, subtree_empty) = subtree.update(&entries[i..end], prefix.len(), db)?;
+                has_intent_to_add |= subtree.entry_count < 0;
+
+                if !subtree_empty {
+                    tree.add_subtree(&String::from_utf8_lossy(name), oid);
+                    empty = false;
+                }
+                used.push(name.to_vec());
+                i = end;
+            } else {
+                let entry = entries[i];
+                if entry.intent_to_add() {
+                    has_intent_to_add = true;
+                } else {
+                    tree.add_entry(
+                        PathBuf::from(String::from_utf8_lossy(name).into_owned()),
+                        entry.oid,
+                        entry.mode,
+                    );
+                    empty = false;
+                }
+                i += 1;
+            }
+        }
+
+        self.subtrees.retain(|(name, _)| used.contains(name));
+
+        let oid = db.store_object(&mut tree)?;
+        self.oid = Some(oid);
+        self.entry_count = if has_intent_to_add {
+            -1
+        } else {
+            entries.len() as i32
+        };
+
+        Ok((oid, empty))
+    }
+}
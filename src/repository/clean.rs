@@ -0,0 +1,214 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::ignore::IgnoreRules;
+use super::pathspec::Pathspec;
+use super::plumbing::relative_to;
+use super::{work_tree, Repository};
+
+/// How `clean` treats files the ignore rules match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ignored {
+    /// Leave them alone
+    Keep,
+    /// Remove them along with other untracked files, as `-x` does
+    Remove,
+    /// Remove only them, as `-X` does
+    Only,
+}
+
+/// The untracked files and directories of a work tree, and how to clean
+/// them.
+struct Untracked {
+    /// The untracked files and subdirectories of each directory
+    children: BTreeMap<PathBuf, Vec<(PathBuf, bool)>>,
+    tracked_dirs: HashSet<PathBuf>,
+    nested: HashSet<PathBuf>,
+    rules: IgnoreRules,
+    ignored: Ignored,
+    dirs: bool,
+    pathspec: Pathspec,
+}
+
+impl Untracked {
+    fn removable(&mut self, path: &Path, is_dir: bool) -> Result<bool, anyhow::Error> {
+        Ok(match self.ignored {
+            Ignored::Keep => !self.rules.is_ignored(&path.to_string_lossy(), is_dir)?,
+            Ignored::Remove => true,
+            Ignored::Only => self.rules.is_ignored(&path.to_string_lossy(), is_dir)?,
+        })
+    }
+
+    /// Whether the untracked directory `dir` can go as a whole: everything
+    /// in it is to be removed, or it is empty and to be removed itself.
+    fn whole(&mut self, dir: &Path) -> Result<bool, anyhow::Error> {
+        if self.nested.contains(dir) {
+            return Ok(false);
+        }
+        let children = self.children.get(dir).cloned().unwrap_or_default();
+        if children.is_empty() {
+            return self.removable(dir, true);
+        }
+
+        for (path, is_dir) in children {
+            let whole = if is_dir {
+                self.whole(&path)?
+            } else {
+                self.removable(&path, false)?
+            };
+            if !whole {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Collects what to remove below `dir`, each path with whether it is a
+    /// directory to remove as a whole.
+    fn collect(
+        &mut self,
+        dir: &Path,
+        removed: &mut Vec<(PathBuf, bool)>,
+    ) -> Result<(), anyhow::Error> {
+        for (path, is_dir) in self.children.get(dir).cloned().unwrap_or_default() {
+            if !is_dir {
+                if self.pathspec.matches(&path) && self.removable(&path, false)? {
+                    removed.push((path, false));
+                }
+                continue;
+            }
+
+            if !self.pathspec.may_match_below(&path) || self.nested.contains(&path) {
+                continue;
+            }
+
+            if self.tracked_dirs.contains(&path) {
+                self.collect(&path, removed)?;
+            } else if self.dirs {
+                if self.pathspec.matches(&path) && self.whole(&path)? {
+                    removed.push((path, true));
+                } else {
+                    self.collect(&path, removed)?;
+                }
+            } else if self.ignored == Ignored::Only
+                && !self.rules.is_ignored(&path.to_string_lossy(), true)?
+            {
+                // Like Git, -X without -d still finds ignored files in
+                // untracked directories that are not ignored themselves
+                self.collect(&path, removed)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Repository {
+    /// Removes untracked files below `cwd`, or below `paths`, printing
+    /// each. Untracked directories are only entered with `dirs`, or when
+    /// `paths` are given, and then go as a whole when nothing in them is
+    /// kept. `ignored` decides what happens to ignored files. Nested
+    /// repositories are never touched. Unless `clean.requireForce` is
+    /// false, nothing is removed without `force`; `dry_run` only reports.
+    pub fn clean(
+        &mut self,
+        cwd: &Path,
+        dry_run: bool,
+        force: bool,
+        dirs: bool,
+        ignored: Ignored,
+        paths: &[PathBuf],
+    ) -> Result<(), anyhow::Error> {
+        let workspace = work_tree(&self.workspace)?;
+        if !force && !dry_run {
+            match self.config.get_bool("clean.requireForce") {
+                Some(false) => {}
+                Some(true) => {
+                    return Err(anyhow::anyhow!(
+                        "clean.requireForce set to true and neither -n nor -f given; \
+                         refusing to clean"
+                    ))
+                }
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "clean.requireForce defaults to true and neither -n nor -f given; \
+                         refusing to clean"
+                    ))
+                }
+            }
+        }
+
+        let prefix = workspace.relative_path(cwd, Path::new(""))?;
+        let pathspec = if paths.is_empty() {
+            Pathspec::parse(workspace, cwd, &[PathBuf::from(".")])?
+        } else {
+            Pathspec::parse(workspace, cwd, paths)?
+        };
+
+        self.index.load()?;
+        let mut tracked = HashSet::new();
+        let mut tracked_dirs = HashSet::from([PathBuf::new()]);
+        for entry in self.index.entries.values() {
+            let path = PathBuf::from(entry.path.to_str()?);
+            tracked_dirs.extend(path.ancestors().skip(1).map(Path::to_path_buf));
+            tracked.insert(path);
+        }
+
+        let mut children: BTreeMap<PathBuf, Vec<(PathBuf, bool)>> = BTreeMap::new();
+        let mut nested = HashSet::new();
+        for dir in workspace.list_dirs()? {
+            if workspace.is_nested_repository(&dir) {
+                nested.insert(dir.clone());
+            }
+            let parent = dir.parent().unwrap_or(Path::new("")).to_path_buf();
+            children.entry(parent).or_default().push((dir, true));
+        }
+        for file in workspace.list_files(None)? {
+            if !tracked.contains(&file) {
+                let parent = file.parent().unwrap_or(Path::new("")).to_path_buf();
+                children.entry(parent).or_default().push((file, false));
+            }
+        }
+
+        let mut untracked = Untracked {
+            children,
+            tracked_dirs,
+            nested,
+            rules: IgnoreRules::new(
+                &workspace.root,
+                &self.git_path,
+                self.config.get("core.excludesFile"),
+            )?,
+            ignored,
+            dirs: dirs || !paths.is_empty(),
+            pathspec,
+        };
+        let mut removed = Vec::new();
+        untracked.collect(Path::new(""), &mut removed)?;
+        removed.sort();
+
+        for (path, is_dir) in removed {
+            let mut display = relative_to(&path, &prefix).display().to_string();
+            if is_dir {
+                display.push('/');
+            }
+
+            if dry_run {
+                println!("Would remove {}", display);
+                continue;
+            }
+            println!("Removing {}", display);
+            let full_path = workspace.root.join(&path);
+            if is_dir {
+                fs::remove_dir_all(full_path)?;
+            } else {
+                fs::remove_file(full_path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+use std::{env, fs, io::ErrorKind, path::PathBuf};
+
+use anyhow::Context;
+
+use crate::lockfile::Lockfile;
+
+pub struct ConfigUser {
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Debug, Clone)]
+struct ConfigSection {
+    name: String,
+    subsection: Option<String>,
+    entries: Vec<(String, String)>,
+}
+
+impl ConfigSection {
+    fn matches(&self, name: &str, subsection: Option<&str>) -> bool {
+        self.name.eq_ignore_ascii_case(name) && self.subsection.as_deref() == subsection
+    }
+
+    fn header(&self) -> String {
+        match &self.subsection {
+            Some(sub) => format!(
+                "[{} \"{}\"]",
+                self.name,
+                sub.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            None => format!("[{}]", self.name),
+        }
+    }
+}
+
+pub struct Config {
+    pub user: ConfigUser,
+    pub committer: ConfigUser,
+    path: PathBuf,
+    sections: Vec<ConfigSection>,
+}
+
+fn env_or_default(key: &str) -> String {
+    env::var_os(key)
+        .map(|var| var.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Splits `section.subsection.key` into its parts. The subsection may itself
+/// contain dots, so only the first and last components are special.
+fn split_key(key: &str) -> Result<(&str, Option<&str>, &str), anyhow::Error> {
+    let (section, rest) = key
+        .split_once('.')
+        .ok_or_else(|| anyhow::anyhow!("key does not contain a section: {}", key))?;
+
+    match rest.rsplit_once('.') {
+        Some((subsection, name)) => Ok((section, Some(subsection), name)),
+        None => Ok((section, None, rest)),
+    }
+}
+
+fn parse_value(raw: &str) -> String {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    let mut chars = raw.trim().chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(other) => value.push(other),
+                None => {}
+            },
+            ';' | '#' if !in_quotes => break,
+            _ => value.push(c),
+        }
+    }
+
+    value.trim_end().to_string()
+}
+
+fn format_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+
+    if value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']) {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+fn parse_header(line: &str) -> Result<(String, Option<String>), anyhow::Error> {
+    let inner = line
+        .strip_prefix('[')
+        .and_then(|l| l.split_once(']'))
+        .map(|(inner, _)| inner.trim())
+        .ok_or_else(|| anyhow::anyhow!("bad config section header: {}", line))?;
+
+    match inner.split_once(char::is_whitespace) {
+        Some((name, sub)) => {
+            let sub = sub.trim();
+            let sub = sub
+                .strip_prefix('"')
+                .and_then(|s| s.strip_suffix('"'))
+                .ok_or_else(|| anyhow::anyhow!("bad config section header: {}", line))?;
+            Ok((
+                name.to_string(),
+                Some(sub.replace("\\\"", "\"").replace("\\\\", "\\")),
+            ))
+        }
+        // Legacy `[section.subsection]` syntax
+        None => match inner.split_once('.') {
+            Some((name, sub)) => Ok((name.to_string(), Some(sub.to_lowercase()))),
+            None => Ok((inner.to_string(), None)),
+        },
+    }
+}
+
+impl Config {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            user: ConfigUser {
+                name: String::new(),
+                email: String::new(),
+            },
+            committer: ConfigUser {
+                name: String::new(),
+                email: String::new(),
+            },
+            path,
+            sections: Vec::new(),
+        }
+    }
+
+    pub fn load(path: PathBuf) -> Result<Self, anyhow::Error> {
+        let mut config = Self::new(path);
+
+        let content = match fs::read_to_string(&config.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Could not read config {:?}", config.path))
+            }
+        };
+        config.parse(&content)?;
+
+        config.user = config.identity("GIT_AUTHOR_NAME", "GIT_AUTHOR_EMAIL");
+        config.committer = config.identity("GIT_COMMITTER_NAME", "GIT_COMMITTER_EMAIL");
+
+        Ok(config)
+    }
+
+    /// Reads an identity from the given environment variables, falling
+    /// back to `user.name` and `user.email`.
+    fn identity(&self, name_var: &str, email_var: &str) -> ConfigUser {
+        let name = env_or_default(name_var);
+        let email = env_or_default(email_var);
+
+        ConfigUser {
+            name: if name.is_empty() {
+                self.get("user.name").unwrap_or_default().to_string()
+            } else {
+                name
+            },
+            email: if email.is_empty() {
+                self.get("user.email").unwrap_or_default().to_string()
+            } else {
+                email
+            },
+        }
+    }
+
+    fn parse(&mut self, content: &str) -> Result<(), anyhow::Error> {
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let (name, subsection) = parse_header(line)?;
+                self.sections.push(ConfigSection {
+                    name,
+                    subsection,
+                    entries: Vec::new(),
+                });
+                continue;
+            }
+
+            let section = self
+                .sections
+                .last_mut()
+                .ok_or_else(|| anyhow::anyhow!("config entry outside of a section: {}", line))?;
+
+            match line.split_once('=') {
+                Some((key, value)) => section
+                    .entries
+                    .push((key.trim().to_string(), parse_value(value))),
+                // A bare key is shorthand for `key = true`
+                None => section.entries.push((line.to_string(), "true".to_string())),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let (section, subsection, name) = split_key(key).ok()?;
+
+        self.sections
+            .iter()
+            .filter(|s| s.matches(section, subsection))
+            .flat_map(|s| s.entries.iter())
+            .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .next_back()
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), anyhow::Error> {
+        let (section, subsection, name) = split_key(key)?;
+
+        let existing = self
+            .sections
+            .iter_mut()
+            .rev()
+            .filter(|s| s.matches(section, subsection))
+            .find_map(|s| {
+                s.entries
+                    .iter_mut()
+                    .rev()
+                    .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            });
+
+        if let Some(entry) = existing {
+            entry.1 = value.to_string();
+            return Ok(());
+        }
+
+        match self
+            .sections
+            .iter_mut()
+            .rev()
+            .find(|s| s.matches(section, subsection))
+        {
+            Some(s) => s.entries.push((name.to_string(), value.to_string())),
+            None => self.sections.push(ConfigSection {
+                name: section.to_string(),
+                subsection: subsection.map(str::to_string),
+                entries: vec![(name.to_string(), value.to_string())],
+            }),
+        }
+
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        let mut lockfile = Lockfile::new(self.path.clone());
+        if !lockfile.hold_for_update()? {
+            return Err(anyhow::anyhow!(
+                "Could not acquire lock on file: {:?}",
+                self.path
+            ));
+        }
+
+        let mut content = String::new();
+        for section in &self.sections {
+            content.push_str(&section.header());
+            content.push('\n');
+            for (key, value) in &section.entries {
+                content.push_str(&format!("\t{} = {}\n", key, format_value(value)));
+            }
+        }
+
+        lockfile.write(content.as_bytes())?;
+        lockfile.commit()?;
+        Ok(())
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key).map(|value| {
+            !matches!(
+                value.to_lowercase().as_str(),
+                "false" | "no" | "off" | "0" | ""
+            )
+        })
+    }
+}
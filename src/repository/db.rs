@@ -1,17 +1,23 @@
 use std::{
+    collections::BTreeMap,
     fs::{self, File},
-    io::{self, Write},
+    io::{self, Read, Write},
     path::PathBuf,
 };
 
 use anyhow::Context;
 
-use flate2::{write::ZlibEncoder, Compression};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 use rand::distributions::{Alphanumeric, DistString};
 
 use crate::oid::Oid;
 
-use super::object::Object;
+use super::object::{
+    commit::Commit,
+    tag::Tag,
+    tree::{Tree, TreeEntry, MODE_DIR},
+    Object,
+};
 
 pub struct Db {
     root: PathBuf,
@@ -27,28 +33,153 @@ impl Db {
     }
 
     pub fn init(&self) -> Result<(), io::Error> {
-        fs::create_dir_all(self.objects_path())?;
-        fs::create_dir_all(self.root.join("refs"))?;
+        fs::create_dir_all(self.objects_path().join("info"))?;
+        fs::create_dir_all(self.objects_path().join("pack"))?;
         Ok(())
     }
 
+    /// Returns every stored object whose hex ID starts with `prefix`.
+    pub fn prefix_match(&self, prefix: &str) -> Result<Vec<Oid>, anyhow::Error> {
+        if prefix.len() < 2 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(Vec::new());
+        }
+
+        let prefix = prefix.to_lowercase();
+        let (group, rest) = prefix.split_at(2);
+        let entries = match fs::read_dir(self.objects_path().join(group)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut matches = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name.starts_with(rest) {
+                if let Some(oid) = Oid::parse(&format!("{}{}", group, name)) {
+                    matches.push(oid);
+                }
+            }
+        }
+
+        matches.sort();
+        Ok(matches)
+    }
+
     pub fn store_object(&self, object: &mut impl Object) -> Result<Oid, anyhow::Error> {
-        let serialized_object = object.to_bytes();
+        let oid = self.hash_object(object.kind(), &object.to_bytes(), true)?;
+        object.set_oid(oid);
+
+        Ok(oid)
+    }
+
+    /// Computes the ID of an object of type `kind` holding `data`, storing
+    /// it when `write` is set.
+    pub fn hash_object(&self, kind: &[u8], data: &[u8], write: bool) -> Result<Oid, anyhow::Error> {
         let mut content: Vec<u8> = vec![];
-        content.extend_from_slice(object.kind());
+        content.extend_from_slice(kind);
         content.push(b' ');
-        content.extend_from_slice(serialized_object.len().to_string().as_bytes());
+        content.extend_from_slice(data.len().to_string().as_bytes());
         content.push(0);
-        content.extend_from_slice(&serialized_object);
+        content.extend_from_slice(data);
 
         let oid = Oid::new(&content);
-        object.set_oid(oid.clone());
 
-        self.write_object(&oid.to_string(), &content)?;
+        if write {
+            self.write_object(&oid.to_string(), &content)?;
+        }
 
         Ok(oid)
     }
 
+    /// Reads the object `oid`, returning its type and its content with the
+    /// header stripped.
+    pub fn read_raw(&self, oid: &Oid) -> Result<(String, Vec<u8>), anyhow::Error> {
+        let hex = oid.to_string();
+        let (group, rest) = hex.split_at(2);
+        let path = self.objects_path().join(group).join(rest);
+
+        let file = File::open(&path).with_context(|| format!("object {} not found", oid))?;
+        let mut content = Vec::new();
+        ZlibDecoder::new(file)
+            .read_to_end(&mut content)
+            .with_context(|| format!("Could not inflate object {}", oid))?;
+
+        let header_end = content
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| anyhow::anyhow!("object {} has a corrupt header", oid))?;
+        let header = String::from_utf8_lossy(&content[..header_end]).into_owned();
+        let (kind, size) = header
+            .split_once(' ')
+            .ok_or_else(|| anyhow::anyhow!("object {} has a corrupt header", oid))?;
+
+        let data = content.split_off(header_end + 1);
+        if size.parse::<usize>().ok() != Some(data.len()) {
+            return Err(anyhow::anyhow!("object {} has the wrong size", oid));
+        }
+
+        Ok((kind.to_string(), data))
+    }
+
+    pub fn read_commit(&self, oid: &Oid) -> Result<Commit, anyhow::Error> {
+        let (kind, data) = self.read_raw(oid)?;
+        if kind != "commit" {
+            return Err(anyhow::anyhow!(
+                "object {} is a {}, not a commit",
+                oid,
+                kind
+            ));
+        }
+        Commit::parse(&data)
+    }
+
+    /// Lists every non-tree entry reachable from tree `oid`, keyed by its
+    /// full path, in Git's index order.
+    pub fn tree_files(&self, oid: &Oid) -> Result<BTreeMap<String, TreeEntry>, anyhow::Error> {
+        let mut files = BTreeMap::new();
+        self.collect_tree_files(oid, "", &mut files)?;
+        Ok(files)
+    }
+
+    fn collect_tree_files(
+        &self,
+        oid: &Oid,
+        prefix: &str,
+        files: &mut BTreeMap<String, TreeEntry>,
+    ) -> Result<(), anyhow::Error> {
+        let (kind, data) = self.read_raw(oid)?;
+        if kind != "tree" {
+            return Err(anyhow::anyhow!("object {} is a {}, not a tree", oid, kind));
+        }
+
+        for mut entry in Tree::parse(&data)? {
+            entry.name = format!("{}{}", prefix, entry.name);
+
+            if entry.mode == MODE_DIR {
+                self.collect_tree_files(&entry.oid, &format!("{}/", entry.name), files)?;
+            } else {
+                files.insert(entry.name.clone(), entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Follows tag objects until reaching a non-tag object.
+    pub fn peel(&self, oid: &Oid) -> Result<Oid, anyhow::Error> {
+        let mut oid = *oid;
+
+        loop {
+            let (kind, data) = self.read_raw(&oid)?;
+            if kind != "tag" {
+                return Ok(oid);
+            }
+
+            oid = Tag::parse(&data)?.object();
+        }
+    }
+
     pub fn write_object(&self, oid: &str, content: &[u8]) -> Result<(), anyhow::Error> {
         let (group, rest) = oid.split_at(2);
         let group_path = self.objects_path().join(group);
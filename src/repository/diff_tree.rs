@@ -0,0 +1,331 @@
+use std::io::{self, Write};
+
+use crate::diff::unified;
+use crate::merge_file::is_binary;
+use crate::oid::Oid;
+use crate::similarity::MAX_SCORE;
+
+use super::object::tree::{Tree, MODE_DIR};
+use super::read_tree::Files;
+use super::rename::{detect_renames, RenameOptions};
+use super::revision::{peel_to, Revision};
+use super::Repository;
+
+/// Lines of context around changes in patches.
+const CONTEXT: usize = 3;
+
+/// How a file changed between two trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Added,
+    Deleted,
+    Modified,
+    TypeChanged,
+    Renamed,
+    Copied,
+}
+
+impl Status {
+    pub fn letter(self) -> char {
+        match self {
+            Status::Added => 'A',
+            Status::Deleted => 'D',
+            Status::Modified => 'M',
+            Status::TypeChanged => 'T',
+            Status::Renamed => 'R',
+            Status::Copied => 'C',
+        }
+    }
+}
+
+/// A file before and after a change. Both paths are the same unless the
+/// file was renamed or copied, and `score` says how similar the two are
+/// then.
+#[derive(Debug, Clone)]
+pub struct FilePair {
+    pub status: Status,
+    pub old_path: String,
+    pub new_path: String,
+    pub old: Option<(Oid, u32)>,
+    pub new: Option<(Oid, u32)>,
+    pub score: u64,
+}
+
+impl FilePair {
+    pub fn is_unmodified(&self) -> bool {
+        self.status == Status::Modified && self.old == self.new
+    }
+
+    /// The status as raw output shows it, with the similarity of renames
+    /// and copies.
+    fn status_field(&self) -> String {
+        match self.status {
+            Status::Renamed | Status::Copied => {
+                format!("{}{:03}", self.status.letter(), self.similarity())
+            }
+            status => status.letter().to_string(),
+        }
+    }
+
+    fn similarity(&self) -> u64 {
+        self.score * 100 / MAX_SCORE
+    }
+}
+
+/// How `diff-tree` shows the changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    Raw,
+    NameOnly,
+    NameStatus,
+    Patch,
+}
+
+/// Pairs up the files of two trees by path, in path order. Unchanged files
+/// are kept as unmodified pairs when `unmodified` is set, to serve as copy
+/// sources.
+pub(super) fn compare_files(old: &Files, new: &Files, unmodified: bool) -> Vec<FilePair> {
+    let mut paths: Vec<&String> = old.keys().chain(new.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut pairs = Vec::new();
+    for path in paths {
+        let (before, after) = (old.get(path).copied(), new.get(path).copied());
+        let pair = |status, old, new| FilePair {
+            status,
+            old_path: path.clone(),
+            new_path: path.clone(),
+            old,
+            new,
+            score: 0,
+        };
+        match (before, after) {
+            (None, _) => pairs.push(pair(Status::Added, before, after)),
+            (_, None) => pairs.push(pair(Status::Deleted, before, after)),
+            (Some(a), Some(b)) if a == b && !unmodified => {}
+            (Some((_, a)), Some((_, b))) if (a == MODE_DIR) != (b == MODE_DIR) => {
+                // A file and a directory are different entries, the file
+                // sorting first
+                let deleted = pair(Status::Deleted, before, None);
+                let added = pair(Status::Added, None, after);
+                if b == MODE_DIR {
+                    pairs.extend([deleted, added]);
+                } else {
+                    pairs.extend([added, deleted]);
+                }
+            }
+            (Some((_, a)), Some((_, b))) if a & 0o170000 != b & 0o170000 => {
+                pairs.push(pair(Status::TypeChanged, before, after))
+            }
+            _ => pairs.push(pair(Status::Modified, before, after)),
+        }
+    }
+    pairs
+}
+
+fn mode_and_oid(file: Option<(Oid, u32)>) -> (u32, Oid) {
+    file.map_or((0, Oid::zero()), |(oid, mode)| (mode, oid))
+}
+
+impl Repository {
+    /// The files of `treeish`, or with `recursive` unset only its top-level
+    /// entries, subtrees included.
+    fn tree_entries(&self, treeish: &str, recursive: bool) -> Result<Files, anyhow::Error> {
+        if recursive {
+            return self.read_tree_files(treeish);
+        }
+
+        let tree = peel_to(
+            &self.db,
+            Revision::parse(treeish)?.resolve(&self.refs, &self.db)?,
+            "tree",
+        )?;
+        let (_, data) = self.db.read_raw(&tree)?;
+        Ok(Tree::parse(&data)?
+            .into_iter()
+            .map(|entry| (entry.name, (entry.oid, entry.mode)))
+            .collect())
+    }
+
+    /// Compares two trees, or a commit with its parent, and prints what
+    /// changed in `format`. `renames` turns on rename and copy detection;
+    /// with `find_copies_harder`, unchanged files are copy sources too. A
+    /// single commit is printed before its changes; root and merge commits
+    /// show nothing.
+    pub fn diff_tree(
+        &self,
+        trees: &[String],
+        recursive: bool,
+        format: DiffFormat,
+        renames: &RenameOptions,
+        find_copies_harder: bool,
+    ) -> Result<(), anyhow::Error> {
+        let recursive = recursive || format == DiffFormat::Patch;
+        let mut out = Vec::new();
+
+        let (old, new) = match trees {
+            [old, new] => (
+                self.tree_entries(old, recursive)?,
+                self.tree_entries(new, recursive)?,
+            ),
+            [commit] => {
+                let oid = self.resolve_commit(commit)?;
+                let &[parent] = self.db.read_commit(&oid)?.parents() else {
+                    return Ok(());
+                };
+                out.extend_from_slice(format!("{}\n", oid).as_bytes());
+                (
+                    self.tree_entries(&parent.to_string(), recursive)?,
+                    self.tree_entries(&oid.to_string(), recursive)?,
+                )
+            }
+            _ => return Err(anyhow::anyhow!("diff-tree takes a commit or two trees")),
+        };
+
+        let pairs = compare_files(&old, &new, find_copies_harder);
+        let pairs = detect_renames(&self.db, pairs, renames)?;
+        if pairs.is_empty() {
+            return Ok(());
+        }
+
+        for pair in &pairs {
+            match format {
+                DiffFormat::Raw => {
+                    let (old_mode, old_oid) = mode_and_oid(pair.old);
+                    let (new_mode, new_oid) = mode_and_oid(pair.new);
+                    out.extend_from_slice(
+                        format!(
+                            ":{:06o} {:06o} {} {} {}\t{}",
+                            old_mode,
+                            new_mode,
+                            old_oid,
+                            new_oid,
+                            pair.status_field(),
+                            pair.old_path
+                        )
+                        .as_bytes(),
+                    );
+                    if pair.old_path != pair.new_path {
+                        out.extend_from_slice(format!("\t{}", pair.new_path).as_bytes());
+                    }
+                    out.push(b'\n');
+                }
+                DiffFormat::NameOnly => {
+                    out.extend_from_slice(format!("{}\n", pair.new_path).as_bytes());
+                }
+                DiffFormat::NameStatus => {
+                    out.extend_from_slice(
+                        format!("{}\t{}", pair.status_field(), pair.old_path).as_bytes(),
+                    );
+                    if pair.old_path != pair.new_path {
+                        out.extend_from_slice(format!("\t{}", pair.new_path).as_bytes());
+                    }
+                    out.push(b'\n');
+                }
+                DiffFormat::Patch if pair.status == Status::TypeChanged => {
+                    // A file turning into a symlink or back is shown as a
+                    // deletion and a creation
+                    for (old, new) in [(pair.old, None), (None, pair.new)] {
+                        let half = FilePair {
+                            status: if old.is_some() {
+                                Status::Deleted
+                            } else {
+                                Status::Added
+                            },
+                            old,
+                            new,
+                            ..pair.clone()
+                        };
+                        self.write_patch(&half, &mut out)?;
+                    }
+                }
+                DiffFormat::Patch => self.write_patch(pair, &mut out)?,
+            }
+        }
+
+        io::stdout().write_all(&out)?;
+        Ok(())
+    }
+
+    /// Writes the patch for one file in Git's format: the extended header
+    /// lines, then the hunks, or a note that binary files differ.
+    fn write_patch(&self, pair: &FilePair, out: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        let a = format!("a/{}", pair.old_path);
+        let b = format!("b/{}", pair.new_path);
+        let mut header = format!("diff --git {} {}\n", a, b);
+
+        match (pair.old, pair.new) {
+            (None, Some((_, mode))) => header.push_str(&format!("new file mode {:06o}\n", mode)),
+            (Some((_, mode)), None) => {
+                header.push_str(&format!("deleted file mode {:06o}\n", mode))
+            }
+            (Some((_, old)), Some((_, new))) if old != new => {
+                header.push_str(&format!("old mode {:06o}\nnew mode {:06o}\n", old, new));
+            }
+            _ => {}
+        }
+        match pair.status {
+            Status::Renamed | Status::Copied => {
+                let verb = if pair.status == Status::Renamed {
+                    "rename"
+                } else {
+                    "copy"
+                };
+                header.push_str(&format!(
+                    "similarity index {}%\n{} from {}\n{} to {}\n",
+                    pair.similarity(),
+                    verb,
+                    pair.old_path,
+                    verb,
+                    pair.new_path
+                ));
+            }
+            _ => {}
+        }
+
+        let (old_mode, old_oid) = mode_and_oid(pair.old);
+        let (new_mode, new_oid) = mode_and_oid(pair.new);
+        if old_oid != new_oid {
+            header.push_str(&format!(
+                "index {}..{}",
+                old_oid.to_short(),
+                new_oid.to_short()
+            ));
+            if old_mode == new_mode {
+                header.push_str(&format!(" {:06o}", old_mode));
+            }
+            header.push('\n');
+        }
+        out.extend_from_slice(header.as_bytes());
+
+        let read = |file: Option<(Oid, u32)>| -> Result<Vec<u8>, anyhow::Error> {
+            match file {
+                Some((oid, mode)) if mode != MODE_DIR => Ok(self.db.read_raw(&oid)?.1),
+                _ => Ok(Vec::new()),
+            }
+        };
+        let (old_data, new_data) = (read(pair.old)?, read(pair.new)?);
+        if old_data == new_data {
+            return Ok(());
+        }
+
+        let a = if pair.old.is_some() {
+            a
+        } else {
+            "/dev/null".to_string()
+        };
+        let b = if pair.new.is_some() {
+            b
+        } else {
+            "/dev/null".to_string()
+        };
+        if is_binary(&old_data) || is_binary(&new_data) {
+            out.extend_from_slice(format!("Binary files {} and {} differ\n", a, b).as_bytes());
+        } else {
+            out.extend_from_slice(format!("--- {}\n+++ {}\n", a, b).as_bytes());
+            unified(&old_data, &new_data, CONTEXT, out);
+        }
+        Ok(())
+    }
+}
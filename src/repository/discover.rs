@@ -0,0 +1,134 @@
+use std::{
+    env, fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use super::config::Config;
+
+/// Location of a repository: the git directory and the work tree it
+/// belongs to, if it is not bare.
+pub struct Location {
+    pub git_path: PathBuf,
+    pub work_tree: Option<PathBuf>,
+}
+
+/// Lexically resolves `.` and `..` components without touching the
+/// filesystem, so paths that do not exist yet can still be normalized.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            other => result.push(other),
+        }
+    }
+
+    result
+}
+
+fn absolute(cwd: &Path, path: impl AsRef<Path>) -> PathBuf {
+    normalize(&cwd.join(path))
+}
+
+/// Follows a `.git` file of the form `gitdir: <path>`, as used by
+/// submodules and linked worktrees.
+fn read_gitfile(path: &Path) -> Result<PathBuf, anyhow::Error> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Could not read gitfile {:?}", path))?;
+
+    let target = content
+        .trim_end()
+        .strip_prefix("gitdir: ")
+        .ok_or_else(|| anyhow::anyhow!("invalid gitfile format: {}", path.display()))?;
+
+    let base = path.parent().unwrap_or(Path::new("/"));
+    Ok(absolute(base, target))
+}
+
+fn ceiling_directories(cwd: &Path) -> Vec<PathBuf> {
+    env::var_os("GIT_CEILING_DIRECTORIES")
+        .map(|dirs| {
+            env::split_paths(&dirs)
+                .filter(|dir| dir.is_absolute())
+                .map(|dir| absolute(cwd, dir))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A directory is a git directory if it has `HEAD`, `objects` and `refs`.
+fn is_git_dir(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+fn is_bare(git_path: &Path) -> Result<bool, anyhow::Error> {
+    Ok(Config::load(git_path.join("config"))?
+        .get_bool("core.bare")
+        .unwrap_or(false))
+}
+
+fn find_dot_git(dir: &Path) -> Result<Option<PathBuf>, anyhow::Error> {
+    let dot_git = dir.join(".git");
+
+    match fs::metadata(&dot_git) {
+        Ok(meta) if meta.is_dir() => Ok(Some(dot_git)),
+        Ok(meta) if meta.is_file() => read_gitfile(&dot_git).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Finds the repository containing `cwd`, honouring `GIT_DIR`,
+/// `GIT_WORK_TREE` and `GIT_CEILING_DIRECTORIES`.
+pub fn discover(cwd: &Path) -> Result<Location, anyhow::Error> {
+    let work_tree_override = env::var_os("GIT_WORK_TREE").map(|path| absolute(cwd, path));
+
+    if let Some(git_dir) = env::var_os("GIT_DIR") {
+        let git_path = absolute(cwd, git_dir);
+        let work_tree = match work_tree_override {
+            Some(work_tree) => Some(work_tree),
+            None if is_bare(&git_path)? => None,
+            None => Some(cwd.to_path_buf()),
+        };
+
+        return Ok(Location {
+            git_path,
+            work_tree,
+        });
+    }
+
+    let ceilings = ceiling_directories(cwd);
+    let mut dir = normalize(cwd);
+
+    loop {
+        if let Some(git_path) = find_dot_git(&dir)? {
+            return Ok(Location {
+                git_path,
+                work_tree: Some(work_tree_override.unwrap_or(dir)),
+            });
+        }
+
+        if is_git_dir(&dir) {
+            return Ok(Location {
+                git_path: dir,
+                work_tree: work_tree_override,
+            });
+        }
+
+        match dir.parent() {
+            Some(parent) if !ceilings.iter().any(|c| c == parent) => {
+                dir = parent.to_path_buf();
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "not a git repository (or any of the parent directories): .git"
+                ))
+            }
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    cmp::Ordering,
+    io::{self, Write},
+};
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::oid::Oid;
+use crate::wildmatch::wildmatch;
+
+use super::graph::is_ancestor;
+use super::object::{
+    commit::{Author, Commit},
+    tag::Tag,
+};
+use super::revision::Revision;
+use super::tag::version_cmp;
+use super::Repository;
+
+const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+const DATE_FORMAT: &str = "%a %b %-d %H:%M:%S %Y %z";
+
+/// A ref together with the object it points at, loaded once so every
+/// atom in the format can be expanded from it.
+struct RefItem {
+    name: String,
+    oid: Oid,
+    kind: String,
+    commit: Option<Commit>,
+    tag: Option<Tag>,
+    upstream: Option<String>,
+}
+
+/// A value an atom expands to; dates keep their time so they sort
+/// chronologically rather than as text.
+enum AtomValue {
+    Text(String),
+    Date(Option<DateTime<FixedOffset>>),
+}
+
+impl AtomValue {
+    fn render(&self, modifier: Option<&str>) -> String {
+        match self {
+            AtomValue::Text(text) => text.clone(),
+            AtomValue::Date(None) => String::new(),
+            AtomValue::Date(Some(date)) => match modifier {
+                Some("unix") => date.timestamp().to_string(),
+                Some("iso") | Some("iso8601") => date.format("%Y-%m-%d %H:%M:%S %z").to_string(),
+                Some("short") => date.format("%Y-%m-%d").to_string(),
+                _ => date.format(DATE_FORMAT).to_string(),
+            },
+        }
+    }
+}
+
+/// Shortens a full ref name the way `:short` does, e.g. `refs/heads/main`
+/// to `main`.
+pub fn short_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+fn identity_atom(person: Option<&Author>, field: &str) -> AtomValue {
+    match field {
+        "name" => AtomValue::Text(person.map(|p| p.name().to_string()).unwrap_or_default()),
+        "email" => AtomValue::Text(
+            person
+                .map(|p| format!("<{}>", p.email()))
+                .unwrap_or_default(),
+        ),
+        "date" => AtomValue::Date(person.map(Author::time)),
+        _ => AtomValue::Text(person.map(Author::string).unwrap_or_default()),
+    }
+}
+
+impl RefItem {
+    fn message(&self) -> &str {
+        match (&self.commit, &self.tag) {
+            (Some(commit), _) => commit.message(),
+            (_, Some(tag)) => tag.message(),
+            _ => "",
+        }
+    }
+
+    fn atom(&self, name: &str) -> Result<AtomValue, anyhow::Error> {
+        let (atom, modifier) = match name.split_once(':') {
+            Some((atom, modifier)) => (atom, Some(modifier)),
+            None => (name, None),
+        };
+
+        let value = match atom {
+            "refname" => AtomValue::Text(match modifier {
+                Some("short") => short_ref_name(&self.name).to_string(),
+                _ => self.name.clone(),
+            }),
+            "objectname" => AtomValue::Text(match modifier {
+                Some("short") => self.oid.to_short(),
+                _ => self.oid.to_string(),
+            }),
+            "objecttype" => AtomValue::Text(self.kind.clone()),
+            "upstream" => AtomValue::Text(match (&self.upstream, modifier) {
+                (Some(upstream), Some("short")) => short_ref_name(upstream).to_string(),
+                (Some(upstream), _) => upstream.clone(),
+                (None, _) => String::new(),
+            }),
+            "subject" => AtomValue::Text(self.message().lines().next().unwrap_or_default().into()),
+            "body" => AtomValue::Text(
+                self.message()
+                    .split_once("\n\n")
+                    .map(|(_, body)| body.to_string())
+                    .unwrap_or_default(),
+            ),
+            "contents" => AtomValue::Text(self.message().to_string()),
+            _ => {
+                let person = |prefix: &str| atom.strip_prefix(prefix);
+
+                if let Some(field) = person("author") {
+                    identity_atom(self.commit.as_ref().map(Commit::author), field)
+                } else if let Some(field) = person("committer") {
+                    identity_atom(self.commit.as_ref().map(Commit::committer), field)
+                } else if let Some(field) = person("tagger") {
+                    identity_atom(self.tag.as_ref().and_then(Tag::tagger), field)
+                } else if let Some(field) = person("creator") {
+                    identity_atom(
+                        self.commit
+                            .as_ref()
+                            .map(Commit::committer)
+                            .or(self.tag.as_ref().and_then(Tag::tagger)),
+                        field,
+                    )
+                } else {
+                    return Err(anyhow::anyhow!("unknown field name: {}", atom));
+                }
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn render_atom(&self, name: &str) -> Result<String, anyhow::Error> {
+        let modifier = name.split_once(':').map(|(_, modifier)| modifier);
+        Ok(self.atom(name)?.render(modifier))
+    }
+}
+
+/// Expands `%(atom)`, `%%` and `%xx` hex escapes in `format` for `item`.
+/// `%(*atom)` expands the atom for the object a tag points at.
+/// The output is bytes, as an escape may produce any byte.
+fn expand_format(
+    format: &str,
+    item: &RefItem,
+    deref: Option<&RefItem>,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut output = Vec::new();
+    let mut rest = format;
+
+    while let Some(start) = rest.find('%') {
+        output.extend_from_slice(&rest.as_bytes()[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(after) = rest.strip_prefix('(') {
+            let end = after
+                .find(')')
+                .ok_or_else(|| anyhow::anyhow!("malformed format string {}", format))?;
+            let atom = &after[..end];
+
+            match atom.strip_prefix('*') {
+                Some(atom) => {
+                    if let Some(deref) = deref {
+                        output.extend_from_slice(deref.render_atom(atom)?.as_bytes());
+                    }
+                }
+                None => output.extend_from_slice(item.render_atom(atom)?.as_bytes()),
+            }
+            rest = &after[end + 1..];
+        } else if let Some(after) = rest.strip_prefix('%') {
+            output.push(b'%');
+            rest = after;
+        } else if let Some(byte) = rest
+            .get(..2)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            output.push(byte);
+            rest = &rest[2..];
+        } else {
+            output.push(b'%');
+        }
+    }
+
+    output.extend_from_slice(rest.as_bytes());
+    Ok(output)
+}
+
+fn compare_items(a: &RefItem, b: &RefItem, key: &str) -> Result<Ordering, anyhow::Error> {
+    if key == "version:refname" || key == "v:refname" {
+        return Ok(version_cmp(&a.name, &b.name));
+    }
+
+    Ok(match (a.atom(key)?, b.atom(key)?) {
+        (AtomValue::Date(a), AtomValue::Date(b)) => a.cmp(&b),
+        (a, b) => a.render(None).cmp(&b.render(None)),
+    })
+}
+
+/// `for-each-ref` patterns match either as a prefix ending at a `/`
+/// boundary or as a glob against the full name.
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    let prefix = pattern.trim_end_matches('/');
+    name == prefix
+        || name
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+        || wildmatch(pattern, name, true, false)
+}
+
+impl Repository {
+    fn load_ref_item(&self, name: String, oid: Oid) -> Result<RefItem, anyhow::Error> {
+        let (kind, data) = self.db.read_raw(&oid)?;
+
+        let upstream = name
+            .strip_prefix("refs/heads/")
+            .and_then(|branch| self.upstream_of(branch));
+
+        Ok(RefItem {
+            commit: (kind == "commit")
+                .then(|| Commit::parse(&data))
+                .transpose()?,
+            tag: (kind == "tag").then(|| Tag::parse(&data)).transpose()?,
+            name,
+            oid,
+            kind,
+            upstream,
+        })
+    }
+
+    /// The remote-tracking ref configured as `branch`'s upstream through
+    /// `branch.<name>.remote` and `branch.<name>.merge`. The remote must
+    /// itself be configured.
+    fn upstream_of(&self, branch: &str) -> Option<String> {
+        let remote = self.config.get(&format!("branch.{}.remote", branch))?;
+        let merge = self.config.get(&format!("branch.{}.merge", branch))?;
+
+        if remote == "." {
+            return Some(merge.to_string());
+        }
+
+        self.config.get(&format!("remote.{}.url", remote))?;
+
+        let merge = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+        Some(format!("refs/remotes/{}/{}", remote, merge))
+    }
+
+    fn commit_of(&self, oid: &Oid) -> Result<Option<Oid>, anyhow::Error> {
+        let peeled = self.db.peel(oid)?;
+        let (kind, _) = self.db.read_raw(&peeled)?;
+        Ok((kind == "commit").then_some(peeled))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn for_each_ref(
+        &self,
+        patterns: &[String],
+        format: Option<&str>,
+        sort: &[String],
+        count: Option<usize>,
+        contains: Option<&str>,
+        merged: Option<&str>,
+        no_merged: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        let resolve = |rev: Option<&str>| -> Result<Option<Oid>, anyhow::Error> {
+            rev.map(|rev| Revision::parse(rev)?.resolve(&self.refs, &self.db))
+                .transpose()
+        };
+        let contains = resolve(contains)?;
+        let merged = resolve(merged)?;
+        let no_merged = resolve(no_merged)?;
+
+        let mut items = Vec::new();
+        for (name, oid) in self.refs.list_refs("refs/")? {
+            if !patterns.is_empty() && !patterns.iter().any(|p| matches_pattern(&name, p)) {
+                continue;
+            }
+
+            if contains.is_some() || merged.is_some() || no_merged.is_some() {
+                let Some(commit) = self.commit_of(&oid)? else {
+                    continue;
+                };
+                if let Some(contains) = contains {
+                    if !is_ancestor(&self.db, &contains, &commit)? {
+                        continue;
+                    }
+                }
+                if let Some(merged) = merged {
+                    if !is_ancestor(&self.db, &commit, &merged)? {
+                        continue;
+                    }
+                }
+                if let Some(no_merged) = no_merged {
+                    if is_ancestor(&self.db, &commit, &no_merged)? {
+                        continue;
+                    }
+                }
+            }
+
+            items.push(self.load_ref_item(name, oid)?);
+        }
+
+        // Sort by each key in turn with a stable sort, so the last key given
+        // ends up as the primary one
+        let mut error = None;
+        for key in sort {
+            let (reverse, key) = match key.strip_prefix('-') {
+                Some(key) => (true, key),
+                None => (false, key.as_str()),
+            };
+
+            items.sort_by(|a, b| {
+                let ordering = compare_items(a, b, key).unwrap_or_else(|e| {
+                    error.get_or_insert(e);
+                    Ordering::Equal
+                });
+                if reverse {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+        if let Some(e) = error {
+            return Err(e);
+        }
+
+        let format = format.unwrap_or(DEFAULT_FORMAT);
+        let deref_needed = format.contains("%(*");
+
+        let mut stdout = io::stdout().lock();
+        for item in items.iter().take(count.unwrap_or(usize::MAX)) {
+            let deref = match &item.tag {
+                Some(_) if deref_needed => {
+                    let peeled = self.db.peel(&item.oid)?;
+                    Some(self.load_ref_item(item.name.clone(), peeled)?)
+                }
+                _ => None,
+            };
+
+            let mut line = expand_format(format, item, deref.as_ref())?;
+            line.push(b'\n');
+            stdout.write_all(&line)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lists refs as `<oid> <refname>`. Patterns match whole trailing path
+    /// components; with `verify` they must be exact ref names, and any that
+    /// is not an error. Returns whether anything matched.
+    pub fn show_ref(
+        &self,
+        patterns: &[String],
+        heads: bool,
+        tags: bool,
+        dereference: bool,
+        hash: Option<usize>,
+        verify: bool,
+    ) -> Result<bool, anyhow::Error> {
+        let refs: Vec<(String, Oid)> = if verify {
+            let mut refs = Vec::new();
+            for name in patterns {
+                match self.refs.read_ref(name)? {
+                    Some(oid) if name.starts_with("refs/") || name == "HEAD" => {
+                        refs.push((name.clone(), oid))
+                    }
+                    _ => return Err(anyhow::anyhow!("'{}' - not a valid ref", name)),
+                }
+            }
+            refs
+        } else {
+            self.refs
+                .list_refs("refs/")?
+                .into_iter()
+                .filter(|(name, _)| {
+                    (!heads && !tags)
+                        || (heads && name.starts_with("refs/heads/"))
+                        || (tags && name.starts_with("refs/tags/"))
+                })
+                .filter(|(name, _)| {
+                    patterns.is_empty()
+                        || patterns.iter().any(|p| {
+                            name == p || name.ends_with(&format!("/{}", p.trim_start_matches('/')))
+                        })
+                })
+                .collect()
+        };
+
+        for (name, oid) in &refs {
+            let print = |oid: &Oid, name: &str| match hash {
+                Some(0) => println!("{}", oid),
+                Some(len) => println!("{}", &oid.to_string()[..len.clamp(4, 40)]),
+                None => println!("{} {}", oid, name),
+            };
+
+            print(oid, name);
+
+            if dereference {
+                let peeled = self.db.peel(oid)?;
+                if peeled != *oid {
+                    print(&peeled, &format!("{}^{{}}", name));
+                }
+            }
+        }
+
+        Ok(!refs.is_empty())
+    }
+}
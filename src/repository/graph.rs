@@ -0,0 +1,219 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+use crate::oid::Oid;
+
+use super::db::Db;
+
+/// Whether `ancestor` can be reached from `descendant` by following parent
+/// links. A commit counts as its own ancestor.
+pub fn is_ancestor(db: &Db, ancestor: &Oid, descendant: &Oid) -> Result<bool, anyhow::Error> {
+    let mut queue = VecDeque::from([*descendant]);
+    let mut seen = HashSet::from([*descendant]);
+
+    while let Some(oid) = queue.pop_front() {
+        if oid == *ancestor {
+            return Ok(true);
+        }
+
+        for parent in db.read_commit(&oid)?.parents() {
+            if seen.insert(*parent) {
+                queue.push_back(*parent);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+// Marks used while painting the graph in `paint_down_to_common`
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// Parents and committer dates of the commits walked so far, so each is
+/// read from the database once.
+struct CommitGraph<'a> {
+    db: &'a Db,
+    commits: HashMap<Oid, (Vec<Oid>, i64)>,
+}
+
+impl<'a> CommitGraph<'a> {
+    fn new(db: &'a Db) -> Self {
+        Self {
+            db,
+            commits: HashMap::new(),
+        }
+    }
+
+    fn load(&mut self, oid: &Oid) -> Result<&(Vec<Oid>, i64), anyhow::Error> {
+        if !self.commits.contains_key(oid) {
+            let commit = self.db.read_commit(oid)?;
+            let date = commit.committer().time().timestamp();
+            self.commits.insert(*oid, (commit.parents().to_vec(), date));
+        }
+        Ok(&self.commits[oid])
+    }
+
+    fn parents(&mut self, oid: &Oid) -> Result<Vec<Oid>, anyhow::Error> {
+        Ok(self.load(oid)?.0.clone())
+    }
+
+    fn date(&mut self, oid: &Oid) -> Result<i64, anyhow::Error> {
+        Ok(self.load(oid)?.1)
+    }
+
+    /// Sorts `commits` newest first, keeping the order of equal dates.
+    fn sort_by_date(&mut self, commits: &mut [Oid]) -> Result<(), anyhow::Error> {
+        for oid in commits.iter() {
+            self.load(oid)?;
+        }
+        commits.sort_by_key(|oid| Reverse(self.commits[oid].1));
+        Ok(())
+    }
+}
+
+/// Git's merge-base walk: paints everything reachable from `one` and from
+/// `twos`, newest commit first, and collects the commits reached from both
+/// sides. Once such a commit is found, the paint below it is marked stale,
+/// and the walk stops when only stale commits are left to visit. Returns
+/// the common commits that are not below another one, newest first.
+fn paint_down_to_common(
+    graph: &mut CommitGraph,
+    one: &Oid,
+    twos: &[Oid],
+) -> Result<Vec<Oid>, anyhow::Error> {
+    let mut flags: HashMap<Oid, u8> = HashMap::new();
+    // Newest first; commits with the same date in the order they were queued
+    let mut queue = BinaryHeap::new();
+    let mut queued: u64 = 0;
+
+    *flags.entry(*one).or_default() |= PARENT1;
+    queue.push((graph.date(one)?, Reverse(queued), *one));
+    for two in twos {
+        queued += 1;
+        *flags.entry(*two).or_default() |= PARENT2;
+        queue.push((graph.date(two)?, Reverse(queued), *two));
+    }
+
+    let mut result = Vec::new();
+
+    while queue.iter().any(|(_, _, oid)| flags[oid] & STALE == 0) {
+        let (_, _, oid) = queue.pop().unwrap();
+        let mut paint = flags[&oid] & (PARENT1 | PARENT2 | STALE);
+
+        if paint == PARENT1 | PARENT2 {
+            let commit_flags = flags.get_mut(&oid).unwrap();
+            if *commit_flags & RESULT == 0 {
+                *commit_flags |= RESULT;
+                result.push(oid);
+            }
+            paint |= STALE;
+        }
+
+        for parent in graph.parents(&oid)? {
+            let parent_flags = flags.entry(parent).or_default();
+            if *parent_flags & paint == paint {
+                continue;
+            }
+            *parent_flags |= paint;
+            queued += 1;
+            queue.push((graph.date(&parent)?, Reverse(queued), parent));
+        }
+    }
+
+    result.retain(|oid| flags[oid] & STALE == 0);
+    graph.sort_by_date(&mut result)?;
+    Ok(result)
+}
+
+/// The best common ancestors of `one` and all of `twos`, as if `twos`
+/// were merged into a single commit first. Several bases are possible
+/// when there are criss-cross merges; they are returned newest first.
+pub fn merge_bases(db: &Db, one: &Oid, twos: &[Oid]) -> Result<Vec<Oid>, anyhow::Error> {
+    if twos.contains(one) {
+        return Ok(vec![*one]);
+    }
+
+    let mut graph = CommitGraph::new(db);
+    let candidates = paint_down_to_common(&mut graph, one, twos)?;
+    if candidates.len() < 2 {
+        return Ok(candidates);
+    }
+
+    // Drop candidates that are ancestors of another one
+    let mut bases = Vec::new();
+    for (i, candidate) in candidates.iter().enumerate() {
+        let mut redundant = false;
+        for (j, other) in candidates.iter().enumerate() {
+            if i != j && is_ancestor(db, candidate, other)? {
+                redundant = true;
+                break;
+            }
+        }
+        if !redundant {
+            bases.push(*candidate);
+        }
+    }
+
+    Ok(bases)
+}
+
+/// The common ancestors of all `commits`, for an octopus merge: the merge
+/// bases of the first two, then of each of those with the next commit,
+/// and so on.
+pub fn octopus_merge_bases(db: &Db, commits: &[Oid]) -> Result<Vec<Oid>, anyhow::Error> {
+    let Some((first, rest)) = commits.split_first() else {
+        return Ok(Vec::new());
+    };
+
+    let mut bases = vec![*first];
+    for commit in rest {
+        let mut next = Vec::new();
+        for base in &bases {
+            next.extend(merge_bases(db, commit, &[*base])?);
+        }
+        bases = next;
+    }
+
+    Ok(bases)
+}
+
+/// The commits reachable from `include` but not from `exclude`, newest
+/// first, the way `git rev-list exclude..include` lists them.
+pub fn range(db: &Db, exclude: &Oid, include: &Oid) -> Result<Vec<Oid>, anyhow::Error> {
+    let mut graph = CommitGraph::new(db);
+
+    let mut hidden = HashSet::from([*exclude]);
+    let mut queue = VecDeque::from([*exclude]);
+    while let Some(oid) = queue.pop_front() {
+        for parent in graph.parents(&oid)? {
+            if hidden.insert(parent) {
+                queue.push_back(parent);
+            }
+        }
+    }
+
+    // Newest first; commits with the same date in the order they were queued
+    let mut queue = BinaryHeap::new();
+    let mut queued: u64 = 0;
+    let mut seen = HashSet::from([*include]);
+    if !hidden.contains(include) {
+        queue.push((graph.date(include)?, Reverse(queued), *include));
+    }
+
+    let mut result = Vec::new();
+    while let Some((_, _, oid)) = queue.pop() {
+        result.push(oid);
+        for parent in graph.parents(&oid)? {
+            if hidden.contains(&parent) || !seen.insert(parent) {
+                continue;
+            }
+            queued += 1;
+            queue.push((graph.date(&parent)?, Reverse(queued), parent));
+        }
+    }
+
+    Ok(result)
+}
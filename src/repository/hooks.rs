@@ -0,0 +1,69 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Context;
+
+pub struct Hooks {
+    hooks_path: PathBuf,
+    work_dir: PathBuf,
+    index_path: PathBuf,
+}
+
+impl Hooks {
+    /// `hooks_path` is `core.hooksPath` when configured; relative values are
+    /// resolved against the directory hooks run in, as Git does.
+    pub fn new(git_path: &Path, work_dir: PathBuf, hooks_path: Option<&str>) -> Self {
+        let hooks_path = match hooks_path {
+            Some(path) => work_dir.join(path),
+            None => git_path.join("hooks"),
+        };
+
+        Self {
+            hooks_path,
+            index_path: git_path.join("index"),
+            work_dir,
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<PathBuf> {
+        let path = self.hooks_path.join(name);
+        let metadata = fs::metadata(&path).ok()?;
+
+        if !metadata.is_file() {
+            return None;
+        }
+
+        if metadata.permissions().mode() & 0o111 == 0 {
+            eprintln!(
+                "hint: The '{}' hook was ignored because it's not set as executable.",
+                path.display()
+            );
+            return None;
+        }
+
+        Some(path)
+    }
+
+    /// Runs hook `name` if it exists. Returns `false` when the hook exited
+    /// with a non-zero status; a missing hook counts as success.
+    pub fn run<S: AsRef<OsStr>>(&self, name: &str, args: &[S]) -> Result<bool, anyhow::Error> {
+        let Some(path) = self.find(name) else {
+            return Ok(true);
+        };
+
+        let status = Command::new(&path)
+            .args(args)
+            .current_dir(&self.work_dir)
+            .env("GIT_INDEX_FILE", &self.index_path)
+            .env("GIT_EDITOR", ":")
+            .status()
+            .with_context(|| format!("Could not run hook {:?}", path))?;
+
+        Ok(status.success())
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::wildmatch::wildmatch;
+
+/// A line of an ignore file.
+struct Pattern {
+    pattern: String,
+    /// `!pattern`: paths it matches are not ignored after all
+    negated: bool,
+    /// `pattern/`: only directories match
+    dir_only: bool,
+    /// Without a `/`, the pattern matches the last path component at any
+    /// depth; otherwise the whole path below the ignore file's directory.
+    basename_only: bool,
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        // Trailing spaces are dropped unless escaped
+        let mut end = line.len();
+        while line[..end].ends_with(' ') && !line[..end - 1].ends_with('\\') {
+            end -= 1;
+        }
+        let line = &line[..end];
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let line = line
+            .strip_prefix('\\')
+            .filter(|rest| rest.starts_with(['#', '!']))
+            .unwrap_or(line);
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let basename_only = !line.contains('/');
+        let pattern = line.strip_prefix('/').unwrap_or(line);
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            pattern: pattern.to_string(),
+            negated,
+            dir_only,
+            basename_only,
+        })
+    }
+
+    /// Whether the pattern matches `path`, relative to the directory of the
+    /// ignore file it comes from.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.basename_only {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            wildmatch(&self.pattern, name, false, false)
+        } else {
+            wildmatch(&self.pattern, path, true, false)
+        }
+    }
+}
+
+fn parse_patterns(content: &str) -> Vec<Pattern> {
+    content.lines().filter_map(Pattern::parse).collect()
+}
+
+fn read_patterns(path: &Path) -> Result<Vec<Pattern>, anyhow::Error> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(parse_patterns(&content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The user's ignore file: `core.excludesFile`, or `git/ignore` in the
+/// XDG config directory.
+fn excludes_file_path(excludes_file: Option<&str>) -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    match excludes_file {
+        Some(path) => match path.strip_prefix("~/") {
+            Some(rest) => home.map(|home| home.join(rest)),
+            None => Some(PathBuf::from(path)),
+        },
+        None => env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home.map(|home| home.join(".config")))
+            .map(|config| config.join("git").join("ignore")),
+    }
+}
+
+/// Git's ignore rules for a work tree. The `.gitignore` of each directory
+/// applies below it and wins over those of its parents, which win over
+/// `info/exclude` and then the user's excludes file. Within a file the
+/// last matching pattern decides, and nothing inside an ignored directory
+/// can be brought back.
+pub struct IgnoreRules {
+    root: PathBuf,
+    /// `.gitignore` patterns by directory, read when first needed
+    per_dir: HashMap<String, Vec<Pattern>>,
+    /// `info/exclude`, then the user's excludes file
+    global: Vec<Vec<Pattern>>,
+}
+
+impl IgnoreRules {
+    pub fn new(
+        root: &Path,
+        git_path: &Path,
+        excludes_file: Option<&str>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut global = vec![read_patterns(&git_path.join("info").join("exclude"))?];
+        if let Some(path) = excludes_file_path(excludes_file) {
+            global.push(read_patterns(&path)?);
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            per_dir: HashMap::new(),
+            global,
+        })
+    }
+
+    /// Whether the root-relative `path` is ignored, itself or through one
+    /// of its parent directories.
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> Result<bool, anyhow::Error> {
+        let mut end = 0;
+        while let Some(slash) = path[end..].find('/') {
+            end += slash;
+            if self.last_match(&path[..end], true)? == Some(true) {
+                return Ok(true);
+            }
+            end += 1;
+        }
+        Ok(self.last_match(path, is_dir)? == Some(true))
+    }
+
+    /// Whether the pattern deciding for `path` ignores it, if any does.
+    fn last_match(&mut self, path: &str, is_dir: bool) -> Result<Option<bool>, anyhow::Error> {
+        let mut dirs = vec![""];
+        dirs.extend(path.match_indices('/').map(|(i, _)| &path[..i]));
+
+        for dir in &dirs {
+            if !self.per_dir.contains_key(*dir) {
+                let patterns = read_patterns(&self.root.join(dir).join(".gitignore"))?;
+                self.per_dir.insert(dir.to_string(), patterns);
+            }
+        }
+
+        let per_dir = dirs.iter().rev().map(|dir| {
+            let relative = if dir.is_empty() {
+                path
+            } else {
+                &path[dir.len() + 1..]
+            };
+            (&self.per_dir[*dir], relative)
+        });
+        let global = self.global.iter().map(|patterns| (patterns, path));
+
+        for (patterns, relative) in per_dir.chain(global) {
+            if let Some(pattern) = patterns
+                .iter()
+                .rev()
+                .find(|pattern| pattern.matches(relative, is_dir))
+            {
+                return Ok(Some(!pattern.negated));
+            }
+        }
+        Ok(None)
+    }
+}
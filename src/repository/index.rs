@@ -1,8 +1,6 @@
-use lexical_sort::natural_lexical_cmp;
-use std::cmp::Ordering;
 use std::collections::BTreeMap;
-use std::fs::{File, Metadata};
-use std::io::ErrorKind;
+use std::fs::{self, Metadata};
+use std::io::{Cursor, ErrorKind, Read, Write};
 use std::os::unix::fs::MetadataExt;
 use std::{ffi::CString, path::PathBuf};
 
@@ -11,69 +9,242 @@ use crate::lockfile::Lockfile;
 use crate::oid::Oid;
 use anyhow;
 
+use super::cache_tree::CacheTree;
+use super::db::Db;
+use super::resolve_undo::ResolveUndo;
+
 const ENTRY_BLOCK: usize = 8;
-const ENTRY_MIN_SIZE: usize = 64;
+const ENTRY_HEADER_SIZE: usize = 62;
 const EXECUTABLE_MODE: u32 = 0o100755;
 const HEADER_SIZE: usize = 12;
 const MAX_PATH_SIZE: usize = 0xFFF;
 const REGULAR_MODE: u32 = 0o100644;
 const SIGNATURE: &[u8] = b"DIRC";
-const VERSION: u32 = 2;
+const MIN_VERSION: u32 = 2;
+const MAX_VERSION: u32 = 4;
+const CHECKSUM_SIZE: usize = 20;
+
+// Extension signatures
+const CACHE_TREE: &[u8] = b"TREE";
+const RESOLVE_UNDO: &[u8] = b"REUC";
+// These record offsets into the file they were read from, so they are
+// dropped rather than carried over to a rewritten index.
+const END_OF_INDEX_ENTRIES: &[u8] = b"EOIE";
+const INDEX_ENTRY_OFFSET_TABLE: &[u8] = b"IEOT";
+
+// Bits of the entry flags
+const ASSUME_VALID: u16 = 0x8000;
+const EXTENDED: u16 = 0x4000;
+
+// Bits of the extended flags stored by version 3 and later
+const SKIP_WORKTREE: u16 = 0x4000;
+const INTENT_TO_ADD: u16 = 0x2000;
+const EXTENDED_FLAGS: u16 = SKIP_WORKTREE | INTENT_TO_ADD;
+
+/// Writes `value` in the variable-length offset encoding used by index
+/// version 4: seven bits per byte, most significant first, with each
+/// continuation byte implicitly adding one.
+fn encode_varint(mut value: usize, bytes: &mut Vec<u8>) {
+    let mut encoded = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        encoded.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.extend(encoded.iter().rev());
+}
+
+/// The stat data the index records for a file, at the width the system
+/// reports it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileStat {
+    pub ctime: i64,
+    pub ctime_nsec: i64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+}
+
+impl From<&Metadata> for FileStat {
+    fn from(metadata: &Metadata) -> Self {
+        Self {
+            ctime: metadata.ctime(),
+            ctime_nsec: metadata.ctime_nsec(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size(),
+        }
+    }
+}
 
+/// An index entry. Like Git, the index keeps only the low 32 bits of each
+/// stat field: times are unsigned seconds, good until 2106, and the size of
+/// a file over 4 GiB is stored modulo 2^32. That is enough to notice a
+/// change, and a stored size of 0 for a non-empty file makes the content
+/// be compared instead.
 #[derive(Debug)]
 pub struct IndexEntry {
-    ctime: i32,
+    ctime: u32,
     ctime_nsec: u32,
-    mtime: i32,
+    mtime: u32,
     mtime_nsec: u32,
     dev: u32,
     ino: u32,
-    mode: u32,
+    pub mode: u32,
     uid: u32,
     gid: u32,
     size: u32,
     pub oid: Oid,
     flags: u16,
+    extended_flags: u16,
     pub path: CString,
 }
 
 impl IndexEntry {
     pub fn new(pathname: PathBuf, oid: Oid, metadata: Metadata) -> Result<Self, anyhow::Error> {
-        let ctime = metadata.ctime() as i32;
-        let ctime_nsec = metadata.ctime_nsec() as u32;
-        let mtime = metadata.mtime() as i32;
-        let mtime_nsec = metadata.mtime_nsec() as u32;
-        let dev = metadata.dev() as u32;
-        let ino = metadata.ino() as u32;
-        let mode = if metadata.mode() & 0o111 != 0 {
-            EXECUTABLE_MODE
-        } else {
-            REGULAR_MODE
-        };
-        let uid = metadata.uid();
-        let gid = metadata.gid();
-        let size = metadata.size() as u32;
-        let path = CString::new(pathname.to_str().unwrap())?;
+        let mut entry = IndexEntry::from_object(
+            pathname.to_str().unwrap(),
+            oid,
+            IndexEntry::mode_for(&metadata),
+        )?;
+        entry.set_stat(&FileStat::from(&metadata));
+        Ok(entry)
+    }
+
+    /// An entry for content that is not (yet) in the work tree, such as one
+    /// read from a tree. Its stat data is zero, so it never looks clean.
+    pub fn from_object(pathname: &str, oid: Oid, mode: u32) -> Result<Self, anyhow::Error> {
+        let path = CString::new(pathname)?;
         let flags = std::cmp::min(path.as_bytes().len(), MAX_PATH_SIZE) as u16;
 
         Ok(IndexEntry {
-            ctime,
-            ctime_nsec,
-            mtime,
-            mtime_nsec,
-            dev,
-            ino,
+            ctime: 0,
+            ctime_nsec: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            dev: 0,
+            ino: 0,
             mode,
-            uid,
-            gid,
-            size,
+            uid: 0,
+            gid: 0,
+            size: 0,
             oid,
             flags,
+            extended_flags: 0,
             path,
         })
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// Records the stat data of the file just written or verified for this
+    /// entry, keeping its mode and object ID.
+    pub fn update_stat(&mut self, metadata: &Metadata) {
+        self.set_stat(&FileStat::from(metadata));
+    }
+
+    /// Stores `stat`, deliberately truncating every field to 32 bits.
+    fn set_stat(&mut self, stat: &FileStat) {
+        self.ctime = stat.ctime as u32;
+        self.ctime_nsec = stat.ctime_nsec as u32;
+        self.mtime = stat.mtime as u32;
+        self.mtime_nsec = stat.mtime_nsec as u32;
+        self.dev = stat.dev as u32;
+        self.ino = stat.ino as u32;
+        self.uid = stat.uid;
+        self.gid = stat.gid;
+        self.size = stat.size as u32;
+    }
+
+    /// The merge stage recorded in the flags; 0 for a normal entry, and 1,
+    /// 2 or 3 for the base, ours and theirs sides of a conflict.
+    pub fn stage(&self) -> u16 {
+        (self.flags >> 12) & 0b11
+    }
+
+    pub fn set_stage(&mut self, stage: u16) {
+        self.flags = (self.flags & !0x3000) | ((stage & 0b11) << 12);
+    }
+
+    /// Set by `update-index --assume-unchanged`: the work tree file is
+    /// taken to match the entry without looking at it.
+    pub fn assume_valid(&self) -> bool {
+        self.flags & ASSUME_VALID != 0
+    }
+
+    pub fn set_assume_valid(&mut self, value: bool) {
+        if value {
+            self.flags |= ASSUME_VALID;
+        } else {
+            self.flags &= !ASSUME_VALID;
+        }
+    }
+
+    /// Set for paths a sparse checkout leaves out of the work tree.
+    pub fn skip_worktree(&self) -> bool {
+        self.extended_flags & SKIP_WORKTREE != 0
+    }
+
+    pub fn set_skip_worktree(&mut self, value: bool) {
+        if value {
+            self.extended_flags |= SKIP_WORKTREE;
+        } else {
+            self.extended_flags &= !SKIP_WORKTREE;
+        }
+    }
+
+    /// Set by `git add -N` for a path that will be added but has no staged
+    /// content yet; such entries are left out of trees written from the
+    /// index.
+    pub fn intent_to_add(&self) -> bool {
+        self.extended_flags & INTENT_TO_ADD != 0
+    }
+
+    /// Whether the file's stat data still matches what was recorded when it
+    /// was added, meaning its content can be assumed unchanged.
+    pub fn stat_matches(&self, metadata: &Metadata) -> bool {
+        self.matches_stat(&FileStat::from(metadata))
+    }
+
+    /// Compares `stat` with the recorded stat data the way it was stored,
+    /// modulo 2^32. A recorded size of 0 only matches an empty file: it is
+    /// also what a file of a multiple of 4 GiB or an entry without stat
+    /// data records, and neither can be trusted without reading it.
+    fn matches_stat(&self, stat: &FileStat) -> bool {
+        if self.size == 0 && stat.size != 0 {
+            return false;
+        }
+
+        self.mode == IndexEntry::mode_from_bits(stat.mode)
+            && self.size == stat.size as u32
+            && self.mtime == stat.mtime as u32
+            && self.mtime_nsec == stat.mtime_nsec as u32
+    }
+
+    pub fn mode_for(metadata: &Metadata) -> u32 {
+        IndexEntry::mode_from_bits(metadata.mode())
+    }
+
+    fn mode_from_bits(mode: u32) -> u32 {
+        if mode & 0o111 != 0 {
+            EXECUTABLE_MODE
+        } else {
+            REGULAR_MODE
+        }
+    }
+
+    /// Serializes the entry for an index of the given `version`. Version 4
+    /// stores the path relative to the `previous` entry's path.
+    pub fn to_bytes(&self, version: u32, previous: &[u8]) -> Vec<u8> {
         let mut bytes = Vec::new();
 
         bytes.extend_from_slice(&self.ctime.to_be_bytes());
@@ -89,26 +260,48 @@ impl IndexEntry {
 
         bytes.extend_from_slice(self.oid.as_bytes());
 
-        bytes.extend_from_slice(&self.flags.to_be_bytes());
+        let mut flags = self.flags & !EXTENDED;
+        if self.extended_flags != 0 {
+            flags |= EXTENDED;
+        }
+        bytes.extend_from_slice(&flags.to_be_bytes());
+        if self.extended_flags != 0 {
+            bytes.extend_from_slice(&self.extended_flags.to_be_bytes());
+        }
 
-        bytes.extend_from_slice(self.path.as_bytes());
-        bytes.push(0);
+        let path = self.path.as_bytes();
 
-        while bytes.len() % 8 != 0 {
+        if version >= 4 {
+            let common = path
+                .iter()
+                .zip(previous)
+                .take_while(|(a, b)| a == b)
+                .count();
+            encode_varint(previous.len() - common, &mut bytes);
+            bytes.extend_from_slice(&path[common..]);
+            bytes.push(0);
+        } else {
+            bytes.extend_from_slice(path);
             bytes.push(0);
+
+            while bytes.len() % ENTRY_BLOCK != 0 {
+                bytes.push(0);
+            }
         }
 
         bytes
     }
 
-    fn parse(data: &[u8]) -> Result<Self, anyhow::Error> {
-        if data.len() < ENTRY_MIN_SIZE {
+    /// Parses the fixed-size part of an entry, up to and including the
+    /// flags; the caller fills in the extended flags and path.
+    fn parse_header(data: &[u8]) -> Result<Self, anyhow::Error> {
+        if data.len() < ENTRY_HEADER_SIZE {
             return Err(anyhow::anyhow!("Entry data too short"));
         }
 
-        let ctime = i32::from_be_bytes(data[0..4].try_into()?);
+        let ctime = u32::from_be_bytes(data[0..4].try_into()?);
         let ctime_nsec = u32::from_be_bytes(data[4..8].try_into()?);
-        let mtime = i32::from_be_bytes(data[8..12].try_into()?);
+        let mtime = u32::from_be_bytes(data[8..12].try_into()?);
         let mtime_nsec = u32::from_be_bytes(data[12..16].try_into()?);
         let dev = u32::from_be_bytes(data[16..20].try_into()?);
         let ino = u32::from_be_bytes(data[20..24].try_into()?);
@@ -123,13 +316,6 @@ impl IndexEntry {
 
         let flags = u16::from_be_bytes(data[60..62].try_into()?);
 
-        let path_end = data[62..]
-            .iter()
-            .position(|&b| b == 0)
-            .ok_or_else(|| anyhow::anyhow!("Path not null-terminated"))?;
-
-        let path = CString::new(&data[62..62 + path_end])?;
-
         Ok(IndexEntry {
             ctime,
             ctime_nsec,
@@ -143,33 +329,23 @@ impl IndexEntry {
             size,
             oid,
             flags,
-            path,
+            extended_flags: 0,
+            path: CString::default(),
         })
     }
 }
 
-#[derive(Eq, PartialEq)]
-pub struct NaturalCString(CString);
-
-impl PartialOrd for NaturalCString {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-impl Ord for NaturalCString {
-    fn cmp(&self, other: &Self) -> Ordering {
-        natural_lexical_cmp(
-            self.0.to_str().unwrap_or_default(),
-            other.0.to_str().unwrap_or_default(),
-        )
-    }
-}
-
 pub struct Index {
     lockfile: Lockfile,
     changed: bool,
-    pub entries: BTreeMap<NaturalCString, IndexEntry>,
+    version: u32,
+    /// Entries keyed by path and stage; Git requires them sorted bytewise
+    /// by path, then by stage
+    pub entries: BTreeMap<(CString, u16), IndexEntry>,
+    cache_tree: Option<CacheTree>,
+    resolve_undo: ResolveUndo,
+    /// Optional extensions we do not interpret, written back unchanged
+    extensions: Vec<([u8; 4], Vec<u8>)>,
 }
 
 impl Index {
@@ -177,12 +353,17 @@ impl Index {
         Index {
             lockfile: Lockfile::new(root_path.join("index")),
             changed: false,
+            version: MIN_VERSION,
             entries: BTreeMap::new(),
+            cache_tree: None,
+            resolve_undo: ResolveUndo::default(),
+            extensions: Vec::new(),
         }
     }
-    fn open_index_file(&self) -> Result<Option<File>, anyhow::Error> {
-        match File::open(&self.lockfile.file_path) {
-            Ok(file) => Ok(Some(file)),
+    /// Reads the whole index file into memory, if there is one.
+    fn open_index_file(&self) -> Result<Option<Cursor<Vec<u8>>>, anyhow::Error> {
+        match fs::read(&self.lockfile.file_path) {
+            Ok(data) => Ok(Some(Cursor::new(data))),
             Err(err) => {
                 if err.kind() == ErrorKind::NotFound {
                     Ok(None)
@@ -193,7 +374,10 @@ impl Index {
         }
     }
 
-    fn read_header(&self, reader: &mut Checksum<File>) -> Result<u32, anyhow::Error> {
+    fn read_header<R: Read + Write>(
+        &mut self,
+        reader: &mut Checksum<R>,
+    ) -> Result<u32, anyhow::Error> {
         let data = reader.read(HEADER_SIZE)?;
 
         let signature = &data[0..4];
@@ -205,49 +389,142 @@ impl Index {
         }
 
         let version = u32::from_be_bytes(data[4..8].try_into()?);
-        if version != VERSION {
+        if !(MIN_VERSION..=MAX_VERSION).contains(&version) {
             return Err(anyhow::anyhow!(
-                "Version: expected '{}' but found '{}'",
-                VERSION,
+                "Version: expected {}..{} but found '{}'",
+                MIN_VERSION,
+                MAX_VERSION,
                 version
             ));
         }
+        self.version = version;
 
         let count = u32::from_be_bytes(data[8..12].try_into()?);
 
         Ok(count)
     }
 
-    fn read_entry(&self, reader: &mut Checksum<File>) -> Result<IndexEntry, anyhow::Error> {
-        let mut entry_data = reader.read(ENTRY_MIN_SIZE)?;
+    fn read_entry<R: Read + Write>(
+        &self,
+        reader: &mut Checksum<R>,
+        previous: &[u8],
+    ) -> Result<IndexEntry, anyhow::Error> {
+        let mut entry = IndexEntry::parse_header(&reader.read(ENTRY_HEADER_SIZE)?)?;
+        let mut entry_size = ENTRY_HEADER_SIZE;
+
+        if entry.flags & EXTENDED != 0 {
+            if self.version < 3 {
+                return Err(anyhow::anyhow!(
+                    "index version {} does not support extended flags",
+                    self.version
+                ));
+            }
+            let data = reader.read(2)?;
+            entry.extended_flags = u16::from_be_bytes([data[0], data[1]]);
+            if entry.extended_flags & !EXTENDED_FLAGS != 0 {
+                return Err(anyhow::anyhow!(
+                    "unknown index entry format 0x{:04x}",
+                    entry.extended_flags
+                ));
+            }
+            entry_size += 2;
+        }
+
+        let mut path = Vec::new();
 
-        while entry_data.last() != Some(&0) {
-            entry_data.extend(reader.read(ENTRY_BLOCK)?);
+        if self.version >= 4 {
+            let mut byte = reader.read(1)?[0];
+            let mut strip = (byte & 0x7f) as usize;
+            while byte & 0x80 != 0 {
+                byte = reader.read(1)?[0];
+                strip = ((strip + 1) << 7) | (byte & 0x7f) as usize;
+            }
+
+            let keep = previous.len().checked_sub(strip).ok_or_else(|| {
+                anyhow::anyhow!("malformed name field in the index, near path '{}'", {
+                    String::from_utf8_lossy(previous)
+                })
+            })?;
+            path.extend_from_slice(&previous[..keep]);
+        }
+
+        loop {
+            let byte = reader.read(1)?[0];
+            entry_size += 1;
+            if byte == 0 {
+                break;
+            }
+            path.push(byte);
+        }
+
+        if self.version < 4 {
+            let padding = (ENTRY_BLOCK - entry_size % ENTRY_BLOCK) % ENTRY_BLOCK;
+            reader.read(padding)?;
         }
 
-        IndexEntry::parse(&entry_data)
+        entry.path = CString::new(path)?;
+        Ok(entry)
     }
 
-    fn read_entries(
+    fn read_entries<R: Read + Write>(
         &mut self,
-        reader: &mut Checksum<File>,
+        reader: &mut Checksum<R>,
         count: u32,
     ) -> Result<(), anyhow::Error> {
+        let mut previous = Vec::new();
+
         for _ in 0..count {
-            let entry = self.read_entry(reader)?;
+            let entry = self.read_entry(reader, &previous)?;
+            previous = entry.path.as_bytes().to_vec();
             self.entries
-                .insert(NaturalCString(entry.path.clone()), entry);
+                .insert((entry.path.clone(), entry.stage()), entry);
+        }
+        Ok(())
+    }
+
+    /// Reads the extensions between the entries and the checksum. Unknown
+    /// extensions whose signature starts with an uppercase letter are
+    /// optional and kept as they are; any other one changes the meaning
+    /// of the index, so it cannot be read without understanding it.
+    fn read_extensions(
+        &mut self,
+        reader: &mut Checksum<Cursor<Vec<u8>>>,
+    ) -> Result<(), anyhow::Error> {
+        while reader.remaining() > CHECKSUM_SIZE {
+            let header = reader.read(8)?;
+            let signature: [u8; 4] = header[0..4].try_into()?;
+            let size = u32::from_be_bytes(header[4..8].try_into()?) as usize;
+            let data = reader.read(size)?;
+
+            match &signature[..] {
+                CACHE_TREE => self.cache_tree = Some(CacheTree::parse(&data)?),
+                RESOLVE_UNDO => self.resolve_undo = ResolveUndo::parse(&data)?,
+                END_OF_INDEX_ENTRIES | INDEX_ENTRY_OFFSET_TABLE => {}
+                _ if signature[0].is_ascii_uppercase() => self.extensions.push((signature, data)),
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "index uses {} extension, which we do not understand",
+                        String::from_utf8_lossy(&signature)
+                    ))
+                }
+            }
         }
         Ok(())
     }
 
     pub fn load(&mut self) -> Result<(), anyhow::Error> {
+        self.entries.clear();
+        self.cache_tree = None;
+        self.resolve_undo.clear();
+        self.extensions.clear();
+
         let file = self.open_index_file()?;
 
         if let Some(file) = file {
             let mut rdr = Checksum::new(file);
             let count = self.read_header(&mut rdr)?;
             self.read_entries(&mut rdr, count)?;
+            self.read_extensions(&mut rdr)?;
             rdr.verify_checksum()?;
             return Ok(());
         }
@@ -255,6 +532,21 @@ impl Index {
         Ok(())
     }
 
+    /// Switches the on-disk format written by the next update.
+    pub fn set_version(&mut self, version: u32) -> Result<(), anyhow::Error> {
+        if !(MIN_VERSION..=MAX_VERSION).contains(&version) {
+            return Err(anyhow::anyhow!(
+                "index-version {} not in range: {}..{}",
+                version,
+                MIN_VERSION,
+                MAX_VERSION
+            ));
+        }
+        self.version = version;
+        self.changed = true;
+        Ok(())
+    }
+
     pub fn load_for_update(&mut self) -> Result<bool, anyhow::Error> {
         if !self.lockfile.hold_for_update()? {
             return Ok(false);
@@ -269,36 +561,172 @@ impl Index {
         oid: Oid,
         stat: std::fs::Metadata,
     ) -> Result<(), anyhow::Error> {
-        self.entries.insert(
-            NaturalCString(CString::new(path.to_str().unwrap())?),
-            IndexEntry::new(path, oid, stat)?,
-        );
-        self.changed = true;
+        let entry = IndexEntry::new(path, oid, stat)?;
+        self.insert(entry);
         Ok(())
     }
 
+    /// Adds or replaces an entry. A stage 0 entry resolves any conflict
+    /// on its path, and a conflict stage replaces a stage 0 entry.
+    pub fn insert(&mut self, entry: IndexEntry) {
+        let path = entry.path.clone();
+        self.invalidate_cache_tree(path.as_bytes());
+
+        if entry.stage() == 0 {
+            self.remove_conflict(&path);
+        } else {
+            self.entries.remove(&(path.clone(), 0));
+        }
+        self.entries.insert((path, entry.stage()), entry);
+        self.changed = true;
+    }
+
+    /// Removes every stage of `path`, returning whether there was any.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let Ok(path) = CString::new(path) else {
+            return false;
+        };
+        let removed =
+            self.entries.remove(&(path.clone(), 0)).is_some() | self.remove_conflict(&path);
+        if removed {
+            self.invalidate_cache_tree(path.as_bytes());
+            self.changed = true;
+        }
+        removed
+    }
+
+    /// Drops the conflict stages of `path`, remembering them in the
+    /// resolve-undo extension.
+    fn remove_conflict(&mut self, path: &CString) -> bool {
+        let stages = [1, 2, 3].map(|stage| {
+            self.entries
+                .remove(&(path.clone(), stage))
+                .map(|entry| (entry.mode, entry.oid))
+        });
+        if stages.iter().all(Option::is_none) {
+            return false;
+        }
+        self.resolve_undo.record(path.clone(), stages);
+        true
+    }
+
+    /// The stage 0 entry for `path`; a conflicted path has none.
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        CString::new(path)
+            .ok()
+            .and_then(|path| self.entries.get(&(path, 0)))
+    }
+
+    /// Looks up an entry for modification. The cached trees containing it
+    /// are invalidated, as the caller may change its object ID or mode.
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut IndexEntry> {
+        self.changed = true;
+        self.invalidate_cache_tree(path.as_bytes());
+        CString::new(path)
+            .ok()
+            .and_then(|path| self.entries.get_mut(&(path, 0)))
+    }
+
+    /// Whether `path` is in the index at any stage.
+    pub fn contains(&self, path: &str) -> bool {
+        self.get(path).is_some() || self.is_conflicted(path)
+    }
+
+    pub fn is_conflicted(&self, path: &str) -> bool {
+        CString::new(path).is_ok_and(|path| {
+            (1..=3).any(|stage| self.entries.contains_key(&(path.clone(), stage)))
+        })
+    }
+
+    /// The entries of conflicted paths, in index order.
+    pub fn conflicts(&self) -> impl Iterator<Item = &IndexEntry> {
+        self.entries.values().filter(|entry| entry.stage() > 0)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.cache_tree = None;
+        self.resolve_undo.clear();
+        self.changed = true;
+    }
+
+    /// Forgets the recorded conflicts of resolved paths, as Git does when
+    /// a merge of trees replaces the index.
+    pub fn clear_resolve_undo(&mut self) {
+        if !self.resolve_undo.is_empty() {
+            self.resolve_undo.clear();
+            self.changed = true;
+        }
+    }
+
+    fn invalidate_cache_tree(&mut self, path: &[u8]) {
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate(path);
+        }
+    }
+
+    /// Stores the tree described by the index and returns its ID. Only the
+    /// directories the cache-tree does not already know are hashed; the
+    /// updated cache-tree is saved by the next `write_updates`. The index
+    /// must not have conflicts.
+    pub fn write_tree(&mut self, db: &Db) -> Result<Oid, anyhow::Error> {
+        if self.conflicts().next().is_some() {
+            return Err(anyhow::anyhow!("cannot write a tree with unmerged entries"));
+        }
+        let entries: Vec<&IndexEntry> = self.entries.values().collect();
+        let cache_tree = self.cache_tree.get_or_insert_with(CacheTree::new);
+        let (oid, _) = cache_tree.update(&entries, 0, db)?;
+        self.changed = true;
+        Ok(oid)
+    }
+
     pub fn write_updates(&mut self) -> Result<bool, anyhow::Error> {
         if !self.changed {
             self.lockfile.rollback()?;
+            return Ok(false);
         }
 
         let mut writer = Checksum::new(self.lockfile.lock.as_ref().unwrap());
 
         let entries_len = self.entries.len() as u32;
         let dirc = b"DIRC";
-        let version: u32 = 2;
+
+        // Like Git, use version 3 only when some entry needs extended flags
+        let extended = self.entries.values().any(|entry| entry.extended_flags != 0);
+        if self.version < 4 {
+            self.version = if extended { 3 } else { 2 };
+        }
 
         let header = [
             &dirc[..],
-            &version.to_be_bytes(),
+            &self.version.to_be_bytes(),
             &entries_len.to_be_bytes(),
         ]
         .concat();
 
         writer.write(&header)?;
 
+        let mut previous: &[u8] = &[];
         for entry in self.entries.values() {
-            writer.write(&entry.to_bytes())?;
+            writer.write(&entry.to_bytes(self.version, previous))?;
+            previous = entry.path.as_bytes();
+        }
+
+        let mut extensions = Vec::new();
+        if let Some(cache_tree) = &self.cache_tree {
+            extensions.push((CACHE_TREE, cache_tree.to_bytes()));
+        }
+        if !self.resolve_undo.is_empty() {
+            extensions.push((RESOLVE_UNDO, self.resolve_undo.to_bytes()));
+        }
+        for (signature, data) in &self.extensions {
+            extensions.push((&signature[..], data.clone()));
+        }
+
+        for (signature, data) in extensions {
+            writer.write(signature)?;
+            writer.write(&(data.len() as u32).to_be_bytes())?;
+            writer.write(&data)?;
         }
 
         writer.write_checksum()?;
@@ -308,3 +736,96 @@ impl Index {
         Ok(true)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1 << 30;
+    // 2040-01-01, past the end of signed 32-bit time
+    const AFTER_2038: i64 = 2_208_988_800;
+
+    fn stat(size: u64, mtime: i64) -> FileStat {
+        FileStat {
+            ctime: mtime,
+            ctime_nsec: 500,
+            mtime,
+            mtime_nsec: 500,
+            dev: (1 << 40) + 7,
+            ino: (1 << 33) + 42,
+            mode: 0o100644,
+            uid: 1000,
+            gid: 1000,
+            size,
+        }
+    }
+
+    fn entry_for(stat: &FileStat) -> IndexEntry {
+        let mut entry = IndexEntry::from_object("file", Oid::zero(), REGULAR_MODE).unwrap();
+        entry.set_stat(stat);
+        entry
+    }
+
+    fn round_trip(entry: &IndexEntry) -> IndexEntry {
+        let bytes = entry.to_bytes(2, b"");
+        IndexEntry::parse_header(&bytes).unwrap()
+    }
+
+    #[test]
+    fn stat_fields_are_truncated_to_32_bits() {
+        let entry = entry_for(&stat(4 * GIB + 10, AFTER_2038));
+
+        assert_eq!(entry.size, 10);
+        assert_eq!(entry.dev, 7);
+        assert_eq!(entry.ino, 42);
+        assert_eq!(entry.mtime, AFTER_2038 as u32);
+    }
+
+    #[test]
+    fn huge_file_matches_its_own_stat_data() {
+        let stat = stat(4 * GIB + 10, 1_700_000_000);
+        let entry = round_trip(&entry_for(&stat));
+
+        assert!(entry.matches_stat(&stat));
+    }
+
+    #[test]
+    fn huge_file_size_change_is_detected() {
+        let entry = entry_for(&stat(4 * GIB + 10, 1_700_000_000));
+
+        assert!(!entry.matches_stat(&stat(4 * GIB + 11, 1_700_000_000)));
+    }
+
+    #[test]
+    fn size_wrapping_to_zero_forces_content_check() {
+        let stat = stat(4 * GIB, 1_700_000_000);
+        let entry = entry_for(&stat);
+
+        assert_eq!(entry.size, 0);
+        assert!(!entry.matches_stat(&stat));
+    }
+
+    #[test]
+    fn empty_file_still_matches() {
+        let stat = stat(0, 1_700_000_000);
+
+        assert!(entry_for(&stat).matches_stat(&stat));
+    }
+
+    #[test]
+    fn timestamps_after_2038_survive_a_round_trip() {
+        let stat = stat(10, AFTER_2038);
+        let entry = round_trip(&entry_for(&stat));
+
+        assert_eq!(entry.mtime, AFTER_2038 as u32);
+        assert_eq!(entry.ctime, AFTER_2038 as u32);
+        assert!(entry.matches_stat(&stat));
+    }
+
+    #[test]
+    fn modification_after_2038_is_detected() {
+        let entry = entry_for(&stat(10, AFTER_2038));
+
+        assert!(!entry.matches_stat(&stat(10, AFTER_2038 + 1)));
+    }
+}
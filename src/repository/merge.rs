@@ -0,0 +1,734 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use crate::merge_file::{is_binary, merge_file, ConflictStyle, MergeOptions};
+use crate::oid::Oid;
+
+use super::diff_tree::{compare_files, Status};
+use super::graph::merge_bases;
+use super::index::IndexEntry;
+use super::object::{blob::Blob, commit::Commit};
+use super::read_tree::{union_paths, Files};
+use super::rename::{detect_renames, Detect, RenameOptions};
+use super::{work_tree, Repository};
+
+const MERGE_HEAD: &str = "MERGE_HEAD";
+const MERGE_MSG: &str = "MERGE_MSG";
+const MERGE_MODE: &str = "MERGE_MODE";
+
+/// Names for the sides of a merge in conflict markers.
+struct MergeLabels {
+    ours: String,
+    base: String,
+    theirs: String,
+}
+
+impl MergeLabels {
+    /// Labels from `names`, for ours, the base and theirs, with the path on
+    /// each side when a rename made them differ.
+    fn new(names: [&str; 3], paths: Option<&[String; 3]>) -> Self {
+        let [ours, base, theirs] = match paths {
+            Some(paths) => [0, 1, 2].map(|i| format!("{}:{}", names[i], paths[i])),
+            None => names.map(str::to_string),
+        };
+        Self { ours, base, theirs }
+    }
+}
+
+/// A path a merge reached by following a rename from `old`.
+enum Renamed {
+    /// The file was renamed on one or both sides and merged at its new
+    /// path, where `paths` are its paths in ours, the base and theirs.
+    Followed { paths: [String; 3] },
+    /// Their side renamed a file ours modified; the path-by-path merge
+    /// leaves our version at the old path, to be merged here.
+    Modified { old: String },
+    /// One side renamed a file the other deleted.
+    Deleted { old: String, by_us: bool },
+}
+
+/// Whether `merge` may, must or must not fast-forward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FastForward {
+    Allow,
+    Never,
+    Only,
+}
+
+/// `message` followed by the `# Conflicts:` comment listing `paths`, for
+/// MERGE_MSG.
+pub(super) fn conflicts_message(message: &str, paths: &[String]) -> String {
+    let mut result = format!("{}\n\n# Conflicts:\n", message.trim_end());
+    for path in paths {
+        result.push_str(&format!("#\t{}\n", path));
+    }
+    result
+}
+
+/// Cleans up a message the way Git does before committing it: the `#`
+/// comment lines Git adds go, along with trailing whitespace, and runs of
+/// blank lines are squeezed into one, dropping those at the start and end.
+pub(super) fn strip_comments(message: &str) -> String {
+    let mut result = String::new();
+    let mut blank = false;
+
+    for line in message.lines().filter(|line| !line.starts_with('#')) {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && !result.is_empty() {
+            result.push('\n');
+        }
+        blank = false;
+        result.push_str(line);
+        result.push('\n');
+    }
+
+    result
+}
+
+impl Repository {
+    /// The commit being merged, while a conflicted merge waits to be
+    /// concluded.
+    pub(super) fn merge_head(&self) -> Result<Option<Oid>, anyhow::Error> {
+        match fs::read_to_string(self.git_path.join(MERGE_HEAD)) {
+            Ok(content) => Oid::parse(content.trim())
+                .map(Some)
+                .ok_or_else(|| anyhow::anyhow!("invalid {}: {}", MERGE_HEAD, content.trim())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(super) fn clear_merge_state(&self) -> Result<(), anyhow::Error> {
+        for name in [MERGE_HEAD, MERGE_MSG, MERGE_MODE] {
+            match fs::remove_file(self.git_path.join(name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn commit_files(&self, commit: &Oid) -> Result<Files, anyhow::Error> {
+        self.read_tree_files(&commit.to_string())
+    }
+
+    /// Git's default message for merging `rev` into the current branch.
+    fn merge_message(&self, rev: &str) -> Result<String, anyhow::Error> {
+        let name = self.refs.expand_ref(rev)?.unwrap_or_default();
+
+        let mut message = if let Some(branch) = name.strip_prefix("refs/heads/") {
+            format!("Merge branch '{}'", branch)
+        } else if let Some(branch) = name.strip_prefix("refs/remotes/") {
+            format!("Merge remote-tracking branch '{}'", branch)
+        } else if let Some(tag) = name.strip_prefix("refs/tags/") {
+            format!("Merge tag '{}'", tag)
+        } else {
+            format!("Merge commit '{}'", rev)
+        };
+
+        if let Some(branch) = self.refs.current_ref()?.strip_prefix("refs/heads/") {
+            if branch != "main" && branch != "master" {
+                message.push_str(&format!(" into {}", branch));
+            }
+        }
+
+        Ok(message)
+    }
+
+    fn write_orig_head(&self, head: &Oid) -> Result<(), anyhow::Error> {
+        self.refs
+            .update_ref("ORIG_HEAD", head, None, &self.committer(), "merge")
+    }
+
+    /// The conflict style set by `merge.conflictStyle`.
+    pub fn conflict_style(&self) -> Result<ConflictStyle, anyhow::Error> {
+        match self.config.get("merge.conflictStyle") {
+            None => Ok(ConflictStyle::default()),
+            Some(name) => ConflictStyle::parse(name).ok_or_else(|| {
+                anyhow::anyhow!("unknown style '{}' given for 'merge.conflictstyle'", name)
+            }),
+        }
+    }
+
+    /// Merges the contents of a file both sides changed, writing the result
+    /// to the work tree. A clean result is staged and `true` returned;
+    /// otherwise the conflict stages stay and the work tree file has
+    /// conflict markers. Binary files, files that are not regular and mode
+    /// changes that conflict are left as they are.
+    fn merge_contents(
+        &mut self,
+        path: &str,
+        base: Option<(Oid, u32)>,
+        ours: (Oid, u32),
+        theirs: (Oid, u32),
+        labels: &MergeLabels,
+    ) -> Result<bool, anyhow::Error> {
+        let mode = match base {
+            _ if ours.1 == theirs.1 => ours.1,
+            Some((_, mode)) if mode == ours.1 => theirs.1,
+            Some((_, mode)) if mode == theirs.1 => ours.1,
+            _ => return Ok(false),
+        };
+        if mode != 0o100644 && mode != 0o100755 {
+            return Ok(false);
+        }
+
+        let read = |oid: &Oid| -> Result<Vec<u8>, anyhow::Error> { Ok(self.db.read_raw(oid)?.1) };
+        let base_data = match base {
+            Some((oid, _)) => read(&oid)?,
+            None => Vec::new(),
+        };
+        let ours_data = read(&ours.0)?;
+        let theirs_data = read(&theirs.0)?;
+
+        if [&base_data, &ours_data, &theirs_data]
+            .iter()
+            .any(|data| is_binary(data))
+        {
+            println!(
+                "warning: Cannot merge binary files: {} ({} vs. {})",
+                path, labels.ours, labels.theirs
+            );
+            return Ok(false);
+        }
+
+        let options = MergeOptions {
+            style: self.conflict_style()?,
+            ours_label: Some(&labels.ours),
+            base_label: Some(&labels.base),
+            theirs_label: Some(&labels.theirs),
+            ..MergeOptions::default()
+        };
+        let result = merge_file(&base_data, &ours_data, &theirs_data, &options);
+
+        let workspace = work_tree(&self.workspace)?;
+        workspace.write_file(Path::new(path), &result.data, mode)?;
+        if result.conflicts > 0 {
+            return Ok(false);
+        }
+
+        let oid = self.db.store_object(&mut Blob::new(result.data))?;
+        let mut entry = IndexEntry::from_object(path, oid, mode)?;
+        entry.update_stat(&workspace.stat_file(Path::new(path))?);
+        self.index.insert(entry);
+        Ok(true)
+    }
+
+    /// Merges a file their side renamed to `path` with our changes to it
+    /// at `old`. Our version moves to the new path first, so it is what the
+    /// work tree holds if the contents cannot be merged; a conflict leaves
+    /// all three stages at the new path.
+    fn merge_renamed(
+        &mut self,
+        path: &str,
+        old: &str,
+        base: Option<(Oid, u32)>,
+        ours: (Oid, u32),
+        theirs: (Oid, u32),
+        labels: &MergeLabels,
+    ) -> Result<bool, anyhow::Error> {
+        let workspace = work_tree(&self.workspace)?;
+        let (_, data) = self.db.read_raw(&ours.0)?;
+        self.index.remove(old);
+        workspace.remove_file(Path::new(old))?;
+        workspace.write_file(Path::new(path), &data, ours.1)?;
+
+        if self.merge_contents(path, base, ours, theirs, labels)? {
+            return Ok(true);
+        }
+        for (stage, file) in [(1, base), (2, Some(ours)), (3, Some(theirs))] {
+            if let Some((oid, mode)) = file {
+                let mut entry = IndexEntry::from_object(path, oid, mode)?;
+                entry.set_stage(stage);
+                self.index.insert(entry);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The files `side` renamed since `base`, as `(old, new)` paths.
+    fn renames(&self, base: &Files, side: &Files) -> Result<Vec<(String, String)>, anyhow::Error> {
+        let options = RenameOptions {
+            detect: Detect::Renames,
+            rename_empty: false,
+            ..RenameOptions::default()
+        };
+        Ok(
+            detect_renames(&self.db, compare_files(base, side, false), &options)?
+                .into_iter()
+                .filter(|pair| pair.status == Status::Renamed)
+                .map(|pair| (pair.old_path, pair.new_path))
+                .collect(),
+        )
+    }
+
+    /// Lines up files renamed on one side of a merge with the other side's
+    /// version under the old path, so their changes come together at the
+    /// new path. Base and their entries are moved to the new path in
+    /// `trees`; ours cannot be, as the index holds it, so files their side
+    /// renamed and ours modified are left for the caller to merge. Renames
+    /// of files the other side deleted are returned as well.
+    fn follow_renames(
+        &self,
+        trees: &mut [Files; 3],
+    ) -> Result<BTreeMap<String, Renamed>, anyhow::Error> {
+        let [base, ours, theirs] = trees;
+        let our_renames = self.renames(base, ours)?;
+        let their_renames = self.renames(base, theirs)?;
+        let mut renamed = BTreeMap::new();
+
+        for (old, new) in &our_renames {
+            let their_new = their_renames
+                .iter()
+                .find(|(from, _)| from == old)
+                .map(|(_, to)| to);
+            let paths = match (theirs.get(old).copied(), their_new) {
+                (_, Some(to)) if to == new => [new.clone(), old.clone(), new.clone()],
+                (None, None) if !theirs.contains_key(new) => {
+                    renamed.insert(
+                        new.clone(),
+                        Renamed::Deleted {
+                            old: old.clone(),
+                            by_us: true,
+                        },
+                    );
+                    continue;
+                }
+                (Some(file), None) if !theirs.contains_key(new) && Some(&file) != base.get(old) => {
+                    theirs.remove(old);
+                    theirs.insert(new.clone(), file);
+                    [new.clone(), old.clone(), old.clone()]
+                }
+                _ => continue,
+            };
+            if let Some(file) = base.remove(old) {
+                base.insert(new.clone(), file);
+            }
+            renamed.insert(new.clone(), Renamed::Followed { paths });
+        }
+
+        for (old, new) in &their_renames {
+            if our_renames.iter().any(|(from, _)| from == old) || ours.contains_key(new) {
+                continue;
+            }
+            match ours.get(old) {
+                Some(file) if Some(file) != base.get(old) => {
+                    if let Some(file) = base.remove(old) {
+                        base.insert(new.clone(), file);
+                    }
+                    renamed.insert(new.clone(), Renamed::Modified { old: old.clone() });
+                }
+                Some(_) => {}
+                None => {
+                    renamed.insert(
+                        new.clone(),
+                        Renamed::Deleted {
+                            old: old.clone(),
+                            by_us: false,
+                        },
+                    );
+                }
+            }
+        }
+
+        Ok(renamed)
+    }
+
+    /// Refuses to merge `trees` when it would lose local changes or
+    /// overwrite untracked files. Every path the merge may write or remove
+    /// is checked up front, as content merges, renames and modify/delete
+    /// conflicts write files after the index has been merged: those their
+    /// side changed, and both paths of a file their side renamed.
+    fn check_work_tree_for_merge_trees(
+        &self,
+        trees: &[Files; 3],
+        renamed: &BTreeMap<String, Renamed>,
+    ) -> Result<(), anyhow::Error> {
+        let [base, ours, theirs] = trees;
+        let mut touched: Vec<&str> = union_paths(&[base, ours, theirs])
+            .into_iter()
+            .filter(|path| {
+                theirs.get(*path) != ours.get(*path) && theirs.get(*path) != base.get(*path)
+            })
+            .collect();
+        for (path, renamed) in renamed {
+            touched.push(path);
+            if let Renamed::Modified { old } = renamed {
+                touched.push(old);
+            }
+        }
+        touched.sort();
+        touched.dedup();
+
+        let workspace = work_tree(&self.workspace)?;
+        let mut dirty = String::new();
+        let mut untracked = String::new();
+        for path in touched {
+            match self.index.get(path) {
+                Some(entry) => {
+                    if self.file_matches_entry(entry)? == Some(false) {
+                        dirty.push_str(&format!("\t{}\n", path));
+                    }
+                }
+                // A file the index does not know about is only safe to
+                // replace with what it already holds
+                None => {
+                    let Ok(data) = workspace.read_file(Path::new(path)) else {
+                        continue;
+                    };
+                    let wanted = theirs.get(path).map(|(oid, _)| *oid);
+                    if wanted != Some(self.db.hash_object(b"blob", &data, false)?) {
+                        untracked.push_str(&format!("\t{}\n", path));
+                    }
+                }
+            }
+        }
+        if !dirty.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Your local changes to the following files would be overwritten by merge:\n\
+                 {}Please commit your changes or stash them before you merge.\nAborting",
+                dirty
+            ));
+        }
+        if !untracked.is_empty() {
+            return Err(anyhow::anyhow!(
+                "The following untracked working tree files would be overwritten by merge:\n\
+                 {}Please move or remove them before you merge.\nAborting",
+                untracked
+            ));
+        }
+        Ok(())
+    }
+
+    /// Merges `trees`, the base, ours and theirs, into the locked index and
+    /// the work tree, following renames, and writes the index. Ours must be
+    /// what the index holds. Each path that needs merging is reported the
+    /// way `merge` does, with `names` naming the three sides in conflict
+    /// markers and messages. Returns the paths left unresolved.
+    pub(super) fn merge_trees(
+        &mut self,
+        mut trees: [Files; 3],
+        names: [&str; 3],
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let [base_name, ours_name, rev] = names;
+        let renamed = self.follow_renames(&mut trees)?;
+        self.check_work_tree_for_merge_trees(&trees, &renamed)?;
+
+        // Renamed files the path-by-path merge could not bring together
+        // are merged here as well
+        let mut paths = self.merge_into_index(&trees, true, true, true)?;
+        paths.extend(
+            renamed
+                .iter()
+                .filter(|(_, renamed)| !matches!(renamed, Renamed::Followed { .. }))
+                .map(|(path, _)| path.clone()),
+        );
+        paths.sort();
+        paths.dedup();
+        let [base_files, head_files, theirs_files] = &trees;
+
+        let mut unresolved = Vec::new();
+
+        for path in paths {
+            let base_file = base_files.get(&path).copied();
+            let ours_file = head_files.get(&path).copied();
+            let theirs_file = theirs_files.get(&path).copied();
+
+            match (renamed.get(&path), ours_file, theirs_file) {
+                (Some(Renamed::Modified { old }), _, Some(theirs)) => {
+                    let paths = [old.clone(), old.clone(), path.clone()];
+                    let labels = MergeLabels::new([ours_name, base_name, rev], Some(&paths));
+                    if base_file != Some(theirs) {
+                        println!("Auto-merging {}", path);
+                    }
+                    if self.merge_renamed(
+                        &path,
+                        old,
+                        base_file,
+                        head_files[old],
+                        theirs,
+                        &labels,
+                    )? {
+                        continue;
+                    }
+                    println!("CONFLICT (content): Merge conflict in {}", path);
+                }
+                (Some(Renamed::Deleted { old, by_us }), _, _) => {
+                    let (renamed_in, deleted_in, file, stage) = if *by_us {
+                        (ours_name, rev, ours_file, 2)
+                    } else {
+                        (rev, ours_name, theirs_file, 3)
+                    };
+                    for (stage, file) in [(1, base_files.get(old)), (stage, file.as_ref())] {
+                        if let Some((oid, mode)) = file {
+                            let mut entry = IndexEntry::from_object(&path, *oid, *mode)?;
+                            entry.set_stage(stage);
+                            self.index.insert(entry);
+                        }
+                    }
+                    println!(
+                        "CONFLICT (rename/delete): {} renamed to {} in {}, but deleted in {}.",
+                        old, path, renamed_in, deleted_in
+                    );
+                }
+                (_, None, Some((oid, mode))) => {
+                    // Their version is left in the work tree to be resolved
+                    let (_, data) = self.db.read_raw(&oid)?;
+                    work_tree(&self.workspace)?.write_file(Path::new(&path), &data, mode)?;
+                    println!(
+                        "CONFLICT (modify/delete): {} deleted in {} and modified in {}. \
+                         Version {} of {} left in tree.",
+                        path, ours_name, rev, rev, path
+                    );
+                }
+                (_, Some(_), None) => println!(
+                    "CONFLICT (modify/delete): {} deleted in {} and modified in {}. \
+                     Version {} of {} left in tree.",
+                    path, rev, ours_name, ours_name, path
+                ),
+                (renamed, Some(ours), Some(theirs)) => {
+                    let paths = match renamed {
+                        Some(Renamed::Followed { paths }) => Some(paths),
+                        _ => None,
+                    };
+                    let labels = MergeLabels::new([ours_name, base_name, rev], paths);
+                    println!("Auto-merging {}", path);
+                    if self.merge_contents(&path, base_file, ours, theirs, &labels)? {
+                        continue;
+                    }
+                    match base_file {
+                        None => println!("CONFLICT (add/add): Merge conflict in {}", path),
+                        Some(_) => println!("CONFLICT (content): Merge conflict in {}", path),
+                    }
+                }
+                (_, None, None) => {}
+            }
+
+            unresolved.push(path);
+        }
+
+        // Paths merged here were never conflicts anyone resolved
+        self.index.clear_resolve_undo();
+        self.index.write_updates()?;
+        Ok(unresolved)
+    }
+
+    /// Merges the commit `rev` into HEAD, which the index must match. When
+    /// HEAD is an ancestor of it, HEAD is fast-forwarded unless
+    /// `fast_forward` forbids it. Otherwise the trees are merged against the best merge base and, if every path
+    /// resolves, a merge commit with `message` (or Git's default one) is
+    /// made. Unresolved paths are left as conflict stages, with MERGE_HEAD
+    /// and MERGE_MSG recording the merge for `merge_continue`; `false` is
+    /// returned in that case.
+    pub fn merge(
+        &mut self,
+        rev: &str,
+        fast_forward: FastForward,
+        message: Option<&str>,
+    ) -> Result<bool, anyhow::Error> {
+        work_tree(&self.workspace)?;
+        if self.merge_head()?.is_some() {
+            return Err(anyhow::anyhow!(
+                "You have not concluded your merge (MERGE_HEAD exists).\n\
+                 Please, commit your changes before you merge."
+            ));
+        }
+
+        let theirs = self.resolve_commit(rev)?;
+        let head = self.refs.read_ref("HEAD")?;
+        let committer = self.committer();
+
+        self.lock_index()?;
+        if self.index.conflicts().next().is_some() {
+            return Err(anyhow::anyhow!(
+                "Merging is not possible because you have unmerged files."
+            ));
+        }
+
+        let theirs_files = self.commit_files(&theirs)?;
+
+        let Some(head) = head else {
+            // Merging into an unborn branch just checks out the commit
+            self.merge_into_index(&[Files::new(), theirs_files], true, true, false)?;
+            self.index.write_updates()?;
+            self.refs.set_head(
+                &theirs,
+                Some(Oid::zero()),
+                &committer,
+                &format!("merge {}: Fast-forward", rev),
+            )?;
+            return Ok(true);
+        };
+
+        let bases = merge_bases(&self.db, &head, &[theirs])?;
+        if bases.contains(&theirs) {
+            println!("Already up to date.");
+            return Ok(true);
+        }
+
+        let head_files = self.commit_files(&head)?;
+        let index_files = self.index_files()?;
+        if index_files != head_files {
+            let staged: String = union_paths(&[&index_files, &head_files])
+                .into_iter()
+                .filter(|path| index_files.get(*path) != head_files.get(*path))
+                .map(|path| format!("\t{}\n", path))
+                .collect();
+            return Err(anyhow::anyhow!(
+                "Your local changes to the following files would be overwritten by merge:\n\
+                 {}Please commit your changes or stash them before you merge.\nAborting",
+                staged
+            ));
+        }
+
+        if bases == [head] && fast_forward != FastForward::Never {
+            println!("Updating {}..{}", head.to_short(), theirs.to_short());
+            println!("Fast-forward");
+            self.merge_into_index(&[head_files, theirs_files], true, true, false)?;
+            self.index.write_updates()?;
+            self.write_orig_head(&head)?;
+            self.refs.set_head(
+                &theirs,
+                Some(head),
+                &committer,
+                &format!("merge {}: Fast-forward", rev),
+            )?;
+            return Ok(true);
+        }
+
+        if fast_forward == FastForward::Only {
+            return Err(anyhow::anyhow!("Not possible to fast-forward, aborting."));
+        }
+
+        // Like Git's "resolve" strategy, merge against the newest base only
+        let base = bases
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("refusing to merge unrelated histories"))?;
+        let trees = [self.commit_files(base)?, head_files, theirs_files];
+        let unresolved = self.merge_trees(trees, [&base.to_short(), "HEAD", rev])?;
+        self.write_orig_head(&head)?;
+
+        let message = match message {
+            Some(message) => message.to_string(),
+            None => self.merge_message(rev)?,
+        };
+
+        if unresolved.is_empty() {
+            let tree = self.write_tree()?;
+            let mut commit = Commit::new(
+                tree,
+                vec![head, theirs],
+                self.author(),
+                committer.clone(),
+                format!("{}\n", message),
+            );
+            let commit_oid = self.db.store_object(&mut commit)?;
+
+            self.refs.set_head(
+                &commit_oid,
+                Some(head),
+                &committer,
+                &format!("merge {}: Merge made by the 'resolve' strategy.", rev),
+            )?;
+            println!("Merge made by the 'resolve' strategy.");
+            return Ok(true);
+        }
+
+        fs::write(self.git_path.join(MERGE_HEAD), format!("{}\n", theirs))?;
+        fs::write(
+            self.git_path.join(MERGE_MSG),
+            conflicts_message(&message, &unresolved),
+        )?;
+        let mode = if fast_forward == FastForward::Never {
+            "no-ff"
+        } else {
+            ""
+        };
+        fs::write(self.git_path.join(MERGE_MODE), mode)?;
+
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+        Ok(false)
+    }
+
+    /// Concludes a conflicted merge once every path is resolved, committing
+    /// with the message from MERGE_MSG.
+    pub fn merge_continue(&mut self, no_verify: bool) -> Result<(), anyhow::Error> {
+        if self.merge_head()?.is_none() {
+            return Err(anyhow::anyhow!(
+                "There is no merge in progress (MERGE_HEAD missing)."
+            ));
+        }
+
+        let message = fs::read_to_string(self.git_path.join(MERGE_MSG))?;
+        self.commit_with_message(Some(strip_comments(&message)), no_verify)
+    }
+
+    /// Gives up a conflicted merge: every path the merge touched goes back
+    /// to its HEAD version.
+    pub fn merge_abort(&mut self) -> Result<(), anyhow::Error> {
+        if self.merge_head()?.is_none() {
+            return Err(anyhow::anyhow!(
+                "There is no merge to abort (MERGE_HEAD missing)."
+            ));
+        }
+
+        let head = self
+            .refs
+            .read_ref("HEAD")?
+            .ok_or_else(|| anyhow::anyhow!("HEAD does not point at a commit"))?;
+        self.reset_merge(&head)?;
+        self.clear_merge_state()
+    }
+
+    /// Brings the index and the work tree back to `commit`, as `git reset
+    /// --merge` does: every path whose index entry differs from it, or is
+    /// conflicted, gets its version from `commit`, while files whose index
+    /// entry already matches keep their local changes. HEAD is left alone.
+    pub(super) fn reset_merge(&mut self, commit: &Oid) -> Result<(), anyhow::Error> {
+        let files = self.commit_files(commit)?;
+
+        self.lock_index()?;
+
+        let mut paths: Vec<String> = self
+            .index
+            .entries
+            .values()
+            .map(|entry| Ok(entry.path.to_str()?.to_string()))
+            .collect::<Result<_, anyhow::Error>>()?;
+        paths.extend(files.keys().cloned());
+        paths.sort();
+        paths.dedup();
+
+        let workspace = work_tree(&self.workspace)?;
+        for path in paths {
+            let current = self.index.get(&path).map(|entry| (entry.oid, entry.mode));
+            let wanted = files.get(&path).copied();
+            if current == wanted && !self.index.is_conflicted(&path) {
+                continue;
+            }
+
+            match wanted {
+                Some((oid, mode)) => {
+                    let (_, data) = self.db.read_raw(&oid)?;
+                    workspace.write_file(Path::new(&path), &data, mode)?;
+                    let mut entry = IndexEntry::from_object(&path, oid, mode)?;
+                    entry.update_stat(&workspace.stat_file(Path::new(&path))?);
+                    self.index.insert(entry);
+                }
+                None => {
+                    self.index.remove(&path);
+                    match workspace.remove_file(Path::new(&path)) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        self.index.write_updates()?;
+        Ok(())
+    }
+}
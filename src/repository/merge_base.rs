@@ -0,0 +1,98 @@
+use crate::oid::Oid;
+
+use super::graph::{is_ancestor, merge_bases, octopus_merge_bases};
+use super::revision::peel_to;
+use super::Repository;
+
+impl Repository {
+    /// Prints the best common ancestor of the first commit and the others,
+    /// or all of them with `all`. `octopus` looks for the ancestors common
+    /// to every commit instead. Returns `false` when there is none, which
+    /// callers report through the exit status.
+    pub fn merge_base(
+        &self,
+        commits: &[String],
+        all: bool,
+        octopus: bool,
+    ) -> Result<bool, anyhow::Error> {
+        let commits: Vec<Oid> = commits
+            .iter()
+            .map(|commit| self.resolve_commit(commit))
+            .collect::<Result<_, _>>()?;
+
+        let bases = match commits.split_first() {
+            Some(_) if octopus => octopus_merge_bases(&self.db, &commits)?,
+            Some((one, twos)) if !twos.is_empty() => merge_bases(&self.db, one, twos)?,
+            _ => return Err(anyhow::anyhow!("merge-base needs at least two commits")),
+        };
+
+        let shown = if all { bases.len() } else { 1 };
+        for base in bases.iter().take(shown) {
+            println!("{}", base);
+        }
+
+        Ok(!bases.is_empty())
+    }
+
+    /// Whether the commit `ancestor` is an ancestor of `descendant`.
+    pub fn merge_base_is_ancestor(
+        &self,
+        ancestor: &str,
+        descendant: &str,
+    ) -> Result<bool, anyhow::Error> {
+        is_ancestor(
+            &self.db,
+            &self.resolve_commit(ancestor)?,
+            &self.resolve_commit(descendant)?,
+        )
+    }
+
+    /// Prints the point where `commit` (HEAD by default) forked from the
+    /// history of `reference`, taking into account the commits the ref
+    /// pointed at before being rewound or rebased, as its reflog records.
+    /// That is the one merge base of `commit` and all those commits, and it
+    /// must itself be one of them. Returns `false` when there is none.
+    pub fn merge_base_fork_point(
+        &self,
+        reference: &str,
+        commit: Option<&str>,
+    ) -> Result<bool, anyhow::Error> {
+        let name = self
+            .refs
+            .expand_ref(reference)?
+            .ok_or_else(|| anyhow::anyhow!("No such ref: '{}'", reference))?;
+        let commit = self.resolve_commit(commit.unwrap_or("HEAD"))?;
+
+        let entries = self.refs.reflog().read(&name)?;
+        let mut recorded: Vec<Oid> = entries
+            .first()
+            .map(|first| first.old)
+            .into_iter()
+            .chain(entries.iter().map(|entry| entry.new))
+            .collect();
+        if recorded.is_empty() {
+            recorded.extend(self.refs.read_ref(&name)?);
+        }
+
+        let mut history: Vec<Oid> = Vec::new();
+        for oid in recorded {
+            if oid.is_zero() {
+                continue;
+            }
+            let Ok(oid) = peel_to(&self.db, oid, "commit") else {
+                continue;
+            };
+            if !history.contains(&oid) {
+                history.push(oid);
+            }
+        }
+
+        match merge_bases(&self.db, &commit, &history)?.as_slice() {
+            [base] if history.contains(base) => {
+                println!("{}", base);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
@@ -1,140 +1,467 @@
 use std::{
-    env, fs,
+    collections::HashSet,
+    fs,
     io::{self, Read},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use anyhow::Context;
 use chrono::Local;
+use config::Config;
 use db::Db;
-use index::Index;
+use hooks::Hooks;
+use index::{Index, IndexEntry};
 use object::{
     blob::Blob,
     commit::{self, Commit},
-    tree::Tree,
 };
-use refs::Refs;
+use packed_refs::PackedRef;
+use pathspec::Pathspec;
+use refs::{RefTransaction, Refs};
+use revision::{peel_to, ReflogSelector, Revision};
+use walkdir::WalkDir;
 use workspace::Workspace;
 
+use crate::date::parse_approxidate;
+use crate::oid::Oid;
+
+pub mod cache_tree;
+pub mod clean;
+pub mod config;
 pub mod db;
+pub mod diff_tree;
+pub mod discover;
+pub mod for_each_ref;
+pub mod graph;
+pub mod hooks;
+pub mod ignore;
 pub mod index;
+pub mod merge;
+pub mod merge_base;
 pub mod object;
+pub mod packed_refs;
+pub mod pathspec;
+pub mod plumbing;
+pub mod read_tree;
+pub mod rebase;
+pub mod reflog;
 pub mod refs;
+pub mod rename;
+pub mod resolve_undo;
+pub mod restore;
+pub mod revision;
+pub mod sequencer;
+pub mod stash;
+pub mod tag;
 pub mod workspace;
 
-pub struct ConfigUser {
-    pub name: String,
-    pub email: String,
-}
+const DEFAULT_BRANCH: &str = "master";
 
-pub struct Config {
-    pub user: ConfigUser,
-}
+/// Files and directories every repository starts with, as
+/// `(directory, file, content)`. An empty file name only creates the
+/// directory.
+const INIT_FILES: [(&str, &str, &str); 3] = [
+    (
+        "",
+        "description",
+        "Unnamed repository; edit this file 'description' to name the repository.\n",
+    ),
+    (
+        "info",
+        "exclude",
+        "# git ls-files --others --exclude-from=.git/info/exclude\n\
+         # Lines that start with '#' are comments.\n\
+         # For a project mostly in C, the following would be a good set of\n\
+         # exclude patterns (uncomment them if you want to use them):\n\
+         # *.[oa]\n\
+         # *~\n",
+    ),
+    ("hooks", "", ""),
+];
 
-fn env_or_default(key: &str) -> String {
-    env::var_os(key)
-        .map(|var| var.to_string_lossy().to_string())
-        .unwrap_or_default()
-}
+/// Copies the contents of an init template directory into `git_path`,
+/// leaving files that already exist untouched.
+fn copy_template(template: &Path, git_path: &Path) -> Result<(), anyhow::Error> {
+    if !template.is_dir() {
+        eprintln!("warning: templates not found in {}", template.display());
+        return Ok(());
+    }
 
-impl Config {
-    fn from_env() -> Self {
-        let name = env_or_default("GIT_AUTHOR_NAME");
-        let email = env_or_default("GIT_AUTHOR_EMAIL");
+    for entry in WalkDir::new(template).min_depth(1) {
+        let entry = entry?;
+        let target = git_path.join(entry.path().strip_prefix(template)?);
 
-        Self {
-            user: ConfigUser { name, email },
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if !target.exists() {
+            fs::copy(entry.path(), &target)
+                .with_context(|| format!("Could not copy template {:?}", entry.path()))?;
         }
     }
+
+    Ok(())
 }
 
 pub struct Repository {
-    root: PathBuf,
-    workspace: Workspace,
+    git_path: PathBuf,
+    workspace: Option<Workspace>,
     db: Db,
     refs: Refs,
     config: Config,
     index: Index,
 }
 
+fn work_tree(workspace: &Option<Workspace>) -> Result<&Workspace, anyhow::Error> {
+    workspace
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("this operation must be run in a work tree"))
+}
+
 impl Repository {
-    pub fn open(path: PathBuf) -> Self {
-        let workspace_path = path.clone();
-        let root_path = path.join(".git");
+    /// Opens the repository containing `cwd`, searching parent directories.
+    pub fn discover(cwd: &Path) -> Result<Self, anyhow::Error> {
+        let location = discover::discover(cwd)?;
+        Self::open_at(location.work_tree, location.git_path)
+    }
+
+    fn open_at(work_tree: Option<PathBuf>, git_path: PathBuf) -> Result<Self, anyhow::Error> {
+        let config = Config::load(git_path.join("config"))?;
+        let log_all_ref_updates = config
+            .get_bool("core.logAllRefUpdates")
+            .unwrap_or(work_tree.is_some());
+
+        Ok(Self {
+            workspace: work_tree.map(Workspace::new),
+            db: Db::new(git_path.clone()),
+            refs: refs::Refs::new(git_path.clone(), log_all_ref_updates),
+            config,
+            index: Index::new(git_path.clone()),
+            git_path,
+        })
+    }
+
+    fn author(&self) -> commit::Author {
+        let user = &self.config.user;
+        commit::Author::new(user.name.to_owned(), user.email.to_owned(), Local::now())
+    }
+
+    fn committer(&self) -> commit::Author {
+        let user = &self.config.committer;
+        commit::Author::new(user.name.to_owned(), user.email.to_owned(), Local::now())
+    }
+
+    /// Resolves command-line paths given relative to `cwd` into paths
+    /// relative to the work tree root.
+    pub fn workspace_paths(
+        &self,
+        cwd: &Path,
+        paths: &[PathBuf],
+    ) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let workspace = work_tree(&self.workspace)?;
+
+        paths
+            .iter()
+            .map(|path| workspace.relative_path(cwd, path))
+            .collect()
+    }
+
+    /// Hooks run from the top of the work tree, or from the git directory
+    /// in a bare repository.
+    fn hooks(&self) -> Hooks {
+        let work_dir = match &self.workspace {
+            Some(workspace) => workspace.root.clone(),
+            None => self.git_path.clone(),
+        };
+
+        Hooks::new(&self.git_path, work_dir, self.config.get("core.hooksPath"))
+    }
+
+    /// Creates a repository at `path`. A bare repository keeps the object
+    /// database and refs directly in `path` instead of in `path/.git`.
+    pub fn init(
+        path: PathBuf,
+        bare: bool,
+        initial_branch: Option<&str>,
+        template: Option<&Path>,
+    ) -> Result<Self, anyhow::Error> {
+        let (work_tree, git_path) = if bare {
+            (None, path)
+        } else {
+            (Some(path.clone()), path.join(".git"))
+        };
+
+        let reinit = git_path.join("HEAD").exists();
+
+        fs::create_dir_all(&git_path)
+            .with_context(|| format!("Could not create {:?}", git_path))?;
+
+        if let Some(template) = template {
+            copy_template(template, &git_path)?;
+        }
+
+        let mut repo = Self::open_at(work_tree, git_path)?;
+
+        repo.db.init()?;
+
+        let branch = initial_branch.unwrap_or(DEFAULT_BRANCH);
+        if reinit && initial_branch.is_some() {
+            eprintln!("warning: re-init: ignored --initial-branch={}", branch);
+        }
+        repo.refs.init(&format!("refs/heads/{}", branch))?;
+
+        for (dir, file, content) in INIT_FILES {
+            let path = repo.git_path.join(dir);
+            fs::create_dir_all(&path)?;
+            if !file.is_empty() && !path.join(file).exists() {
+                fs::write(path.join(file), content)?;
+            }
+        }
+
+        let defaults = [
+            ("core.repositoryformatversion", "0"),
+            ("core.filemode", "true"),
+            ("core.bare", if bare { "true" } else { "false" }),
+        ];
+        // Saving rewrites the whole file, so leave a complete one alone
+        let mut missing = false;
+        for (key, value) in defaults {
+            if repo.config.get(key).is_none() {
+                repo.config.set(key, value)?;
+                missing = true;
+            }
+        }
+        if missing {
+            repo.config.save()?;
+        }
+
+        println!(
+            "{} Git repository in {}/",
+            if reinit {
+                "Reinitialized existing"
+            } else {
+                "Initialized empty"
+            },
+            repo.git_path.display()
+        );
+
+        Ok(repo)
+    }
+
+    /// Whether the work tree file for `entry` still has the content and
+    /// mode recorded in the index, or `None` when there is no such file.
+    /// Entries marked assume-unchanged or skip-worktree always match.
+    fn file_matches_entry(&self, entry: &IndexEntry) -> Result<Option<bool>, anyhow::Error> {
+        if entry.assume_valid() || entry.skip_worktree() {
+            return Ok(Some(true));
+        }
+        let workspace = work_tree(&self.workspace)?;
+        let path = Path::new(entry.path.to_str()?);
+
+        let metadata = match workspace.stat_file(path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
 
-        Self {
-            root: path,
-            workspace: Workspace::new(workspace_path),
-            db: Db::new(root_path.clone()),
-            refs: refs::Refs::new(root_path.clone()),
-            config: Config::from_env(),
-            index: Index::new(root_path.clone()),
+        if entry.stat_matches(&metadata) {
+            return Ok(Some(true));
         }
+
+        let oid = self
+            .db
+            .hash_object(b"blob", &workspace.read_file(path)?, false)?;
+        Ok(Some(
+            oid == entry.oid && IndexEntry::mode_for(&metadata) == entry.mode,
+        ))
     }
 
-    pub fn init(&self) -> Result<(), io::Error> {
-        fs::create_dir(self.root.join(".git"))?;
-        self.db.init()?;
+    /// Takes the index lock and loads the index for modification.
+    fn lock_index(&mut self) -> Result<(), anyhow::Error> {
+        if !self.index.load_for_update()? {
+            return Err(anyhow::anyhow!(
+                "Unable to create '{}': File exists.",
+                self.git_path.join("index.lock").display()
+            ));
+        }
         Ok(())
     }
 
-    pub fn add(&mut self, paths: &Vec<PathBuf>) -> Result<(), anyhow::Error> {
-        self.index.load_for_update()?;
+    /// Stages the work tree files `paths` select, given relative to `cwd`,
+    /// and drops the selected index entries whose file is gone.
+    pub fn add(&mut self, cwd: &Path, paths: &[PathBuf]) -> Result<(), anyhow::Error> {
+        let pathspec = Pathspec::parse(work_tree(&self.workspace)?, cwd, paths)?;
+        self.lock_index()?;
+        let workspace = work_tree(&self.workspace)?;
 
-        for p in paths {
-            for path in self.workspace.list_files(Some(p))? {
-                let data = self.workspace.read_file(&path)?;
-                let stats = self.workspace.stat_file(&path)?;
+        let base = pathspec.common_prefix();
+        let mut files = Vec::new();
+        if workspace.root.join(&base).is_dir() {
+            files = workspace.list_files(Some(&base))?;
+            files.retain(|path| pathspec.matches(path));
+        }
 
-                let blob_oid = self.db.store_object(&mut Blob::new(data))?;
+        let present: HashSet<&PathBuf> = files.iter().collect();
+        let mut deleted = Vec::new();
+        for entry in self.index.entries.values() {
+            let path = PathBuf::from(entry.path.to_str()?);
+            if pathspec.matches(&path) && !present.contains(&path) {
+                deleted.push(path);
+            }
+        }
+        let matched: Vec<&PathBuf> = files.iter().chain(&deleted).collect();
+        if let Some(path) = pathspec.unmatched(&matched) {
+            return Err(anyhow::anyhow!(
+                "pathspec '{}' did not match any files",
+                path
+            ));
+        }
 
-                self.index.add(path, blob_oid, stats)?;
+        for path in files {
+            let data = workspace.read_file(&path)?;
+            let stats = workspace.stat_file(&path)?;
 
-                self.index.write_updates()?;
-            }
+            let blob_oid = self.db.store_object(&mut Blob::new(data))?;
+
+            self.index.add(path, blob_oid, stats)?;
+        }
+        for path in deleted {
+            self.index.remove(&path.to_string_lossy());
         }
 
+        self.index.write_updates()?;
+
         Ok(())
     }
 
-    pub fn commit(&mut self) -> Result<(), anyhow::Error> {
-        let mut tree = Tree::new();
+    /// Stores the tree described by the index and returns its ID. When the
+    /// index can be locked, the refreshed cache-tree is saved with it.
+    /// Fails while the index has unmerged paths.
+    pub fn write_tree(&mut self) -> Result<Oid, anyhow::Error> {
+        let locked = self.index.load_for_update()?;
+        if !locked {
+            self.index.load()?;
+        }
+
+        if self.index.conflicts().next().is_some() {
+            for entry in self.index.conflicts() {
+                eprintln!("{}: unmerged ({})", entry.path.to_string_lossy(), entry.oid);
+            }
+            return Err(anyhow::anyhow!("error building trees"));
+        }
+
+        if !locked {
+            return self.index.write_tree(&self.db);
+        }
+
+        let oid = self.index.write_tree(&self.db)?;
+        self.index.write_updates()?;
+        Ok(oid)
+    }
+
+    pub fn commit(&mut self, no_verify: bool) -> Result<(), anyhow::Error> {
+        self.commit_with_message(None, no_verify)
+    }
+
+    /// Commits the index with `message`, or one read from stdin. While a
+    /// merge is in progress the merged commit becomes the second parent
+    /// and the merge state is cleared afterwards. A conflicted cherry-pick
+    /// is committed with the author of the picked commit.
+    pub(super) fn commit_with_message(
+        &mut self,
+        message: Option<String>,
+        no_verify: bool,
+    ) -> Result<(), anyhow::Error> {
+        work_tree(&self.workspace)?;
+        let hooks = self.hooks();
+
         self.index.load()?;
+        if self.index.conflicts().next().is_some() {
+            return Err(anyhow::anyhow!(
+                "Committing is not possible because you have unmerged files."
+            ));
+        }
 
-        for entry in self.index.entries.values() {
-            tree.add_entry(PathBuf::from(entry.path.to_str().unwrap()), entry.oid)?;
+        if !no_verify && !hooks.run::<&str>("pre-commit", &[])? {
+            return Err(anyhow::anyhow!("pre-commit hook failed, aborting commit"));
         }
-        tree.traverse(&|tree| self.db.store_object(tree))?;
 
-        let tree_oid = self
-            .db
-            .store_object(&mut tree)
-            .with_context(|| "Could not store tree")?;
+        let tree_oid = self.write_tree()?;
+        let parent = self.refs.read_ref("HEAD")?;
+        let merge_head = self.merge_head()?;
+        let picked_author = self.picked_author()?;
+        let cherry_pick = picked_author.is_some();
+
+        let author = picked_author.unwrap_or_else(|| self.author());
+        let committer = self.committer();
 
-        let parent = match self.refs.get_head() {
-            Ok(head) => Some(head),
-            Err(_) => None,
+        let commit_message = match message {
+            Some(message) => message,
+            None => {
+                let mut message = String::new();
+                io::stdin().read_to_string(&mut message)?;
+                message
+            }
         };
 
-        let name = &self.config.user.name;
-        let email = &self.config.user.email;
+        let msg_path = self.git_path.join("COMMIT_EDITMSG");
+        fs::write(&msg_path, &commit_message).with_context(|| "Could not write COMMIT_EDITMSG")?;
+
+        let source = if merge_head.is_some() {
+            "merge"
+        } else {
+            "message"
+        };
+        if !hooks.run(
+            "prepare-commit-msg",
+            &[msg_path.as_os_str(), source.as_ref()],
+        )? {
+            return Err(anyhow::anyhow!(
+                "prepare-commit-msg hook failed, aborting commit"
+            ));
+        }
 
-        let author = commit::Author::new(name.to_owned(), email.to_owned(), Local::now());
+        if !no_verify && !hooks.run("commit-msg", &[&msg_path])? {
+            return Err(anyhow::anyhow!("commit-msg hook failed, aborting commit"));
+        }
 
-        let mut commit_message = String::new();
-        io::stdin().read_to_string(&mut commit_message)?;
+        let commit_message =
+            fs::read_to_string(&msg_path).with_context(|| "Could not read COMMIT_EDITMSG")?;
 
-        let mut commit = Commit::new(tree_oid, parent, author, commit_message.clone());
+        let mut commit = Commit::new(
+            tree_oid,
+            parent.into_iter().chain(merge_head).collect(),
+            author,
+            committer.clone(),
+            commit_message.clone(),
+        );
 
         let commit_oid = self
             .db
             .store_object(&mut commit)
             .with_context(|| "Could not store commit")?;
 
-        self.refs.set_head(&commit_oid)?;
-
         let commit_message_fl = commit_message.lines().next().unwrap_or_default();
 
+        let reflog_message = match (parent, merge_head) {
+            (Some(_), Some(_)) => format!("commit (merge): {}", commit_message_fl),
+            (Some(_), None) if cherry_pick => {
+                format!("commit (cherry-pick): {}", commit_message_fl)
+            }
+            (Some(_), None) => format!("commit: {}", commit_message_fl),
+            (None, _) => format!("commit (initial): {}", commit_message_fl),
+        };
+        self.refs.set_head(
+            &commit_oid,
+            Some(parent.unwrap_or_else(Oid::zero)),
+            &committer,
+            &reflog_message,
+        )?;
+        if merge_head.is_some() {
+            self.clear_merge_state()?;
+        }
+        self.clear_pick_state()?;
+
         let root_commit_marker = if parent.is_none() {
             "(root-commit) "
         } else {
@@ -146,6 +473,234 @@ impl Repository {
             root_commit_marker, &commit_oid, commit_message_fl
         );
 
+        hooks.run::<&str>("post-commit", &[])?;
+
+        Ok(())
+    }
+
+    pub fn rev_parse(&self, revisions: &[String]) -> Result<(), anyhow::Error> {
+        for rev in revisions {
+            let oid = Revision::parse(rev)?.resolve(&self.refs, &self.db)?;
+            println!("{}", oid);
+        }
+
         Ok(())
     }
+
+    pub fn reflog_show(&self, rev: Option<&str>) -> Result<(), anyhow::Error> {
+        let display = rev.unwrap_or("HEAD");
+        let name = Revision::reflog_ref(Some(display), &self.refs)?;
+
+        for (i, entry) in self.refs.reflog().read(&name)?.iter().rev().enumerate() {
+            println!(
+                "{} {}@{{{}}}: {}",
+                entry.new.to_short(),
+                display,
+                i,
+                entry.message
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Drops reflog entries older than `expire`, which defaults to
+    /// `gc.reflogExpire` or 90 days.
+    pub fn reflog_expire(
+        &self,
+        expire: Option<&str>,
+        all: bool,
+        dry_run: bool,
+        revs: &[String],
+    ) -> Result<(), anyhow::Error> {
+        let expire = expire
+            .or(self.config.get("gc.reflogExpire"))
+            .unwrap_or("90.days.ago");
+
+        let cutoff = match expire {
+            "never" | "false" => return Ok(()),
+            "all" => None,
+            date => Some(
+                parse_approxidate(date, Local::now().fixed_offset())
+                    .ok_or_else(|| anyhow::anyhow!("invalid expiry date: {}", date))?,
+            ),
+        };
+
+        let names = if all {
+            self.refs.reflog().list()?
+        } else {
+            revs.iter()
+                .map(|rev| Revision::reflog_ref(Some(rev), &self.refs))
+                .collect::<Result<_, _>>()?
+        };
+
+        for name in names {
+            let entries = self.refs.reflog().read(&name)?;
+            let (kept, expired): (Vec<_>, Vec<_>) = entries
+                .into_iter()
+                .partition(|entry| cutoff.is_some_and(|cutoff| entry.committer.time() >= cutoff));
+
+            if dry_run {
+                for entry in &expired {
+                    println!("would prune {}", entry.message);
+                }
+            } else if !expired.is_empty() {
+                self.refs.reflog().write(&name, &kept)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn reflog_delete(&self, revs: &[String]) -> Result<(), anyhow::Error> {
+        let mut deletions: Vec<(String, usize)> = Vec::new();
+
+        for rev in revs {
+            match Revision::parse(rev)? {
+                Revision::Reflog(name, ReflogSelector::Index(index)) => {
+                    deletions.push((Revision::reflog_ref(name.as_deref(), &self.refs)?, index));
+                }
+                _ => return Err(anyhow::anyhow!("not a reflog: {}", rev)),
+            }
+        }
+
+        // Delete from the oldest entry forward so earlier indexes stay valid,
+        // with the same entry named twice next to itself to be deleted once
+        deletions.sort_by_key(|(name, index)| (std::cmp::Reverse(*index), name.clone()));
+        deletions.dedup();
+
+        for (name, index) in deletions {
+            let mut entries = self.refs.reflog().read(&name)?;
+            if index >= entries.len() {
+                return Err(anyhow::anyhow!(
+                    "reflog entry {}@{{{}}} not found",
+                    name,
+                    index
+                ));
+            }
+            entries.remove(entries.len() - 1 - index);
+            self.refs.reflog().write(&name, &entries)?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolves an update-ref value; an empty string stands for the zero ID.
+    fn ref_value(&self, value: &str) -> Result<Oid, anyhow::Error> {
+        if value.is_empty() {
+            return Ok(Oid::zero());
+        }
+        Revision::parse(value)?.resolve(&self.refs, &self.db)
+    }
+
+    /// Resolves a revision that must name a commit, peeling tags.
+    fn resolve_commit(&self, rev: &str) -> Result<Oid, anyhow::Error> {
+        peel_to(
+            &self.db,
+            Revision::parse(rev)?.resolve(&self.refs, &self.db)?,
+            "commit",
+        )
+    }
+
+    pub fn update_ref(
+        &self,
+        name: &str,
+        new: Option<&str>,
+        old: Option<&str>,
+        delete: bool,
+        message: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        refs::check_ref_format(name)?;
+        let message = message.unwrap_or_default();
+
+        if delete {
+            // With -d the only value given is the expected old one
+            let old = new.map(|value| self.ref_value(value)).transpose()?;
+            return self.refs.delete_ref(name, old, &self.committer(), message);
+        }
+
+        let new = new.ok_or_else(|| anyhow::anyhow!("update-ref: missing <new-oid>"))?;
+        let new = self.ref_value(new)?;
+        let old = old.map(|value| self.ref_value(value)).transpose()?;
+
+        self.refs
+            .update_ref(name, &new, old, &self.committer(), message)
+    }
+
+    /// Applies the `update`, `create`, `delete` and `verify` commands read
+    /// from `input` as a single ref transaction.
+    pub fn update_ref_stdin(
+        &self,
+        input: impl io::BufRead,
+        message: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        let mut transaction = RefTransaction::new(self.committer(), message.unwrap_or_default());
+
+        for line in input.lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut args = line.split(' ');
+            let command = args.next().unwrap_or_default();
+            let name = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("{}: missing <ref>", command))?;
+            refs::check_ref_format(name)?;
+            let values: Vec<&str> = args.collect();
+
+            let value = |i: usize| -> Result<Option<Oid>, anyhow::Error> {
+                values.get(i).map(|value| self.ref_value(value)).transpose()
+            };
+            let max_args = match command {
+                "update" => 2,
+                "create" => 1,
+                "delete" | "verify" => 1,
+                _ => return Err(anyhow::anyhow!("unknown command: {}", line)),
+            };
+            if values.len() > max_args {
+                return Err(anyhow::anyhow!("{}: extra input: {}", command, line));
+            }
+
+            match command {
+                "update" => {
+                    let new = value(0)?
+                        .ok_or_else(|| anyhow::anyhow!("update {}: missing <new-oid>", name))?;
+                    transaction.update(name, new, value(1)?);
+                }
+                "create" => {
+                    let new = value(0)?
+                        .ok_or_else(|| anyhow::anyhow!("create {}: missing <new-oid>", name))?;
+                    transaction.create(name, new);
+                }
+                "delete" => transaction.delete(name, value(0)?),
+                _ => transaction.verify(name, value(0)?.unwrap_or_else(Oid::zero)),
+            }
+        }
+
+        transaction.commit(&self.refs)
+    }
+
+    /// Packs loose refs into `packed-refs`: all of them with `all`,
+    /// otherwise only tags and refs that were packed before.
+    pub fn pack_refs(&self, all: bool, prune: bool) -> Result<(), anyhow::Error> {
+        let already_packed = self.refs.packed_ref_names()?;
+        let mut to_pack = Vec::new();
+
+        for (name, oid) in self.refs.loose_refs()? {
+            if !(all || name.starts_with("refs/tags/") || already_packed.contains(&name)) {
+                continue;
+            }
+
+            let peeled = self.db.peel(&oid)?;
+            to_pack.push(PackedRef {
+                name,
+                oid,
+                peeled: (peeled != oid).then_some(peeled),
+            });
+        }
+
+        self.refs.pack(to_pack, prune)
+    }
 }
@@ -19,6 +19,45 @@ impl Author {
         }
     }
 
+    /// Parses the `Name <email> timestamp +zone` form produced by
+    /// [`Author::string`].
+    pub fn parse(value: &str) -> Result<Self, anyhow::Error> {
+        let (name, rest) = value
+            .split_once('<')
+            .ok_or_else(|| anyhow::anyhow!("malformed identity: {}", value))?;
+        let (email, rest) = rest
+            .split_once('>')
+            .ok_or_else(|| anyhow::anyhow!("malformed identity: {}", value))?;
+
+        let mut parts = rest.split_whitespace();
+        let timestamp: i64 = parts.next().unwrap_or("0").parse()?;
+        let offset = FixedOffset::east_opt(parse_utc_offset(parts.next().unwrap_or("+0000"))?)
+            .ok_or_else(|| anyhow::anyhow!("invalid timezone in identity: {}", value))?;
+
+        let a_time = offset
+            .timestamp_opt(timestamp, 0)
+            .single()
+            .ok_or_else(|| anyhow::anyhow!("invalid timestamp in identity: {}", value))?;
+
+        Ok(Self {
+            name: name.trim().to_string(),
+            email: email.to_string(),
+            a_time,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn time(&self) -> DateTime<FixedOffset> {
+        self.a_time
+    }
+
     pub fn string(&self) -> String {
         let unix_timestamp = self.a_time.timestamp();
         let utc_offset = self.a_time.fixed_offset().format("%z");
@@ -30,25 +69,102 @@ impl Author {
     }
 }
 
+/// Parses a `+hhmm`/`-hhmm` offset into seconds east of UTC.
+fn parse_utc_offset(value: &str) -> Result<i32, anyhow::Error> {
+    let (sign, digits) = match value.split_at(1) {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return Err(anyhow::anyhow!("invalid timezone: {}", value)),
+    };
+
+    if digits.len() != 4 {
+        return Err(anyhow::anyhow!("invalid timezone: {}", value));
+    }
+
+    let hours: i32 = digits[..2].parse()?;
+    let minutes: i32 = digits[2..].parse()?;
+    Ok(sign * (hours * 3600 + minutes * 60))
+}
+
 #[derive(Debug, Clone)]
 pub struct Commit {
     oid: Option<Oid>,
     tree: Oid,
-    parent: Option<Oid>,
+    parents: Vec<Oid>,
     author: Author,
+    committer: Author,
     message: String,
 }
 
 impl Commit {
-    pub fn new(tree_oid: Oid, parent: Option<Oid>, author: Author, message: String) -> Self {
+    pub fn new(
+        tree_oid: Oid,
+        parents: Vec<Oid>,
+        author: Author,
+        committer: Author,
+        message: String,
+    ) -> Self {
         Self {
             oid: None,
             tree: tree_oid,
-            parent,
+            parents,
             author,
+            committer,
             message,
         }
     }
+
+    pub fn parse(data: &[u8]) -> Result<Self, anyhow::Error> {
+        let text = String::from_utf8_lossy(data);
+        let (headers, message) = text.split_once("\n\n").unwrap_or((&text, ""));
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+
+        for line in headers.lines() {
+            match line.split_once(' ') {
+                Some(("tree", value)) => tree = Oid::parse(value),
+                Some(("parent", value)) => parents.push(
+                    Oid::parse(value)
+                        .ok_or_else(|| anyhow::anyhow!("bad parent line in commit: {}", line))?,
+                ),
+                Some(("author", value)) => author = Some(Author::parse(value)?),
+                Some(("committer", value)) => committer = Some(Author::parse(value)?),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            oid: None,
+            tree: tree.ok_or_else(|| anyhow::anyhow!("commit has no tree line"))?,
+            parents,
+            author: author.ok_or_else(|| anyhow::anyhow!("commit has no author line"))?,
+            committer: committer.ok_or_else(|| anyhow::anyhow!("commit has no committer line"))?,
+            message: message.to_string(),
+        })
+    }
+
+    pub fn tree(&self) -> Oid {
+        self.tree
+    }
+
+    pub fn parents(&self) -> &[Oid] {
+        &self.parents
+    }
+
+    pub fn author(&self) -> &Author {
+        &self.author
+    }
+
+    pub fn committer(&self) -> &Author {
+        &self.committer
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
 }
 
 impl Object for Commit {
@@ -62,14 +178,14 @@ impl Object for Commit {
 
     fn to_bytes(&self) -> Vec<u8> {
         format!(
-            "tree {}\n{}author {}\ncommiter {}\n\n{}",
+            "tree {}\n{}author {}\ncommitter {}\n\n{}",
             self.tree,
-            match self.parent {
-                Some(parent) => format!("parent {}\n", parent),
-                None => String::new(),
-            },
-            self.author.string(),
+            self.parents
+                .iter()
+                .map(|parent| format!("parent {}\n", parent))
+                .collect::<String>(),
             self.author.string(),
+            self.committer.string(),
             self.message
         )
         .into()
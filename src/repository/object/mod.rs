@@ -2,6 +2,7 @@ use crate::oid::Oid;
 
 pub mod blob;
 pub mod commit;
+pub mod tag;
 pub mod tree;
 
 pub trait Object {
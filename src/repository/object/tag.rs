@@ -0,0 +1,89 @@
+use super::{commit::Author, Object};
+use crate::oid::Oid;
+
+#[derive(Debug, Clone)]
+pub struct Tag {
+    oid: Option<Oid>,
+    object: Oid,
+    kind: String,
+    tag: String,
+    tagger: Option<Author>,
+    message: String,
+}
+
+impl Tag {
+    pub fn new(object: Oid, kind: &str, tag: &str, tagger: Author, message: String) -> Self {
+        Self {
+            oid: None,
+            object,
+            kind: kind.to_string(),
+            tag: tag.to_string(),
+            tagger: Some(tagger),
+            message,
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, anyhow::Error> {
+        let text = String::from_utf8_lossy(data);
+        let (headers, message) = text.split_once("\n\n").unwrap_or((&text, ""));
+
+        let mut object = None;
+        let mut kind = None;
+        let mut tag = None;
+        let mut tagger = None;
+
+        for line in headers.lines() {
+            match line.split_once(' ') {
+                Some(("object", value)) => object = Oid::parse(value),
+                Some(("type", value)) => kind = Some(value.to_string()),
+                Some(("tag", value)) => tag = Some(value.to_string()),
+                Some(("tagger", value)) => tagger = Some(Author::parse(value)?),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            oid: None,
+            object: object.ok_or_else(|| anyhow::anyhow!("tag object has no object line"))?,
+            kind: kind.ok_or_else(|| anyhow::anyhow!("tag object has no type line"))?,
+            tag: tag.ok_or_else(|| anyhow::anyhow!("tag object has no tag line"))?,
+            tagger,
+            message: message.to_string(),
+        })
+    }
+
+    pub fn object(&self) -> Oid {
+        self.object
+    }
+
+    pub fn tagger(&self) -> Option<&Author> {
+        self.tagger.as_ref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Object for Tag {
+    fn kind(&self) -> &[u8] {
+        b"tag"
+    }
+
+    fn set_oid(&mut self, oid: Oid) {
+        self.oid = Some(oid);
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let tagger = match &self.tagger {
+            Some(tagger) => format!("tagger {}\n", tagger.string()),
+            None => String::new(),
+        };
+
+        format!(
+            "object {}\ntype {}\ntag {}\n{}\n{}",
+            self.object, self.kind, self.tag, tagger, self.message
+        )
+        .into()
+    }
+}
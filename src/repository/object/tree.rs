@@ -1,10 +1,5 @@
 use indexmap::IndexMap;
-use std::{
-    ffi::CString,
-    fs::{self, Metadata},
-    os::unix::fs::PermissionsExt,
-    path::PathBuf,
-};
+use std::{ffi::CString, path::PathBuf};
 
 use anyhow;
 
@@ -12,13 +7,31 @@ use crate::oid::Oid;
 
 use super::Object;
 
-const MODE: usize = 100644;
-const MODE_EXECUTABLE: usize = 100755;
-const MODE_DIR: usize = 40000;
+pub const MODE_DIR: u32 = 0o40000;
+pub const MODE_GITLINK: u32 = 0o160000;
+
+/// One entry of a stored tree object.
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: String,
+    pub oid: Oid,
+}
+
+impl TreeEntry {
+    /// The type of object the entry points at, as implied by its mode.
+    pub fn kind(&self) -> &'static str {
+        match self.mode {
+            MODE_DIR => "tree",
+            MODE_GITLINK => "commit",
+            _ => "blob",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 enum TreeNode {
-    Leaf(Oid, Metadata),
+    Leaf(Oid, u32),
     Branch(Tree),
 }
 
@@ -36,34 +49,51 @@ impl Tree {
         }
     }
 
-    pub fn traverse<F>(&mut self, f: &F) -> Result<(), anyhow::Error>
-    where
-        F: Fn(&mut Tree) -> Result<Oid, anyhow::Error>,
-    {
-        for (_name, entry) in self.entries.iter_mut() {
-            if let TreeNode::Branch(ref mut tree) = entry {
-                tree.traverse(f)?;
-            }
+    /// Parses the entries of a stored tree object.
+    pub fn parse(data: &[u8]) -> Result<Vec<TreeEntry>, anyhow::Error> {
+        let mut entries = Vec::new();
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            let space = rest
+                .iter()
+                .position(|&b| b == b' ')
+                .ok_or_else(|| anyhow::anyhow!("malformed tree entry"))?;
+            let nul = rest
+                .iter()
+                .position(|&b| b == 0)
+                .filter(|&nul| nul > space && nul + 21 <= rest.len())
+                .ok_or_else(|| anyhow::anyhow!("malformed tree entry"))?;
+
+            let mode = u32::from_str_radix(std::str::from_utf8(&rest[..space])?, 8)?;
+            let name = String::from_utf8_lossy(&rest[space + 1..nul]).into_owned();
+            let oid = Oid::from(&rest[nul + 1..nul + 21]);
+
+            entries.push(TreeEntry { mode, name, oid });
+            rest = &rest[nul + 21..];
         }
 
-        f(self)?;
-
-        Ok(())
+        Ok(entries)
     }
 
-    pub fn add_entry(&mut self, path: PathBuf, oid: Oid) -> Result<(), anyhow::Error> {
+    pub fn add_entry(&mut self, path: PathBuf, oid: Oid, mode: u32) {
         let components: Vec<_> = path
             .components()
             .map(|comp| comp.as_os_str().to_string_lossy().into_owned())
             .collect();
 
-        let fmeta = fs::metadata(path)?;
+        self.add_entry_recursive(&components, oid, mode);
+    }
 
-        self.add_entry_recursive(&components, oid, fmeta);
-        Ok(())
+    /// Adds a subtree that is already stored, by its object ID.
+    pub fn add_subtree(&mut self, name: &str, oid: Oid) {
+        let mut tree = Tree::new();
+        tree.oid = Some(oid);
+        self.entries
+            .insert(CString::new(name).unwrap(), TreeNode::Branch(tree));
     }
 
-    fn add_entry_recursive(&mut self, components: &[String], oid: Oid, stats: Metadata) {
+    fn add_entry_recursive(&mut self, components: &[String], oid: Oid, mode: u32) {
         if components.is_empty() {
             return;
         }
@@ -73,7 +103,7 @@ impl Tree {
 
         if components.len() == 1 {
             self.entries
-                .insert(fname.clone(), TreeNode::Leaf(oid, stats));
+                .insert(fname.clone(), TreeNode::Leaf(oid, mode));
         } else {
             let entry = self
                 .entries
@@ -81,31 +111,19 @@ impl Tree {
                 .or_insert_with(|| TreeNode::Branch(Tree::new()));
 
             if let TreeNode::Branch(ref mut tree) = entry {
-                tree.add_entry_recursive(&components[1..], oid, stats);
+                tree.add_entry_recursive(&components[1..], oid, mode);
             }
         }
     }
 
-    fn is_executable(stat: &fs::Metadata) -> bool {
-        stat.permissions().mode() & 0o111 != 0
-    }
-
-    fn mode(stat: &fs::Metadata) -> usize {
-        if Tree::is_executable(stat) {
-            MODE_EXECUTABLE
-        } else {
-            MODE
-        }
-    }
-
     fn serialize(name: &CString, tree_node: &TreeNode) -> Vec<u8> {
-        let (oid, stats) = match tree_node {
-            TreeNode::Leaf(o, m) => (o, Tree::mode(m)),
+        let (oid, mode) = match tree_node {
+            TreeNode::Leaf(o, m) => (o, *m),
             TreeNode::Branch(t) => (&t.oid.unwrap(), MODE_DIR),
         };
 
         let mut serialized = Vec::new();
-        serialized.extend_from_slice(stats.to_string().as_bytes());
+        serialized.extend_from_slice(format!("{:o}", mode).as_bytes());
         serialized.push(b' ');
         serialized.extend_from_slice(name.to_bytes_with_nul());
         serialized.extend_from_slice(oid.as_bytes());
@@ -122,9 +140,20 @@ impl Object for Tree {
         self.oid = Some(oid);
     }
 
+    /// Entries are written in Git's order: by name, with subtrees compared
+    /// as if their names ended in `/`.
     fn to_bytes(&self) -> Vec<u8> {
-        self.entries
-            .iter()
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_cached_key(|(name, tree_node)| {
+            let mut key = name.as_bytes().to_vec();
+            if let TreeNode::Branch(_) = tree_node {
+                key.push(b'/');
+            }
+            key
+        });
+
+        entries
+            .into_iter()
             .flat_map(|(name, tree_node)| Tree::serialize(name, tree_node))
             .collect()
     }
@@ -0,0 +1,152 @@
+use std::{
+    cell::RefCell, fs, io::ErrorKind, os::unix::fs::MetadataExt, path::PathBuf, rc::Rc,
+    time::SystemTime,
+};
+
+use anyhow::Context;
+
+use crate::lockfile::Lockfile;
+use crate::oid::Oid;
+
+const HEADER: &str = "# pack-refs with: peeled fully-peeled sorted \n";
+
+#[derive(Debug, Clone)]
+pub struct PackedRef {
+    pub name: String,
+    pub oid: Oid,
+    /// For annotated tags, the object the tag ultimately points at
+    pub peeled: Option<Oid>,
+}
+
+/// What tells versions of the file apart: it is always replaced by a
+/// rename, so a new version has a new inode even within the same mtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    ino: u64,
+    len: u64,
+    modified: SystemTime,
+}
+
+/// The `packed-refs` file: one `<oid> <refname>` line per ref, sorted by
+/// name, each optionally followed by a `^<oid>` line with its peeled value.
+/// The parsed refs are kept until the file changes, as looking up refs one
+/// by one would otherwise parse it again each time.
+pub struct PackedRefs {
+    path: PathBuf,
+    cache: RefCell<Option<(Stamp, Rc<Vec<PackedRef>>)>>,
+}
+
+impl PackedRefs {
+    pub fn new(git_path: PathBuf) -> Self {
+        Self {
+            path: git_path.join("packed-refs"),
+            cache: RefCell::new(None),
+        }
+    }
+
+    pub fn read(&self) -> Result<Vec<PackedRef>, anyhow::Error> {
+        Ok(self.load()?.as_ref().clone())
+    }
+
+    /// The parsed refs, from the cache while the file is unchanged.
+    fn load(&self) -> Result<Rc<Vec<PackedRef>>, anyhow::Error> {
+        let stamp = match fs::metadata(&self.path) {
+            Ok(metadata) => Stamp {
+                ino: metadata.ino(),
+                len: metadata.len(),
+                modified: metadata.modified()?,
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                self.cache.replace(None);
+                return Ok(Rc::new(Vec::new()));
+            }
+            Err(e) => return Err(e).with_context(|| "Could not read packed-refs"),
+        };
+        if let Some((cached, refs)) = &*self.cache.borrow() {
+            if *cached == stamp {
+                return Ok(Rc::clone(refs));
+            }
+        }
+
+        let refs = Rc::new(self.parse()?);
+        self.cache.replace(Some((stamp, Rc::clone(&refs))));
+        Ok(refs)
+    }
+
+    fn parse(&self) -> Result<Vec<PackedRef>, anyhow::Error> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| "Could not read packed-refs"),
+        };
+
+        let mut refs: Vec<PackedRef> = Vec::new();
+
+        for line in content.lines() {
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            if let Some(peeled) = line.strip_prefix('^') {
+                let last = refs
+                    .last_mut()
+                    .ok_or_else(|| anyhow::anyhow!("packed-refs: peeled line without a ref"))?;
+                last.peeled = Some(
+                    Oid::parse(peeled)
+                        .ok_or_else(|| anyhow::anyhow!("packed-refs: bad line: {}", line))?,
+                );
+                continue;
+            }
+
+            let (oid, name) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow::anyhow!("packed-refs: bad line: {}", line))?;
+
+            refs.push(PackedRef {
+                name: name.to_string(),
+                oid: Oid::parse(oid)
+                    .ok_or_else(|| anyhow::anyhow!("packed-refs: bad line: {}", line))?,
+                peeled: None,
+            });
+        }
+
+        refs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(refs)
+    }
+
+    pub fn find(&self, name: &str) -> Result<Option<PackedRef>, anyhow::Error> {
+        let refs = self.load()?;
+        Ok(refs
+            .binary_search_by(|r| r.name.as_str().cmp(name))
+            .ok()
+            .map(|i| refs[i].clone()))
+    }
+
+    pub fn lock(&self) -> Result<Lockfile, anyhow::Error> {
+        let mut lockfile = Lockfile::new(self.path.clone());
+        if !lockfile.hold_for_update()? {
+            return Err(anyhow::anyhow!(
+                "Unable to create '{}.lock': File exists",
+                self.path.display()
+            ));
+        }
+        Ok(lockfile)
+    }
+
+    /// Replaces the file's contents with `refs` through a held `lockfile`.
+    pub fn write(&self, mut lockfile: Lockfile, refs: &[PackedRef]) -> Result<(), anyhow::Error> {
+        let mut refs = refs.to_vec();
+        refs.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut content = String::from(HEADER);
+        for r in &refs {
+            content.push_str(&format!("{} {}\n", r.oid, r.name));
+            if let Some(peeled) = r.peeled {
+                content.push_str(&format!("^{}\n", peeled));
+            }
+        }
+
+        lockfile.write(content.as_bytes())?;
+        lockfile.commit()
+    }
+}
@@ -0,0 +1,249 @@
+use std::path::{Path, PathBuf};
+
+use crate::wildmatch::wildmatch;
+
+use super::workspace::Workspace;
+
+/// Magic changing how a pathspec element is read and matched, given as
+/// `:(<magic>,...)<pattern>` or with the short forms `:/`, `:!` and `:^`.
+#[derive(Debug, Default, Clone, Copy)]
+struct Magic {
+    /// `top`: the pattern is relative to the work tree root, not the
+    /// current directory
+    top: bool,
+    /// `exclude`: paths it matches are left out
+    exclude: bool,
+    /// `icase`: match case-insensitively
+    icase: bool,
+    /// `literal`: wildcards are plain characters
+    literal: bool,
+    /// `glob`: wildcards do not match `/`, except for `**`
+    glob: bool,
+}
+
+impl Magic {
+    /// Splits the magic off the front of `arg`, returning it with the rest.
+    fn parse(arg: &str) -> Result<(Self, &str), anyhow::Error> {
+        let mut magic = Self::default();
+        let Some(rest) = arg.strip_prefix(':') else {
+            return Ok((magic, arg));
+        };
+
+        if let Some(long) = rest.strip_prefix('(') {
+            let (words, pattern) = long.split_once(')').ok_or_else(|| {
+                anyhow::anyhow!("Missing ')' at the end of pathspec magic in '{}'", arg)
+            })?;
+            for word in words.split(',').map(str::trim) {
+                match word {
+                    "top" => magic.top = true,
+                    "exclude" => magic.exclude = true,
+                    "icase" => magic.icase = true,
+                    "literal" => magic.literal = true,
+                    "glob" => magic.glob = true,
+                    "" => {}
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                            "Invalid pathspec magic '{}' in '{}'",
+                            word,
+                            arg
+                        ))
+                    }
+                }
+            }
+            if magic.literal && magic.glob {
+                return Err(anyhow::anyhow!(
+                    "{}: 'literal' and 'glob' are incompatible",
+                    arg
+                ));
+            }
+            return Ok((magic, pattern));
+        }
+
+        let mut chars = rest.char_indices();
+        let pattern = loop {
+            match chars.next() {
+                Some((_, '/')) => magic.top = true,
+                Some((_, '!' | '^')) => magic.exclude = true,
+                Some((i, ':')) => break &rest[i + 1..],
+                Some((i, _)) => break &rest[i..],
+                None => break "",
+            }
+        };
+        Ok((magic, pattern))
+    }
+}
+
+/// One element of a pathspec.
+struct Item {
+    /// As given on the command line, for messages
+    original: String,
+    /// Relative to the work tree root
+    pattern: String,
+    /// The length of the leading part of `pattern` without wildcards
+    literal_len: usize,
+    magic: Magic,
+}
+
+/// Whether `name` starts with `prefix`, ignoring ASCII case with `icase`.
+fn has_prefix(name: &str, prefix: &str, icase: bool) -> bool {
+    name.len() >= prefix.len()
+        && if icase {
+            name.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+        } else {
+            name.as_bytes().starts_with(prefix.as_bytes())
+        }
+}
+
+impl Item {
+    /// Whether the root-relative `name` is the pattern itself or lies in a
+    /// directory it names, or, for a pattern with wildcards, matches it.
+    /// Without `glob` magic wildcards match `/` too.
+    fn matches(&self, name: &str) -> bool {
+        let pattern = &self.pattern;
+        if pattern.is_empty() {
+            return true;
+        }
+
+        if has_prefix(name, pattern, self.magic.icase)
+            && matches!(name.as_bytes().get(pattern.len()), None | Some(b'/'))
+        {
+            return true;
+        }
+        self.literal_len < pattern.len()
+            && has_prefix(name, &pattern[..self.literal_len], self.magic.icase)
+            && wildmatch(pattern, name, self.magic.glob, self.magic.icase)
+    }
+
+    /// Whether the pattern could match something inside the directory
+    /// `dir`.
+    fn may_match_below(&self, dir: &str) -> bool {
+        let literal = &self.pattern[..self.literal_len];
+        let dir_slash = format!("{}/", dir);
+        self.matches(dir)
+            || has_prefix(literal, &dir_slash, self.magic.icase)
+            || self.literal_len < self.pattern.len()
+                && has_prefix(&dir_slash, literal, self.magic.icase)
+    }
+}
+
+/// The paths a command works on, as patterns given relative to the
+/// current directory. A path is selected when some element matches it and
+/// no `exclude` element does; no elements at all select everything, and
+/// only `exclude` ones select what they leave of the current directory.
+pub struct Pathspec {
+    items: Vec<Item>,
+}
+
+impl Pathspec {
+    pub fn parse(
+        workspace: &Workspace,
+        cwd: &Path,
+        args: &[PathBuf],
+    ) -> Result<Self, anyhow::Error> {
+        let mut items = Vec::new();
+        for arg in args {
+            let original = arg.to_string_lossy().into_owned();
+            let (magic, pattern) = Magic::parse(&original)?;
+            let base = if magic.top { &workspace.root } else { cwd };
+            let pattern = workspace
+                .relative_path(base, Path::new(pattern))?
+                .to_string_lossy()
+                .into_owned();
+
+            let literal_len = if magic.literal {
+                pattern.len()
+            } else {
+                pattern.find(['*', '?', '[', '\\']).unwrap_or(pattern.len())
+            };
+            items.push(Item {
+                original,
+                pattern,
+                literal_len,
+                magic,
+            });
+        }
+
+        if !items.is_empty() && items.iter().all(|item| item.magic.exclude) {
+            let pattern = workspace
+                .relative_path(cwd, Path::new(""))?
+                .to_string_lossy()
+                .into_owned();
+            items.push(Item {
+                original: ".".to_string(),
+                literal_len: pattern.len(),
+                pattern,
+                magic: Magic::default(),
+            });
+        }
+
+        Ok(Self { items })
+    }
+
+    fn included(&self) -> impl Iterator<Item = &Item> {
+        self.items.iter().filter(|item| !item.magic.exclude)
+    }
+
+    /// Whether the root-relative `path` is selected.
+    pub fn matches(&self, path: &Path) -> bool {
+        if self.items.is_empty() {
+            return true;
+        }
+        let name = path.to_string_lossy();
+
+        self.included().any(|item| item.matches(&name))
+            && !self
+                .items
+                .iter()
+                .any(|item| item.magic.exclude && item.matches(&name))
+    }
+
+    /// Whether anything inside the root-relative directory `dir` could be
+    /// selected, so that it is worth looking into.
+    pub fn may_match_below(&self, dir: &Path) -> bool {
+        let dir = dir.to_string_lossy();
+        self.items.is_empty() || self.included().any(|item| item.may_match_below(&dir))
+    }
+
+    /// The deepest directory holding every path that can be selected, to
+    /// look for them in.
+    pub fn common_prefix(&self) -> PathBuf {
+        let mut prefix: Option<&str> = None;
+        for item in self.included() {
+            let literal = if item.magic.icase {
+                ""
+            } else {
+                &item.pattern[..item.literal_len]
+            };
+            let common = match prefix {
+                None => literal,
+                Some(prefix) => {
+                    let mut len = prefix
+                        .bytes()
+                        .zip(literal.bytes())
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    while !prefix.is_char_boundary(len) {
+                        len -= 1;
+                    }
+                    &prefix[..len]
+                }
+            };
+            prefix = Some(common);
+        }
+
+        let prefix = prefix.unwrap_or_default();
+        PathBuf::from(prefix.rfind('/').map_or("", |slash| &prefix[..slash]))
+    }
+
+    /// The first element, as given, that does not match any of `paths`;
+    /// `exclude` elements are not checked.
+    pub fn unmatched<P: AsRef<Path>>(&self, paths: &[P]) -> Option<&str> {
+        self.included()
+            .find(|item| {
+                !paths
+                    .iter()
+                    .any(|path| item.matches(&path.as_ref().to_string_lossy()))
+            })
+            .map(|item| item.original.as_str())
+    }
+}
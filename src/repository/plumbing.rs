@@ -0,0 +1,590 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Read, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::oid::Oid;
+
+use super::index::IndexEntry;
+use super::object::{
+    blob::Blob,
+    commit::Commit,
+    tag::Tag,
+    tree::{Tree, TreeEntry},
+};
+use super::pathspec::Pathspec;
+use super::revision::{peel_to, Revision};
+use super::{work_tree, Repository};
+
+/// What `cat-file` shows about an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatFile {
+    Type,
+    Size,
+    Exists,
+    /// The content, with trees listed like `ls-tree` does
+    Pretty,
+}
+
+/// Expresses root-relative `path` relative to the root-relative directory
+/// `base`, climbing out with `..` where needed.
+pub(super) fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[common..]);
+    relative
+}
+
+impl Repository {
+    /// Prints the ID of each object built from `paths` (and standard input
+    /// with `stdin`) as type `kind`, storing it when `write` is set.
+    pub fn hash_object(
+        &self,
+        kind: &str,
+        write: bool,
+        stdin: bool,
+        paths: &[PathBuf],
+    ) -> Result<(), anyhow::Error> {
+        let mut inputs = Vec::new();
+        if stdin {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data)?;
+            inputs.push(data);
+        }
+        for path in paths {
+            inputs.push(
+                fs::read(path).with_context(|| format!("could not open '{}'", path.display()))?,
+            );
+        }
+
+        for data in inputs {
+            // Like Git without --literally, only well-formed objects are accepted
+            match kind {
+                "blob" => {}
+                "tree" => {
+                    Tree::parse(&data)?;
+                }
+                "commit" => {
+                    Commit::parse(&data)?;
+                }
+                "tag" => {
+                    Tag::parse(&data)?;
+                }
+                _ => return Err(anyhow::anyhow!("invalid object type \"{}\"", kind)),
+            }
+
+            println!("{}", self.db.hash_object(kind.as_bytes(), &data, write)?);
+        }
+
+        Ok(())
+    }
+
+    /// Prints what `show` asks for about `object`: for a tag, its target,
+    /// tagger and message in the raw form. Returns whether the object
+    /// exists, which is all `CatFile::Exists` reports.
+    pub fn cat_file(&self, object: &str, show: CatFile) -> Result<bool, anyhow::Error> {
+        let oid = Revision::parse(object)?.resolve(&self.refs, &self.db)?;
+        let (kind, data) = match self.db.read_raw(&oid) {
+            Ok(object) => object,
+            Err(_) if show == CatFile::Exists => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        match show {
+            CatFile::Type => println!("{}", kind),
+            CatFile::Size => println!("{}", data.len()),
+            CatFile::Exists => {}
+            CatFile::Pretty if kind == "tree" => {
+                for entry in Tree::parse(&data)? {
+                    print_tree_entry(&entry, Path::new(&entry.name), false);
+                }
+            }
+            CatFile::Pretty => io::stdout().write_all(&data)?,
+        }
+        Ok(true)
+    }
+
+    /// Creates a commit of `tree` with the given parents. Each message
+    /// becomes its own paragraph; without any the message is read from
+    /// standard input.
+    pub fn commit_tree(
+        &self,
+        tree: &str,
+        parents: &[String],
+        messages: &[String],
+        file: Option<&Path>,
+    ) -> Result<(), anyhow::Error> {
+        let tree = peel_to(
+            &self.db,
+            Revision::parse(tree)?.resolve(&self.refs, &self.db)?,
+            "tree",
+        )?;
+
+        let mut parent_oids: Vec<Oid> = Vec::new();
+        for parent in parents {
+            let oid = peel_to(
+                &self.db,
+                Revision::parse(parent)?.resolve(&self.refs, &self.db)?,
+                "commit",
+            )?;
+            if parent_oids.contains(&oid) {
+                eprintln!("error: duplicate parent {} ignored", oid);
+            } else {
+                parent_oids.push(oid);
+            }
+        }
+
+        let message = if let Some(file) = file {
+            fs::read_to_string(file)
+                .with_context(|| format!("could not read log file '{}'", file.display()))?
+        } else if messages.is_empty() {
+            let mut message = String::new();
+            io::stdin().read_to_string(&mut message)?;
+            message
+        } else {
+            let paragraphs: Vec<&str> = messages.iter().map(|m| m.trim_end()).collect();
+            format!("{}\n", paragraphs.join("\n\n"))
+        };
+
+        let mut commit = Commit::new(tree, parent_oids, self.author(), self.committer(), message);
+        println!("{}", self.db.store_object(&mut commit)?);
+
+        Ok(())
+    }
+
+    /// Lists the entries of `treeish`. Subtrees are only entered with
+    /// `recursive` or when a path filter points inside them; `show_trees`
+    /// also prints the subtrees that were entered.
+    pub fn ls_tree(
+        &self,
+        treeish: &str,
+        recursive: bool,
+        show_trees: bool,
+        name_only: bool,
+        paths: &[String],
+    ) -> Result<(), anyhow::Error> {
+        let tree = peel_to(
+            &self.db,
+            Revision::parse(treeish)?.resolve(&self.refs, &self.db)?,
+            "tree",
+        )?;
+
+        let filters: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+        self.ls_tree_entries(
+            &tree,
+            Path::new(""),
+            recursive,
+            show_trees,
+            name_only,
+            &filters,
+        )
+    }
+
+    fn ls_tree_entries(
+        &self,
+        tree: &Oid,
+        prefix: &Path,
+        recursive: bool,
+        show_trees: bool,
+        name_only: bool,
+        filters: &[PathBuf],
+    ) -> Result<(), anyhow::Error> {
+        let (_, data) = self.db.read_raw(tree)?;
+
+        for entry in Tree::parse(&data)? {
+            let path = prefix.join(&entry.name);
+
+            let listed = filters.is_empty() || filters.iter().any(|f| path.starts_with(f));
+            let inside = filters.iter().any(|f| f.starts_with(&path) && f != &path);
+
+            let descend = entry.kind() == "tree" && (inside || (recursive && listed));
+
+            if descend {
+                if show_trees && listed {
+                    print_tree_entry(&entry, &path, name_only);
+                }
+                self.ls_tree_entries(&entry.oid, &path, recursive, show_trees, name_only, filters)?;
+            } else if listed {
+                print_tree_entry(&entry, &path, name_only);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists index and work tree files relative to `cwd`. `cached` lists
+    /// index entries (the default when nothing else is asked for),
+    /// `deleted` and `modified` list entries whose file is gone or
+    /// changed, and `others` lists untracked files. `unmerged` lists the
+    /// conflict stages of unmerged paths and implies `stage`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn ls_files(
+        &mut self,
+        cwd: &Path,
+        cached: bool,
+        deleted: bool,
+        modified: bool,
+        others: bool,
+        stage: bool,
+        unmerged: bool,
+        paths: &[PathBuf],
+    ) -> Result<(), anyhow::Error> {
+        let workspace = work_tree(&self.workspace)?;
+        let prefix = workspace.relative_path(cwd, Path::new(""))?;
+        let pathspec = if paths.is_empty() {
+            Pathspec::parse(workspace, cwd, &[PathBuf::from(".")])?
+        } else {
+            Pathspec::parse(workspace, cwd, paths)?
+        };
+        let selected = |path: &Path| pathspec.matches(path);
+
+        self.index.load()?;
+        let cached = cached || !(deleted || modified || others || unmerged);
+        let stage = stage || unmerged;
+
+        let show = |path: &Path, entry: Option<&IndexEntry>| {
+            let display = relative_to(path, &prefix);
+            match entry {
+                Some(entry) if stage => println!(
+                    "{:06o} {} {}\t{}",
+                    entry.mode,
+                    entry.oid,
+                    entry.stage(),
+                    display.display()
+                ),
+                _ => println!("{}", display.display()),
+            }
+        };
+
+        if others {
+            let tracked: HashSet<&[u8]> = self
+                .index
+                .entries
+                .values()
+                .map(|entry| entry.path.as_bytes())
+                .collect();
+
+            for path in workspace.list_files(None)? {
+                if !tracked.contains(path.as_os_str().as_bytes()) && selected(&path) {
+                    show(&path, None);
+                }
+            }
+        }
+
+        let mut previous = None;
+        for entry in self.index.entries.values() {
+            let first_stage = previous != Some(&entry.path);
+            previous = Some(&entry.path);
+
+            let path = PathBuf::from(entry.path.to_str()?);
+            if !selected(&path) {
+                continue;
+            }
+
+            if cached || (unmerged && entry.stage() > 0) {
+                show(&path, Some(entry));
+            }
+
+            // An unmerged path counts as modified, once
+            if entry.stage() > 0 {
+                if modified && first_stage {
+                    show(&path, Some(entry));
+                }
+                continue;
+            }
+
+            if deleted || modified {
+                match self.file_matches_entry(entry)? {
+                    None => {
+                        if deleted {
+                            show(&path, Some(entry));
+                        }
+                        if modified {
+                            show(&path, Some(entry));
+                        }
+                    }
+                    Some(false) if modified => show(&path, Some(entry)),
+                    Some(_) => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates index entries directly. Each of `paths` is re-hashed from
+    /// the work tree; new paths need `add` and vanished ones `remove`.
+    /// `cacheinfo` entries (`<mode>,<oid>,<path>`) are inserted without
+    /// touching the work tree, and `refresh` re-checks stat data,
+    /// returning `false` when some entry needs updating. `assume_unchanged`
+    /// and `skip_worktree` set or clear those marks on `paths` instead of
+    /// re-hashing them, and `index_version` converts the index format.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_index(
+        &mut self,
+        cwd: &Path,
+        add: bool,
+        remove: bool,
+        force_remove: bool,
+        refresh: bool,
+        chmod: Option<&str>,
+        cacheinfo: &[String],
+        assume_unchanged: Option<bool>,
+        skip_worktree: Option<bool>,
+        index_version: Option<u32>,
+        paths: &[PathBuf],
+    ) -> Result<bool, anyhow::Error> {
+        let executable = match chmod {
+            None => None,
+            Some("+x") => Some(true),
+            Some("-x") => Some(false),
+            Some(flag) => {
+                return Err(anyhow::anyhow!(
+                    "option 'chmod' expects \"+x\" or \"-x\", not \"{}\"",
+                    flag
+                ))
+            }
+        };
+
+        let paths = if paths.is_empty() {
+            Vec::new()
+        } else {
+            self.workspace_paths(cwd, paths)?
+        };
+
+        self.lock_index()?;
+        let mut clean = true;
+
+        if let Some(version) = index_version {
+            self.index.set_version(version)?;
+        }
+
+        if refresh {
+            clean = self.refresh_index()?;
+        }
+
+        for info in cacheinfo {
+            let mut parts = info.splitn(3, ',');
+            let (Some(mode), Some(oid), Some(path)) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(anyhow::anyhow!(
+                    "option 'cacheinfo' expects <mode>,<sha1>,<path>"
+                ));
+            };
+            let mode = u32::from_str_radix(mode, 8).map_err(|_| {
+                anyhow::anyhow!("git update-index: --cacheinfo cannot add {}", path)
+            })?;
+            let oid = Oid::parse(oid).ok_or_else(|| {
+                anyhow::anyhow!("git update-index: --cacheinfo cannot add {}", path)
+            })?;
+
+            if !self.index.contains(path) && !add {
+                return Err(anyhow::anyhow!(
+                    "{}: cannot add to the index - missing --add option?",
+                    path
+                ));
+            }
+            self.index.insert(IndexEntry::from_object(path, oid, mode)?);
+        }
+
+        for path in &paths {
+            let name = path
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("invalid path {:?}", path))?;
+            let workspace = work_tree(&self.workspace)?;
+
+            if assume_unchanged.is_some() || skip_worktree.is_some() {
+                let entry = self
+                    .index
+                    .get_mut(name)
+                    .ok_or_else(|| anyhow::anyhow!("Unable to mark file {}", name))?;
+                if let Some(value) = assume_unchanged {
+                    entry.set_assume_valid(value);
+                }
+                if let Some(value) = skip_worktree {
+                    entry.set_skip_worktree(value);
+                }
+                continue;
+            }
+
+            let metadata = match workspace.stat_file(path) {
+                Ok(metadata) if metadata.is_file() && !force_remove => Some(metadata),
+                Ok(metadata) if metadata.is_dir() && !force_remove => {
+                    return Err(anyhow::anyhow!(
+                        "{}: is a directory - add files inside instead",
+                        name
+                    ))
+                }
+                Ok(_) => None,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+
+            match metadata {
+                None if remove || force_remove => {
+                    self.index.remove(name);
+                    continue;
+                }
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "{}: does not exist and --remove not passed",
+                        name
+                    ))
+                }
+                Some(_) if !self.index.contains(name) && !add => {
+                    return Err(anyhow::anyhow!(
+                        "{}: cannot add to the index - missing --add option?",
+                        name
+                    ))
+                }
+                Some(metadata) => {
+                    let data = workspace.read_file(path)?;
+                    let oid = self.db.store_object(&mut Blob::new(data))?;
+                    self.index.add(path.clone(), oid, metadata)?;
+                }
+            }
+
+            if let Some(executable) = executable {
+                let entry = self.index.get_mut(name).unwrap();
+                entry.mode = if executable { 0o100755 } else { 0o100644 };
+            }
+        }
+
+        self.index.write_updates()?;
+        Ok(clean)
+    }
+
+    /// Re-stats every entry, recording fresh stat data for files whose
+    /// content is unchanged and reporting the ones that need updating.
+    fn refresh_index(&mut self) -> Result<bool, anyhow::Error> {
+        let workspace = work_tree(&self.workspace)?;
+        let mut clean = true;
+        let mut refreshed = Vec::new();
+
+        let mut previous = None;
+        for entry in self.index.entries.values() {
+            let first_stage = previous != Some(&entry.path);
+            previous = Some(&entry.path);
+
+            if entry.assume_valid() || entry.skip_worktree() {
+                continue;
+            }
+            let name = entry.path.to_str()?;
+            if entry.stage() > 0 {
+                if first_stage {
+                    println!("{}: needs merge", name);
+                    clean = false;
+                }
+                continue;
+            }
+            match self.file_matches_entry(entry)? {
+                Some(true) => {
+                    let metadata = workspace.stat_file(Path::new(name))?;
+                    if !entry.stat_matches(&metadata) {
+                        refreshed.push((name.to_string(), metadata));
+                    }
+                }
+                _ => {
+                    println!("{}: needs update", name);
+                    clean = false;
+                }
+            }
+        }
+
+        for (name, metadata) in refreshed {
+            if let Some(entry) = self.index.get_mut(&name) {
+                entry.update_stat(&metadata);
+            }
+        }
+
+        Ok(clean)
+    }
+
+    /// Writes index entries out as files: every entry not marked
+    /// skip-worktree with `all`, otherwise
+    /// the given paths. Files are written under `prefix` when given, which
+    /// is prepended as a plain string like Git does. Existing files that
+    /// differ are only replaced with `force`.
+    pub fn checkout_index(
+        &mut self,
+        cwd: &Path,
+        all: bool,
+        force: bool,
+        prefix: Option<&str>,
+        paths: &[PathBuf],
+    ) -> Result<(), anyhow::Error> {
+        let paths = self.workspace_paths(cwd, paths)?;
+        self.index.load()?;
+
+        let names: Vec<String> = if all {
+            self.index
+                .entries
+                .values()
+                .filter(|entry| !entry.skip_worktree() && entry.stage() == 0)
+                .map(|entry| Ok(entry.path.to_str()?.to_string()))
+                .collect::<Result<_, anyhow::Error>>()?
+        } else {
+            paths
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect()
+        };
+
+        let workspace = work_tree(&self.workspace)?;
+        let cwd_prefix = workspace.relative_path(cwd, Path::new(""))?;
+
+        for name in names {
+            let Some(entry) = self.index.get(&name) else {
+                if self.index.is_conflicted(&name) {
+                    eprintln!("oxigit checkout-index: {} is unmerged", name);
+                } else {
+                    eprintln!("oxigit checkout-index: {} is not in the cache", name);
+                }
+                continue;
+            };
+
+            let target = match prefix {
+                Some(prefix) => cwd_prefix.join(format!("{}{}", prefix, name)),
+                None => PathBuf::from(&name),
+            };
+
+            if workspace.root.join(&target).exists() && !force {
+                if prefix.is_none() && self.file_matches_entry(entry)? == Some(true) {
+                    continue;
+                }
+                eprintln!("oxigit checkout-index: {} already exists", target.display());
+                continue;
+            }
+
+            let (_, data) = self.db.read_raw(&entry.oid)?;
+            workspace.write_file(&target, &data, entry.mode)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn print_tree_entry(entry: &TreeEntry, path: &Path, name_only: bool) {
+    if name_only {
+        println!("{}", path.display());
+    } else {
+        println!(
+            "{:06o} {} {}\t{}",
+            entry.mode,
+            entry.kind(),
+            entry.oid,
+            path.display()
+        );
+    }
+}
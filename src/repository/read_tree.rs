@@ -0,0 +1,311 @@
+use std::{collections::BTreeMap, path::Path};
+
+use crate::oid::Oid;
+
+use super::index::{Index, IndexEntry};
+use super::revision::{peel_to, Revision};
+use super::{work_tree, Repository};
+
+/// Index or tree content by path: the object ID and mode of each file.
+pub(super) type Files = BTreeMap<String, (Oid, u32)>;
+
+/// Git's two-way merge: the index moves from tree `head` to tree `merge`,
+/// keeping local changes to paths the two trees agree on. A path changed
+/// between the trees must be unchanged in the index, or already be what
+/// `merge` wants.
+fn two_way_merge(index: &Files, head: &Files, merge: &Files) -> Result<Files, anyhow::Error> {
+    let mut result = Files::new();
+
+    for path in union_paths(&[index, head, merge]) {
+        let i = index.get(path);
+        let h = head.get(path);
+        let m = merge.get(path);
+
+        let chosen = if h == m || i == m {
+            i
+        } else if i == h {
+            m
+        } else {
+            return Err(would_overwrite(path));
+        };
+
+        if let Some(file) = chosen {
+            result.insert(path.to_string(), *file);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Git's trivial three-way merge of `ours` and `theirs` against `base`.
+/// A path resolves when both sides agree or exactly one side added or
+/// changed it; with `aggressive`, deletions on one or both sides resolve
+/// too. Everything else is returned as a conflict. The index must
+/// match `ours`, except that a path taken from `theirs` may already be
+/// what it wants.
+fn three_way_merge(
+    index: &Files,
+    base: &Files,
+    ours: &Files,
+    theirs: &Files,
+    aggressive: bool,
+) -> Result<(Files, Vec<String>), anyhow::Error> {
+    let mut result = Files::new();
+    let mut conflicts = Vec::new();
+
+    for path in union_paths(&[index, base, ours, theirs]) {
+        let i = index.get(path);
+        let o = base.get(path);
+        let a = ours.get(path);
+        let b = theirs.get(path);
+
+        // A side "matches" when it left the base alone, where a path
+        // missing from the base and the side counts as unchanged
+        let head_match = o == a;
+        let remote_match = o == b;
+        let removed_on_both = a.is_none() && b.is_none() && (o.is_none() || aggressive);
+        let removed_on_one = (a.is_none() && b.is_some() && remote_match)
+            || (b.is_none() && a.is_some() && head_match);
+
+        let chosen = if b.is_some() && head_match && !remote_match {
+            if i.is_some() && i != a && i != b {
+                return Err(would_overwrite(path));
+            }
+            b
+        } else if i.is_some() && i != a {
+            return Err(would_overwrite(path));
+        } else if a.is_some() && (a == b || (remote_match && !head_match)) {
+            a
+        } else if removed_on_both || (aggressive && removed_on_one) {
+            None
+        } else {
+            conflicts.push(path.to_string());
+            continue;
+        };
+
+        if let Some(file) = chosen {
+            result.insert(path.to_string(), *file);
+        }
+    }
+
+    Ok((result, conflicts))
+}
+
+fn would_overwrite(path: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Entry '{}' would be overwritten by merge. Cannot merge.",
+        path
+    )
+}
+
+pub(super) fn union_paths<'a>(maps: &[&'a Files]) -> Vec<&'a str> {
+    let mut paths: Vec<&str> = maps
+        .iter()
+        .flat_map(|map| map.keys().map(String::as_str))
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+impl Repository {
+    pub(super) fn read_tree_files(&self, treeish: &str) -> Result<Files, anyhow::Error> {
+        let tree = peel_to(
+            &self.db,
+            Revision::parse(treeish)?.resolve(&self.refs, &self.db)?,
+            "tree",
+        )?;
+
+        Ok(self
+            .db
+            .tree_files(&tree)?
+            .into_iter()
+            .map(|(path, entry)| (path, (entry.oid, entry.mode)))
+            .collect())
+    }
+
+    /// The files of the loaded index, for an index without conflict stages.
+    pub(super) fn index_files(&self) -> Result<Files, anyhow::Error> {
+        self.index
+            .entries
+            .values()
+            .map(|entry| Ok((entry.path.to_str()?.to_string(), (entry.oid, entry.mode))))
+            .collect()
+    }
+
+    /// Stores the tree holding `files`, built in a scratch index so the
+    /// real one is left alone, and returns its ID.
+    pub(super) fn write_files_tree(&self, files: &Files) -> Result<Oid, anyhow::Error> {
+        let mut index = Index::new(self.git_path.clone());
+        for (path, (oid, mode)) in files {
+            index.insert(IndexEntry::from_object(path, *oid, *mode)?);
+        }
+        index.write_tree(&self.db)
+    }
+
+    /// Reads `trees` into the index. Without `merge` the single tree
+    /// replaces the index. With `merge`, one tree replaces it while keeping
+    /// the stat data of unchanged entries, two trees do a two-way merge
+    /// from the first to the second, and three trees a three-way merge of
+    /// the last two against the first. `update` also brings the work tree
+    /// in line with the new index. Merges refuse to change entries whose
+    /// work tree file has local changes unless `index_only` is set.
+    /// `aggressive` lets three-way merges resolve removals as well. Paths a
+    /// three-way merge cannot resolve are recorded as conflict stages.
+    pub fn read_tree(
+        &mut self,
+        trees: &[String],
+        merge: bool,
+        update: bool,
+        index_only: bool,
+        aggressive: bool,
+    ) -> Result<(), anyhow::Error> {
+        if (update || index_only) && !merge {
+            return Err(anyhow::anyhow!("-u and -i are meaningless without -m"));
+        }
+        if update && index_only {
+            return Err(anyhow::anyhow!("-u and -i at the same time"));
+        }
+        let check_work_tree = merge && !index_only && self.workspace.is_some();
+
+        let trees: Vec<Files> = trees
+            .iter()
+            .map(|tree| self.read_tree_files(tree))
+            .collect::<Result<_, _>>()?;
+
+        self.lock_index()?;
+        if merge && self.index.conflicts().next().is_some() {
+            return Err(anyhow::anyhow!(
+                "You need to resolve your current index first"
+            ));
+        }
+
+        if !merge {
+            let [tree] = trees.as_slice() else {
+                return Err(anyhow::anyhow!(
+                    "read-tree reads a single tree unless merging with -m"
+                ));
+            };
+
+            // Without -m the index is replaced outright, stat data and all
+            self.index.clear();
+            for (path, (oid, mode)) in tree {
+                self.index
+                    .insert(IndexEntry::from_object(path, *oid, *mode)?);
+            }
+            self.index.write_updates()?;
+            return Ok(());
+        }
+
+        self.merge_into_index(&trees, update, check_work_tree, aggressive)?;
+        self.index.write_updates()?;
+        Ok(())
+    }
+
+    /// Merges `trees` into the locked index the way `read-tree -m` does and
+    /// returns the paths left with conflict stages. Removed and changed
+    /// files are updated in the work tree with `update`, after checking
+    /// that no local change is lost when `check_work_tree` is set. The
+    /// caller writes the index.
+    pub(super) fn merge_into_index(
+        &mut self,
+        trees: &[Files],
+        update: bool,
+        check_work_tree: bool,
+        aggressive: bool,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let current = self.index_files()?;
+
+        let (target, conflicts) = match trees {
+            [tree] => (tree.clone(), Vec::new()),
+            [head, next] => (two_way_merge(&current, head, next)?, Vec::new()),
+            [base, ours, theirs] => three_way_merge(&current, base, ours, theirs, aggressive)?,
+            _ => return Err(anyhow::anyhow!("read-tree -m takes one to three trees")),
+        };
+
+        // Conflicted paths keep their work tree file and get their stages
+        // recorded below
+        let changed: Vec<(&str, Option<&(Oid, u32)>)> = union_paths(&[&current, &target])
+            .into_iter()
+            .filter(|path| current.get(*path) != target.get(*path))
+            .filter(|path| !conflicts.iter().any(|conflict| conflict == path))
+            .map(|path| (path, target.get(path)))
+            .collect();
+
+        if check_work_tree {
+            self.check_work_tree_for_merge(&changed, update)?;
+        }
+        self.index.clear_resolve_undo();
+
+        // Removals go first so a file can turn into a directory and back
+        let (removed, written): (Vec<_>, Vec<_>) =
+            changed.into_iter().partition(|(_, file)| file.is_none());
+
+        for (path, _) in removed {
+            self.index.remove(path);
+            if update {
+                work_tree(&self.workspace)?.remove_file(Path::new(path))?;
+            }
+        }
+
+        for (path, file) in written {
+            let (oid, mode) = *file.unwrap();
+            let mut entry = IndexEntry::from_object(path, oid, mode)?;
+
+            if update {
+                let workspace = work_tree(&self.workspace)?;
+                let (_, data) = self.db.read_raw(&oid)?;
+                workspace.write_file(Path::new(path), &data, mode)?;
+                entry.update_stat(&workspace.stat_file(Path::new(path))?);
+            }
+
+            self.index.insert(entry);
+        }
+
+        for path in &conflicts {
+            for (stage, tree) in (1..).zip(trees) {
+                if let Some((oid, mode)) = tree.get(path) {
+                    let mut entry = IndexEntry::from_object(path, *oid, *mode)?;
+                    entry.set_stage(stage);
+                    self.index.insert(entry);
+                }
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    /// Refuses a merge that would lose local changes or, when `update`
+    /// writes the work tree, overwrite untracked files.
+    fn check_work_tree_for_merge(
+        &self,
+        changed: &[(&str, Option<&(Oid, u32)>)],
+        update: bool,
+    ) -> Result<(), anyhow::Error> {
+        let workspace = work_tree(&self.workspace)?;
+
+        for (path, file) in changed {
+            if let Some(entry) = self.index.get(path) {
+                if self.file_matches_entry(entry)? == Some(false) {
+                    return Err(anyhow::anyhow!(
+                        "Entry '{}' not uptodate. Cannot merge.",
+                        path
+                    ));
+                }
+            } else if update {
+                let Ok(data) = workspace.read_file(Path::new(path)) else {
+                    continue;
+                };
+                let wanted = file.map(|(oid, _)| *oid);
+                if wanted != Some(self.db.hash_object(b"blob", &data, false)?) {
+                    return Err(anyhow::anyhow!(
+                        "Untracked working tree file '{}' would be overwritten by merge.",
+                        path
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
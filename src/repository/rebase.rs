@@ -0,0 +1,1034 @@
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::oid::Oid;
+
+use super::graph::{is_ancestor, range};
+use super::merge::{conflicts_message, strip_comments};
+use super::object::commit::{Author, Commit};
+use super::sequencer::{Action, Merged};
+use super::{work_tree, Repository};
+
+const REBASE_MERGE: &str = "rebase-merge";
+const REBASE_HEAD: &str = "REBASE_HEAD";
+const TODO: &str = "git-rebase-todo";
+
+/// Files in the rebase directory describing a stop, removed once it is
+/// resolved.
+const STOP_STATE: [&str; 4] = ["stopped-sha", "message", "author-script", "amend"];
+
+/// The help below the commands of the todo list opened for editing.
+const TODO_HELP: &str = "\
+#
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\", but discard this commit's log message
+# x, exec <command> = run command (the rest of the line) using shell
+# b, break = stop here (continue rebase later with 'git rebase --continue')
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+#
+";
+
+/// The help below a commit message opened for editing.
+const EDIT_HELP: &str = "
+# Please enter the commit message for your changes. Lines starting
+# with '#' will be ignored, and an empty message aborts the commit.
+";
+
+/// A todo list command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TodoCommand {
+    Pick,
+    /// Pick, then edit the commit message.
+    Reword,
+    /// Pick, then stop so the commit can be amended.
+    Edit,
+    /// Meld into the previous commit, combining the messages.
+    Squash,
+    /// Meld into the previous commit, keeping its message.
+    Fixup,
+    Drop,
+    /// Run a shell command, stopping if it fails.
+    Exec,
+    /// Stop, to go on with `--continue`.
+    Break,
+}
+
+impl TodoCommand {
+    fn parse(word: &str) -> Option<Self> {
+        Some(match word {
+            "p" | "pick" => TodoCommand::Pick,
+            "r" | "reword" => TodoCommand::Reword,
+            "e" | "edit" => TodoCommand::Edit,
+            "s" | "squash" => TodoCommand::Squash,
+            "f" | "fixup" => TodoCommand::Fixup,
+            "d" | "drop" => TodoCommand::Drop,
+            "x" | "exec" => TodoCommand::Exec,
+            "b" | "break" => TodoCommand::Break,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TodoCommand::Pick => "pick",
+            TodoCommand::Reword => "reword",
+            TodoCommand::Edit => "edit",
+            TodoCommand::Squash => "squash",
+            TodoCommand::Fixup => "fixup",
+            TodoCommand::Drop => "drop",
+            TodoCommand::Exec => "exec",
+            TodoCommand::Break => "break",
+        }
+    }
+
+    fn melds(self) -> bool {
+        matches!(self, TodoCommand::Squash | TodoCommand::Fixup)
+    }
+}
+
+/// A line of the todo list.
+struct Step {
+    command: TodoCommand,
+    /// The commit the command uses, if it takes one.
+    commit: Option<Oid>,
+    /// The subject following the commit, or the shell command of `exec`.
+    rest: String,
+}
+
+impl Step {
+    fn pick(commit: Oid, subject: &str) -> Self {
+        Self {
+            command: TodoCommand::Pick,
+            commit: Some(commit),
+            rest: subject.to_string(),
+        }
+    }
+
+    /// The line for the todo list, naming the commit in full or, for the
+    /// list the user edits, abbreviated.
+    fn line(&self, abbreviate: bool) -> String {
+        let mut line = self.command.name().to_string();
+        if let Some(commit) = self.commit {
+            line.push(' ');
+            line.push_str(&if abbreviate {
+                commit.to_short()
+            } else {
+                commit.to_string()
+            });
+        }
+        if !self.rest.is_empty() {
+            line.push(' ');
+            line.push_str(&self.rest);
+        }
+        line
+    }
+}
+
+/// Splits `fixup! ` or `squash! ` off a subject, with the command it asks
+/// `--autosquash` for.
+fn fixupish(subject: &str) -> Option<(TodoCommand, &str)> {
+    if let Some(rest) = subject.strip_prefix("fixup! ") {
+        Some((TodoCommand::Fixup, rest))
+    } else {
+        subject
+            .strip_prefix("squash! ")
+            .map(|rest| (TodoCommand::Squash, rest))
+    }
+}
+
+/// `message` turned into comment lines, except for a body following a
+/// `squash! ` or `fixup! ` subject when `keep_body` is set.
+fn comment_out(message: &str, keep_body: bool) -> String {
+    let mut result = String::new();
+    for (i, line) in message.trim_end().lines().enumerate() {
+        if i > 0 && keep_body {
+            result.push_str(line);
+        } else if line.is_empty() {
+            result.push('#');
+        } else {
+            result.push_str("# ");
+            result.push_str(line);
+        }
+        result.push('\n');
+    }
+    result
+}
+
+fn subject(message: &str) -> &str {
+    message.lines().next().unwrap_or_default()
+}
+
+/// The author line values Git keeps in `author-script`, quoted for the
+/// shell.
+fn author_script(author: &Author) -> String {
+    let quote = |value: &str| format!("'{}'", value.replace('\'', "'\\''"));
+    format!(
+        "GIT_AUTHOR_NAME={}\nGIT_AUTHOR_EMAIL={}\nGIT_AUTHOR_DATE={}\n",
+        quote(author.name()),
+        quote(author.email()),
+        quote(&format!(
+            "@{} {}",
+            author.time().timestamp(),
+            author.time().format("%z")
+        )),
+    )
+}
+
+fn parse_author_script(script: &str) -> Result<Author, anyhow::Error> {
+    let mut values = HashMap::new();
+    for line in script.lines() {
+        let Some((name, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value
+            .strip_prefix('\'')
+            .and_then(|value| value.strip_suffix('\''))
+            .unwrap_or(value)
+            .replace("'\\''", "'");
+        values.insert(name, value);
+    }
+
+    let value = |name: &str| {
+        values
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("missing {} in author-script", name))
+    };
+    let date = value("GIT_AUTHOR_DATE")?;
+    Author::parse(&format!(
+        "{} <{}> {}",
+        value("GIT_AUTHOR_NAME")?,
+        value("GIT_AUTHOR_EMAIL")?,
+        date.strip_prefix('@').unwrap_or(date)
+    ))
+}
+
+impl Repository {
+    fn rebase_path(&self) -> PathBuf {
+        self.git_path.join(REBASE_MERGE)
+    }
+
+    fn read_rebase_state(&self, name: &str) -> Result<Option<String>, anyhow::Error> {
+        match fs::read_to_string(self.rebase_path().join(name)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn rebase_state_oid(&self, name: &str) -> Result<Oid, anyhow::Error> {
+        let content = self.read_rebase_state(name)?.unwrap_or_default();
+        Oid::parse(content.trim()).ok_or_else(|| {
+            anyhow::anyhow!(
+                "could not read '{}'",
+                self.rebase_path().join(name).display()
+            )
+        })
+    }
+
+    fn write_rebase_state(&self, name: &str, content: &str) -> Result<(), anyhow::Error> {
+        fs::write(self.rebase_path().join(name), content)?;
+        Ok(())
+    }
+
+    fn remove_rebase_state(&self, names: &[&str]) -> Result<(), anyhow::Error> {
+        for name in names {
+            match fs::remove_file(self.rebase_path().join(name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_todo(&self, content: &str) -> Result<Vec<Step>, anyhow::Error> {
+        let mut steps = Vec::new();
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let command = TodoCommand::parse(word)
+                .ok_or_else(|| anyhow::anyhow!("invalid command '{}' in todo list", word))?;
+            let rest = rest.trim_start();
+
+            let step = match command {
+                TodoCommand::Exec if rest.is_empty() => {
+                    return Err(anyhow::anyhow!("missing command after exec: {}", line))
+                }
+                TodoCommand::Exec | TodoCommand::Break => Step {
+                    command,
+                    commit: None,
+                    rest: rest.to_string(),
+                },
+                _ => {
+                    let (rev, subject) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    if rev.is_empty() {
+                        return Err(anyhow::anyhow!("missing commit in todo list: {}", line));
+                    }
+                    Step {
+                        command,
+                        commit: Some(self.resolve_commit(rev)?),
+                        rest: subject.trim_start().to_string(),
+                    }
+                }
+            };
+            steps.push(step);
+        }
+
+        Ok(steps)
+    }
+
+    fn read_rebase_todo(&self) -> Result<Vec<Step>, anyhow::Error> {
+        self.parse_todo(&self.read_rebase_state(TODO)?.unwrap_or_default())
+    }
+
+    fn write_rebase_todo(&self, steps: &[Step]) -> Result<(), anyhow::Error> {
+        let todo: String = steps
+            .iter()
+            .map(|step| format!("{}\n", step.line(false)))
+            .collect();
+        self.write_rebase_state(TODO, &todo)
+    }
+
+    /// Moves `fixup! ` and `squash! ` commits right after the commit their
+    /// subject names, by subject, commit name or subject prefix, turning
+    /// them into fixups and squashes as Git's `--autosquash` does.
+    fn autosquash(&self, steps: Vec<Step>) -> Vec<Step> {
+        let count = steps.len();
+        let mut next: Vec<Option<usize>> = vec![None; count];
+        let mut tail: Vec<Option<usize>> = vec![None; count];
+        let mut moved = vec![false; count];
+        let mut commands: Vec<TodoCommand> = steps.iter().map(|step| step.command).collect();
+        let mut subjects: HashMap<&str, usize> = HashMap::new();
+
+        for i in 0..count {
+            let subject = steps[i].rest.as_str();
+            let mut target = None;
+
+            if let Some((command, mut wanted)) = fixupish(subject) {
+                // A fixup of a fixup goes to the commit they both fix
+                while let Some((_, rest)) = fixupish(wanted.trim_start()) {
+                    wanted = rest;
+                }
+                let wanted = wanted.trim_start();
+
+                target = subjects
+                    .get(wanted)
+                    .copied()
+                    .or_else(|| {
+                        if wanted.contains(' ') {
+                            return None;
+                        }
+                        let oid = self.resolve_commit(wanted).ok()?;
+                        (0..i).find(|&j| steps[j].commit == Some(oid))
+                    })
+                    .or_else(|| (0..i).find(|&j| steps[j].rest.starts_with(wanted)));
+
+                if let Some(j) = target {
+                    commands[i] = command;
+                    moved[i] = true;
+                    let after = tail[j].unwrap_or(j);
+                    next[i] = next[after];
+                    next[after] = Some(i);
+                    tail[j] = Some(i);
+                }
+            }
+            if target.is_none() {
+                subjects.entry(subject).or_insert(i);
+            }
+        }
+
+        let mut order = Vec::new();
+        for i in (0..count).filter(|&i| !moved[i]) {
+            let mut item = Some(i);
+            while let Some(i) = item {
+                order.push(i);
+                item = next[i];
+            }
+        }
+
+        let mut steps: Vec<Option<Step>> = steps.into_iter().map(Some).collect();
+        order
+            .into_iter()
+            .map(|i| {
+                let mut step = steps[i].take().unwrap();
+                step.command = commands[i];
+                step
+            })
+            .collect()
+    }
+
+    /// The editor Git would start: `$GIT_EDITOR`, `core.editor`, `$VISUAL`,
+    /// `$EDITOR`, then vi.
+    fn editor(&self) -> String {
+        env::var("GIT_EDITOR")
+            .ok()
+            .or_else(|| self.config.get("core.editor").map(str::to_string))
+            .or_else(|| env::var("VISUAL").ok())
+            .or_else(|| env::var("EDITOR").ok())
+            .unwrap_or_else(|| "vi".to_string())
+    }
+
+    /// The editor for the todo list: `$GIT_SEQUENCE_EDITOR` or
+    /// `sequence.editor`, else the usual one.
+    fn sequence_editor(&self) -> String {
+        env::var("GIT_SEQUENCE_EDITOR")
+            .ok()
+            .or_else(|| self.config.get("sequence.editor").map(str::to_string))
+            .unwrap_or_else(|| self.editor())
+    }
+
+    /// Runs `editor` on `path` through the shell, as Git does, so the
+    /// editor may come with arguments.
+    fn run_editor(&self, editor: &str, path: &Path) -> Result<(), anyhow::Error> {
+        if editor == ":" {
+            return Ok(());
+        }
+
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", editor))
+            .arg(editor)
+            .arg(path)
+            .current_dir(&work_tree(&self.workspace)?.root)
+            .status()?;
+        if !status.success() {
+            return Err(anyhow::anyhow!(
+                "there was a problem with the editor '{}'",
+                editor
+            ));
+        }
+        Ok(())
+    }
+
+    /// Lets the user edit `message` as the commit message.
+    fn edit_message(&self, message: &str) -> Result<String, anyhow::Error> {
+        let path = self.git_path.join("COMMIT_EDITMSG");
+        fs::write(&path, format!("{}{}", message, EDIT_HELP))?;
+        self.run_editor(&self.editor(), &path)?;
+
+        let message = strip_comments(&fs::read_to_string(&path)?);
+        if message.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Aborting commit due to empty commit message."
+            ));
+        }
+        Ok(message)
+    }
+
+    /// Rebases the current branch, or `branch`, onto `onto` or else
+    /// `upstream`: its commits not in `upstream` are picked one by one on
+    /// top of it, with HEAD detached, and the branch is moved to the result
+    /// at the end. With `interactive`, the todo list of commits to pick is
+    /// first opened in the sequence editor, after `autosquash` has moved
+    /// fixups next to their commits. The state is kept in
+    /// `.git/rebase-merge` as Git does, so a rebase stopped by a conflict,
+    /// an `edit` or a failed `exec` goes on with [`Repository::rebase_continue`].
+    /// Returns `false` when the rebase stopped for the user.
+    pub fn rebase(
+        &mut self,
+        upstream: Option<&str>,
+        onto: Option<&str>,
+        branch: Option<&str>,
+        interactive: bool,
+        autosquash: bool,
+    ) -> Result<bool, anyhow::Error> {
+        work_tree(&self.workspace)?;
+        if self.rebase_path().exists() {
+            return Err(anyhow::anyhow!(
+                "It seems that there is already a rebase-merge directory, and\n\
+                 I wonder if you are in the middle of another rebase.  If that is the\n\
+                 case, please try\n\
+                 \tgit rebase (--continue | --abort | --skip)\n\
+                 If that is not the case, please\n\
+                 \trm -fr \"{}\"\n\
+                 and run me again.  I am stopping in case you still have something\n\
+                 valuable there.",
+                self.rebase_path().display()
+            ));
+        }
+        let upstream_name = upstream.ok_or_else(|| {
+            anyhow::anyhow!(
+                "There is no tracking information for the current branch.\n\
+                 Please specify which branch you want to rebase against."
+            )
+        })?;
+
+        let upstream = self.resolve_commit(upstream_name)?;
+        let onto_name = onto.unwrap_or(upstream_name);
+        let onto = self.resolve_commit(onto_name)?;
+        let head = self
+            .refs
+            .read_ref("HEAD")?
+            .ok_or_else(|| anyhow::anyhow!("your current branch does not have any commits yet"))?;
+        let (head_name, orig_head) = match branch {
+            Some(branch) => match self.refs.read_ref(&format!("refs/heads/{}", branch))? {
+                Some(oid) => (format!("refs/heads/{}", branch), oid),
+                None => ("detached HEAD".to_string(), self.resolve_commit(branch)?),
+            },
+            None => match self.refs.current_ref()?.as_str() {
+                "HEAD" => ("detached HEAD".to_string(), head),
+                name => (name.to_string(), head),
+            },
+        };
+
+        self.index.load()?;
+        if self.index.conflicts().next().is_some() {
+            return Err(anyhow::anyhow!(
+                "cannot rebase: You have unmerged files.\nPlease commit or stash them."
+            ));
+        }
+        for entry in self.index.entries.values() {
+            if self.file_matches_entry(entry)? == Some(false) {
+                return Err(anyhow::anyhow!(
+                    "cannot rebase: You have unstaged changes.\nPlease commit or stash them."
+                ));
+            }
+        }
+        let head_files = self.commit_files(&head)?;
+        if self.index_files()? != head_files {
+            return Err(anyhow::anyhow!(
+                "cannot rebase: Your index contains uncommitted changes.\n\
+                 Please commit or stash them."
+            ));
+        }
+
+        let mut commits = range(&self.db, &upstream, &orig_head)?;
+        commits.reverse();
+        let mut steps = Vec::new();
+        let mut merges = false;
+        for commit in commits {
+            let message = self.db.read_commit(&commit)?.message().to_string();
+            if self.db.read_commit(&commit)?.parents().len() > 1 {
+                merges = true;
+                continue;
+            }
+            steps.push(Step::pick(commit, subject(&message)));
+        }
+
+        let committer = self.committer();
+        if !interactive
+            && !autosquash
+            && !merges
+            && is_ancestor(&self.db, &onto, &orig_head)?
+            && is_ancestor(&self.db, &upstream, &onto)?
+        {
+            if orig_head != head || self.refs.current_ref()? != head_name {
+                self.lock_index()?;
+                self.merge_into_index(
+                    &[head_files, self.commit_files(&orig_head)?],
+                    true,
+                    true,
+                    false,
+                )?;
+                self.index.write_updates()?;
+                match head_name.strip_prefix("refs/") {
+                    Some(_) => self.refs.attach_head(
+                        &head_name,
+                        &committer,
+                        &format!("rebase: checkout {}", branch.unwrap_or_default()),
+                    )?,
+                    None => self.refs.detach_head(
+                        &orig_head,
+                        &committer,
+                        &format!("rebase: checkout {}", branch.unwrap_or_default()),
+                    )?,
+                }
+            }
+            let name = head_name.strip_prefix("refs/heads/").unwrap_or("HEAD");
+            println!("Current branch {} is up to date.", name);
+            return Ok(true);
+        }
+
+        fs::create_dir(self.rebase_path())?;
+        if interactive {
+            self.write_rebase_state("interactive", "")?;
+            if autosquash {
+                steps = self.autosquash(steps);
+            }
+
+            let mut todo: String = steps
+                .iter()
+                .map(|step| format!("{}\n", step.line(true)))
+                .collect();
+            todo.push_str(&format!(
+                "\n# Rebase {}..{} onto {} ({} command{})\n",
+                upstream.to_short(),
+                orig_head.to_short(),
+                onto.to_short(),
+                steps.len(),
+                if steps.len() == 1 { "" } else { "s" }
+            ));
+            todo.push_str(TODO_HELP);
+            self.write_rebase_state(TODO, &todo)?;
+
+            let edited = self
+                .run_editor(&self.sequence_editor(), &self.rebase_path().join(TODO))
+                .and_then(|_| self.read_rebase_todo());
+            steps = match edited {
+                Ok(steps) if !steps.is_empty() => steps,
+                Ok(_) => {
+                    fs::remove_dir_all(self.rebase_path())?;
+                    return Err(anyhow::anyhow!("nothing to do"));
+                }
+                Err(e) => {
+                    fs::remove_dir_all(self.rebase_path())?;
+                    return Err(e);
+                }
+            };
+        }
+
+        self.write_rebase_state("head-name", &format!("{}\n", head_name))?;
+        self.write_rebase_state("onto", &format!("{}\n", onto))?;
+        self.write_rebase_state("orig-head", &format!("{}\n", orig_head))?;
+        self.write_rebase_state("end", &format!("{}\n", steps.len()))?;
+
+        // Leading picks already on top of onto need no new commits, so the
+        // rebase starts from the last of them
+        let mut start = onto;
+        let mut done = String::new();
+        let mut skipped = 0;
+        for step in &steps {
+            let Some(commit) = step.commit.filter(|_| step.command == TodoCommand::Pick) else {
+                break;
+            };
+            if self.db.read_commit(&commit)?.parents() != [start] {
+                break;
+            }
+            start = commit;
+            done.push_str(&format!("{}\n", step.line(false)));
+            skipped += 1;
+        }
+        self.write_rebase_state("msgnum", &format!("{}\n", skipped))?;
+        self.write_rebase_state("done", &done)?;
+        self.write_rebase_todo(&steps[skipped..])?;
+
+        self.lock_index()?;
+        self.merge_into_index(&[head_files, self.commit_files(&start)?], true, true, false)?;
+        self.index.write_updates()?;
+        self.refs.detach_head(
+            &start,
+            &committer,
+            &format!("rebase (start): checkout {}", onto_name),
+        )?;
+
+        self.rebase_run()
+    }
+
+    /// Works through the todo list until it is done or a step stops.
+    fn rebase_run(&mut self) -> Result<bool, anyhow::Error> {
+        loop {
+            let mut todo = self.read_rebase_todo()?;
+            if todo.is_empty() {
+                return self.rebase_finish();
+            }
+            let step = todo.remove(0);
+
+            let mut done = self.read_rebase_state("done")?.unwrap_or_default();
+            done.push_str(&format!("{}\n", step.line(false)));
+            self.write_rebase_state("done", &done)?;
+            self.write_rebase_todo(&todo)?;
+            let msgnum: usize = self
+                .read_rebase_state("msgnum")?
+                .and_then(|n| n.trim().parse().ok())
+                .unwrap_or(0);
+            self.write_rebase_state("msgnum", &format!("{}\n", msgnum + 1))?;
+
+            match step.command {
+                TodoCommand::Drop => {}
+                TodoCommand::Break => return Ok(true),
+                TodoCommand::Exec => {
+                    eprintln!("Executing: {}", step.rest);
+                    let status = Command::new("sh")
+                        .arg("-c")
+                        .arg(&step.rest)
+                        .current_dir(&work_tree(&self.workspace)?.root)
+                        .status()?;
+                    if !status.success() {
+                        eprintln!("warning: execution failed: {}", step.rest);
+                        eprintln!("You can fix the problem, and then run\n");
+                        eprintln!("  git rebase --continue\n\n");
+                        return Ok(false);
+                    }
+                }
+                _ => {
+                    let chain_ends = todo.first().is_none_or(|next| !next.command.melds());
+                    if !self.rebase_pick(&step, chain_ends)? {
+                        return Ok(false);
+                    }
+                    if step.command == TodoCommand::Edit {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Applies the commit of `step` on top of HEAD. A conflict stops the
+    /// rebase, as does an `edit` once its commit is made; `false` is
+    /// returned for a conflict.
+    fn rebase_pick(&mut self, step: &Step, chain_ends: bool) -> Result<bool, anyhow::Error> {
+        let commit_oid = step.commit.unwrap();
+        let commit = self.db.read_commit(&commit_oid)?;
+        let Merged {
+            head,
+            tree,
+            empty,
+            unresolved,
+            author,
+            message,
+        } = self.merge_commit(Action::Pick, &commit_oid)?;
+
+        let Some(tree) = tree else {
+            let short = commit_oid.to_short();
+            self.write_rebase_state("stopped-sha", &format!("{}\n", commit_oid))?;
+            self.write_rebase_state("message", &message)?;
+            self.write_rebase_state("author-script", &author_script(&author))?;
+            fs::write(self.git_path.join(REBASE_HEAD), format!("{}\n", commit_oid))?;
+            fs::write(
+                self.git_path.join("MERGE_MSG"),
+                conflicts_message(&message, &unresolved),
+            )?;
+
+            let subject = subject(commit.message());
+            eprintln!("error: could not apply {}... {}", short, subject);
+            eprintln!("hint: Resolve all conflicts manually, mark them as resolved with");
+            eprintln!(
+                "hint: \"git add/rm <conflicted_files>\", then run \"git rebase --continue\"."
+            );
+            eprintln!("hint: You can instead skip this commit: run \"git rebase --skip\".");
+            eprintln!(
+                "hint: To abort and get back to the state before \"git rebase\", \
+                 run \"git rebase --abort\"."
+            );
+            eprintln!("Could not apply {}... {}", short, subject);
+            return Ok(false);
+        };
+
+        // Commits whose changes are upstream already are dropped
+        if empty && !step.command.melds() {
+            return Ok(true);
+        }
+
+        // A commit already on top of HEAD is kept as it is
+        let unchanged = commit.parents() == [head] && tree == commit.tree();
+        let new = if unchanged && matches!(step.command, TodoCommand::Pick | TodoCommand::Edit) {
+            self.refs.set_head(
+                &commit_oid,
+                Some(head),
+                &self.committer(),
+                &format!("rebase ({}): {}", step.command.name(), subject(&message)),
+            )?;
+            commit_oid
+        } else {
+            self.rebase_commit(
+                step,
+                tree,
+                head,
+                author,
+                message,
+                chain_ends,
+                step.command.name(),
+            )?
+        };
+
+        if step.command == TodoCommand::Edit {
+            self.write_rebase_state("stopped-sha", &format!("{}\n", commit_oid))?;
+            self.write_rebase_state("amend", &format!("{}\n", new))?;
+            eprintln!(
+                "Stopped at {}...  {}",
+                commit_oid.to_short(),
+                subject(commit.message())
+            );
+            eprintln!("You can amend the commit now by staging your changes.\n");
+            eprintln!("Once you are satisfied with your changes, run\n");
+            eprintln!("  git rebase --continue");
+        }
+        Ok(true)
+    }
+
+    /// Commits `tree` for `step` on top of `head`, or melds it into `head`
+    /// for a squash or fixup. The messages of a chain of squashes and
+    /// fixups are combined as they come, and the combination is opened in
+    /// the editor at the end of a chain that has a squash. The reflog
+    /// names `action`.
+    #[allow(clippy::too_many_arguments)]
+    fn rebase_commit(
+        &mut self,
+        step: &Step,
+        tree: Oid,
+        head: Oid,
+        author: Author,
+        message: String,
+        chain_ends: bool,
+        action: &str,
+    ) -> Result<Oid, anyhow::Error> {
+        let committer = self.committer();
+
+        let (parents, author, message) = if step.command.melds() {
+            let head_commit = self.db.read_commit(&head)?;
+            let mut fixups = self
+                .read_rebase_state("current-fixups")?
+                .unwrap_or_default();
+            let mut combined = match self.read_rebase_state("message-squash")? {
+                Some(combined) => combined,
+                None => format!(
+                    "# This is the 1st commit message:\n\n{}",
+                    head_commit.message()
+                ),
+            };
+
+            fixups.push_str(&format!(
+                "{} {}\n",
+                step.command.name(),
+                step.commit.unwrap()
+            ));
+            let count = fixups.lines().count() + 1;
+            match step.command {
+                TodoCommand::Squash => combined.push_str(&format!(
+                    "\n# This is the commit message #{}:\n\n{}",
+                    count,
+                    match fixupish(&message) {
+                        Some(_) => comment_out(&message, true),
+                        None => message.clone(),
+                    }
+                )),
+                _ => combined.push_str(&format!(
+                    "\n# The commit message #{} will be skipped:\n\n{}",
+                    count,
+                    comment_out(&message, false)
+                )),
+            }
+            let full = format!(
+                "# This is a combination of {} commits.\n{}",
+                count, combined
+            );
+
+            let message = if chain_ends {
+                let squashed = fixups.lines().any(|line| line.starts_with("squash"));
+                self.remove_rebase_state(&["current-fixups", "message-squash"])?;
+                if squashed {
+                    self.edit_message(&full)?
+                } else {
+                    strip_comments(&full)
+                }
+            } else {
+                self.write_rebase_state("current-fixups", &fixups)?;
+                self.write_rebase_state("message-squash", &combined)?;
+                full
+            };
+            (
+                head_commit.parents().to_vec(),
+                head_commit.author().clone(),
+                message,
+            )
+        } else if step.command == TodoCommand::Reword {
+            (vec![head], author, self.edit_message(&message)?)
+        } else {
+            (vec![head], author, message)
+        };
+
+        let mut commit = Commit::new(tree, parents, author, committer.clone(), message);
+        let oid = self.db.store_object(&mut commit)?;
+        self.refs.set_head(
+            &oid,
+            Some(head),
+            &committer,
+            &format!("rebase ({}): {}", action, subject(commit.message())),
+        )?;
+        Ok(oid)
+    }
+
+    /// Moves the rebased branch to HEAD and checks it out again, unless the
+    /// branch no longer points where the rebase started.
+    fn rebase_finish(&mut self) -> Result<bool, anyhow::Error> {
+        let head_name = self
+            .read_rebase_state("head-name")?
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let onto = self.rebase_state_oid("onto")?;
+        let orig_head = self.rebase_state_oid("orig-head")?;
+        let head = self
+            .refs
+            .read_ref("HEAD")?
+            .ok_or_else(|| anyhow::anyhow!("HEAD does not point at a commit"))?;
+        let committer = self.committer();
+
+        if head_name.starts_with("refs/") {
+            self.refs.update_ref(
+                &head_name,
+                &head,
+                Some(orig_head),
+                &committer,
+                &format!("rebase (finish): {} onto {}", head_name, onto),
+            )?;
+            self.refs.attach_head(
+                &head_name,
+                &committer,
+                &format!("rebase (finish): returning to {}", head_name),
+            )?;
+        }
+
+        fs::remove_dir_all(self.rebase_path())?;
+        self.remove_rebase_head()?;
+        eprintln!("Successfully rebased and updated {}.", head_name);
+        Ok(true)
+    }
+
+    fn remove_rebase_head(&self) -> Result<(), anyhow::Error> {
+        match fs::remove_file(self.git_path.join(REBASE_HEAD)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_rebase_in_progress(&self) -> Result<(), anyhow::Error> {
+        work_tree(&self.workspace)?;
+        if !self.rebase_path().exists() {
+            return Err(anyhow::anyhow!("No rebase in progress?"));
+        }
+        Ok(())
+    }
+
+    /// Goes on with a stopped rebase. After a conflict the resolved index
+    /// is committed with the message and author of the commit that was
+    /// being applied; after an `edit`, staged changes amend the commit
+    /// made for it.
+    pub fn rebase_continue(&mut self) -> Result<bool, anyhow::Error> {
+        self.check_rebase_in_progress()?;
+
+        self.index.load()?;
+        if self.index.conflicts().next().is_some() {
+            return Err(anyhow::anyhow!(
+                "you must edit all merge conflicts and then\n\
+                 mark them as resolved using git add"
+            ));
+        }
+        let head = self
+            .refs
+            .read_ref("HEAD")?
+            .ok_or_else(|| anyhow::anyhow!("HEAD does not point at a commit"))?;
+        let changed = self.index_files()? != self.commit_files(&head)?;
+        let uncommitted = || {
+            anyhow::anyhow!(
+                "You have uncommitted changes in your working tree. Please, commit them\n\
+                 first and then run 'git rebase --continue' again."
+            )
+        };
+
+        if let Some(amend) = self.read_rebase_state("amend")? {
+            if changed {
+                if Oid::parse(amend.trim()) != Some(head) {
+                    return Err(uncommitted());
+                }
+                let head_commit = self.db.read_commit(&head)?;
+                let tree = self.write_tree()?;
+                let committer = self.committer();
+                let mut commit = Commit::new(
+                    tree,
+                    head_commit.parents().to_vec(),
+                    head_commit.author().clone(),
+                    committer.clone(),
+                    head_commit.message().to_string(),
+                );
+                let oid = self.db.store_object(&mut commit)?;
+                self.refs.set_head(
+                    &oid,
+                    Some(head),
+                    &committer,
+                    &format!("rebase (continue): {}", subject(commit.message())),
+                )?;
+            }
+        } else if let Some(message) = self.read_rebase_state("message")? {
+            // The step that stopped is the last one done
+            let done = self.read_rebase_state("done")?.unwrap_or_default();
+            let step = self
+                .parse_todo(done.lines().last().unwrap_or_default())?
+                .pop()
+                .ok_or_else(|| anyhow::anyhow!("could not read the last step done"))?;
+
+            if changed || step.command.melds() {
+                let author = parse_author_script(
+                    &self.read_rebase_state("author-script")?.unwrap_or_default(),
+                )?;
+                let chain_ends = self
+                    .read_rebase_todo()?
+                    .first()
+                    .is_none_or(|next| !next.command.melds());
+                let tree = self.write_tree()?;
+                self.rebase_commit(&step, tree, head, author, message, chain_ends, "continue")?;
+            }
+        } else if changed {
+            return Err(uncommitted());
+        }
+
+        self.remove_rebase_state(&STOP_STATE)?;
+        self.remove_rebase_head()?;
+        self.clear_pick_state()?;
+        self.rebase_run()
+    }
+
+    /// Drops the commit a rebase stopped at, with whatever it left in the
+    /// index and the work tree, and goes on with the rest.
+    pub fn rebase_skip(&mut self) -> Result<bool, anyhow::Error> {
+        self.check_rebase_in_progress()?;
+        let head = self
+            .refs
+            .read_ref("HEAD")?
+            .ok_or_else(|| anyhow::anyhow!("HEAD does not point at a commit"))?;
+
+        self.reset_merge(&head)?;
+        self.remove_rebase_state(&STOP_STATE)?;
+        self.remove_rebase_head()?;
+        self.clear_pick_state()?;
+        self.rebase_run()
+    }
+
+    /// Gives up a rebase: the branch is checked out again as it was before,
+    /// with the index and the work tree.
+    pub fn rebase_abort(&mut self) -> Result<(), anyhow::Error> {
+        self.check_rebase_in_progress()?;
+        let head_name = self
+            .read_rebase_state("head-name")?
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let orig_head = self.rebase_state_oid("orig-head")?;
+
+        self.reset_merge(&orig_head)?;
+        let committer = self.committer();
+        if head_name.starts_with("refs/") {
+            self.refs.attach_head(
+                &head_name,
+                &committer,
+                &format!("rebase (abort): returning to {}", head_name),
+            )?;
+        } else {
+            self.refs.detach_head(
+                &orig_head,
+                &committer,
+                &format!("rebase (abort): returning to {}", orig_head),
+            )?;
+        }
+
+        fs::remove_dir_all(self.rebase_path())?;
+        self.remove_rebase_head()?;
+        self.clear_pick_state()
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use walkdir::WalkDir;
+
+use crate::lockfile::Lockfile;
+use crate::oid::Oid;
+
+use super::object::commit::Author;
+
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: Oid,
+    pub new: Oid,
+    pub committer: Author,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    pub fn new(old: Oid, new: Oid, committer: Author, message: &str) -> Self {
+        // Reflog entries are single lines, so only keep the first line
+        let message = message.lines().next().unwrap_or_default().to_string();
+
+        Self {
+            old,
+            new,
+            committer,
+            message,
+        }
+    }
+
+    fn parse(line: &str) -> Result<Self, anyhow::Error> {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+
+        let (old, rest) = head
+            .split_once(' ')
+            .ok_or_else(|| anyhow::anyhow!("malformed reflog entry: {}", line))?;
+        let (new, identity) = rest
+            .split_once(' ')
+            .ok_or_else(|| anyhow::anyhow!("malformed reflog entry: {}", line))?;
+
+        Ok(Self {
+            old: Oid::parse(old)
+                .ok_or_else(|| anyhow::anyhow!("malformed reflog entry: {}", line))?,
+            new: Oid::parse(new)
+                .ok_or_else(|| anyhow::anyhow!("malformed reflog entry: {}", line))?,
+            committer: Author::parse(identity)?,
+            message: message.to_string(),
+        })
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{} {} {}\t{}\n",
+            self.old,
+            self.new,
+            self.committer.string(),
+            self.message
+        )
+    }
+}
+
+/// Reflogs live in `logs/<refname>` inside the git directory, one entry
+/// per line, oldest first.
+pub struct Reflog {
+    root: PathBuf,
+}
+
+impl Reflog {
+    pub fn new(git_path: PathBuf) -> Self {
+        Self {
+            root: git_path.join("logs"),
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.path(name).is_file()
+    }
+
+    pub fn append(&self, name: &str, entry: &ReflogEntry) -> Result<(), anyhow::Error> {
+        let path = self.path(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(entry.to_line().as_bytes()))
+            .with_context(|| format!("Could not append to reflog {:?}", path))
+    }
+
+    /// Returns the entries of `name`'s reflog, oldest first.
+    pub fn read(&self, name: &str) -> Result<Vec<ReflogEntry>, anyhow::Error> {
+        let content = match fs::read_to_string(self.path(name)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Could not read reflog {}", name)),
+        };
+
+        content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(ReflogEntry::parse)
+            .collect()
+    }
+
+    pub fn write(&self, name: &str, entries: &[ReflogEntry]) -> Result<(), anyhow::Error> {
+        let mut lockfile = Lockfile::new(self.path(name));
+        if !lockfile.hold_for_update()? {
+            return Err(anyhow::anyhow!("Could not acquire lock on reflog {}", name));
+        }
+
+        let content: String = entries.iter().map(ReflogEntry::to_line).collect();
+        lockfile.write(content.as_bytes())?;
+        lockfile.commit()
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), anyhow::Error> {
+        match fs::remove_file(self.path(name)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Names of all refs that have a reflog.
+    pub fn list(&self) -> Result<Vec<String>, anyhow::Error> {
+        let mut names = Vec::new();
+
+        if !self.root.is_dir() {
+            return Ok(names);
+        }
+
+        for entry in WalkDir::new(&self.root).sort_by_file_name() {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                let name = entry.path().strip_prefix(&self.root)?;
+                names.push(name.to_string_lossy().into_owned());
+            }
+        }
+
+        Ok(names)
+    }
+}
@@ -1,29 +1,52 @@
 use crate::lockfile::Lockfile;
 use crate::oid::Oid;
 use anyhow::Context;
-use std::{fs, path::PathBuf};
+use std::{collections::HashSet, fs, io::ErrorKind, path::PathBuf};
+use walkdir::WalkDir;
+
+use super::object::commit::Author;
+use super::packed_refs::{PackedRef, PackedRefs};
+use super::reflog::{Reflog, ReflogEntry};
+
+const SYMREF_PREFIX: &str = "ref: ";
+
+/// Prefixes tried, in order, when a short ref name like `main` is looked
+/// up.
+const DWIM_PREFIXES: [&str; 5] = ["", "refs/", "refs/tags/", "refs/heads/", "refs/remotes/"];
 
 pub struct Refs {
     root: PathBuf,
+    reflog: Reflog,
+    packed: PackedRefs,
+    log_all_ref_updates: bool,
 }
 
 impl Refs {
-    pub fn new(root: PathBuf) -> Self {
-        Self { root }
+    /// `log_all_ref_updates` mirrors `core.logAllRefUpdates`: when set,
+    /// branches and HEAD get a reflog even if none exists yet.
+    pub fn new(root: PathBuf, log_all_ref_updates: bool) -> Self {
+        Self {
+            reflog: Reflog::new(root.clone()),
+            packed: PackedRefs::new(root.clone()),
+            root,
+            log_all_ref_updates,
+        }
+    }
+
+    pub fn reflog(&self) -> &Reflog {
+        &self.reflog
     }
 
-    pub fn set_head(&self, oid: &Oid) -> Result<(), anyhow::Error> {
-        let head_path = self.root.join("HEAD");
-        let mut lockfile = Lockfile::new(head_path.clone());
+    /// Creates the `refs` hierarchy and points HEAD at `default_head`
+    /// unless HEAD already exists.
+    pub fn init(&self, default_head: &str) -> Result<(), anyhow::Error> {
+        fs::create_dir_all(self.root.join("refs").join("heads"))?;
+        fs::create_dir_all(self.root.join("refs").join("tags"))?;
 
-        lockfile
-            .hold_for_update()
-            .with_context(|| format!("Could not acquire lock on file: {:?}", head_path.clone()))?;
+        if !self.head_path().exists() {
+            self.set_symbolic_head(default_head)?;
+        }
 
-        lockfile
-            .write((oid.to_string() + "\n").as_bytes())
-            .with_context(|| "Could not write HEAD reference")?;
-        lockfile.commit()?;
         Ok(())
     }
 
@@ -31,9 +54,528 @@ impl Refs {
         self.root.join("HEAD")
     }
 
-    pub fn get_head(&self) -> Result<Oid, anyhow::Error> {
-        let content = fs::read_to_string(&self.head_path())
-            .with_context(|| "Could not read HEAD reference")?;
-        Ok(Oid::from(content))
+    fn read_ref_file(&self, name: &str) -> Result<Option<String>, anyhow::Error> {
+        match fs::read_to_string(self.root.join(name)) {
+            Ok(content) => Ok(Some(content.trim_end().to_string())),
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::IsADirectory) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Could not read reference {}", name)),
+        }
+    }
+
+    /// Follows symbolic references starting at `name` and returns the name
+    /// of the ref that finally holds (or would hold) an object ID.
+    fn resolve_symbolic(&self, name: &str) -> Result<String, anyhow::Error> {
+        let mut name = name.to_string();
+
+        for _ in 0..5 {
+            match self.read_ref_file(&name)? {
+                Some(content) => match content.strip_prefix(SYMREF_PREFIX) {
+                    Some(target) => name = target.to_string(),
+                    None => return Ok(name),
+                },
+                None => return Ok(name),
+            }
+        }
+
+        Err(anyhow::anyhow!("Too many levels of symbolic references"))
+    }
+
+    fn write_ref(&self, name: &str, content: &str) -> Result<(), anyhow::Error> {
+        let mut lockfile = self.lock_ref(name)?;
+
+        lockfile
+            .write((content.to_string() + "\n").as_bytes())
+            .with_context(|| format!("Could not write {} reference", name))?;
+        lockfile.commit()?;
+        Ok(())
+    }
+
+    fn lock_ref(&self, name: &str) -> Result<Lockfile, anyhow::Error> {
+        let path = self.root.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut lockfile = Lockfile::new(path.clone());
+        if !lockfile.hold_for_update()? {
+            return Err(anyhow::anyhow!(
+                "cannot lock ref '{}': Unable to create '{}.lock': File exists",
+                name,
+                path.display()
+            ));
+        }
+
+        Ok(lockfile)
+    }
+
+    /// Removes empty directories left behind after deleting the ref `name`,
+    /// keeping the top-level namespaces such as `refs/heads`.
+    fn prune_empty_dirs(&self, name: &str) {
+        let mut dir = self.root.join(name);
+
+        while dir.pop()
+            && dir
+                .strip_prefix(&self.root)
+                .is_ok_and(|d| d.components().count() > 2)
+        {
+            if fs::remove_dir(&dir).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Whether updates to `name` are logged. The stash keeps its entries
+    /// in its reflog, so it is always logged.
+    fn should_log(&self, name: &str) -> bool {
+        self.reflog.exists(name)
+            || name == "refs/stash"
+            || (self.log_all_ref_updates
+                && (name == "HEAD"
+                    || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+                        .iter()
+                        .any(|prefix| name.starts_with(prefix))))
+    }
+
+    fn log_update(
+        &self,
+        name: &str,
+        old: Option<Oid>,
+        new: &Oid,
+        committer: &Author,
+        message: &str,
+    ) -> Result<(), anyhow::Error> {
+        if !self.should_log(name) {
+            return Ok(());
+        }
+
+        let entry = ReflogEntry::new(
+            old.unwrap_or_else(Oid::zero),
+            *new,
+            committer.clone(),
+            message,
+        );
+        self.reflog.append(name, &entry)
+    }
+
+    /// Points `name` at `oid` and records the change in its reflog. When
+    /// `expected_old` is given, the update only happens if the ref still
+    /// has that value; the zero ID means the ref must not exist yet.
+    pub fn update_ref(
+        &self,
+        name: &str,
+        oid: &Oid,
+        expected_old: Option<Oid>,
+        committer: &Author,
+        message: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut transaction = RefTransaction::new(committer.clone(), message);
+        transaction.update(name, *oid, expected_old);
+        transaction.commit(self)
+    }
+
+    pub fn delete_ref(
+        &self,
+        name: &str,
+        expected_old: Option<Oid>,
+        committer: &Author,
+        message: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut transaction = RefTransaction::new(committer.clone(), message);
+        transaction.delete(name, expected_old);
+        transaction.commit(self)
+    }
+
+    /// Updates the ref HEAD points at, or HEAD itself when it is detached.
+    /// Both the branch and HEAD reflogs record the change.
+    pub fn set_head(
+        &self,
+        oid: &Oid,
+        expected_old: Option<Oid>,
+        committer: &Author,
+        message: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.update_ref("HEAD", oid, expected_old, committer, message)
+    }
+
+    /// Returns the ref HEAD currently points at, or `HEAD` when detached.
+    pub fn current_ref(&self) -> Result<String, anyhow::Error> {
+        self.resolve_symbolic("HEAD")
+    }
+
+    /// Reads the object ID `name` points at, following symbolic refs.
+    /// Loose refs take precedence over entries in `packed-refs`.
+    pub fn read_ref(&self, name: &str) -> Result<Option<Oid>, anyhow::Error> {
+        let name = self.resolve_symbolic(name)?;
+
+        match self.read_ref_file(&name)? {
+            Some(content) => Oid::parse(&content)
+                .map(Some)
+                .ok_or_else(|| anyhow::anyhow!("invalid reference {}: {}", name, content)),
+            None if name.starts_with("refs/") => {
+                Ok(self.packed.find(&name)?.map(|packed| packed.oid))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn is_symbolic(&self, name: &str) -> Result<bool, anyhow::Error> {
+        Ok(self
+            .read_ref_file(name)?
+            .is_some_and(|content| content.starts_with(SYMREF_PREFIX)))
+    }
+
+    fn loose_ref_names(&self) -> Result<Vec<String>, anyhow::Error> {
+        let refs_path = self.root.join("refs");
+        let mut names = Vec::new();
+
+        if !refs_path.is_dir() {
+            return Ok(names);
+        }
+
+        for entry in WalkDir::new(&refs_path) {
+            let entry = entry?;
+            let name = entry.path().strip_prefix(&self.root)?.to_string_lossy();
+
+            if entry.file_type().is_file() && !name.ends_with(".lock") {
+                names.push(name.into_owned());
+            }
+        }
+
+        Ok(names)
+    }
+
+    /// Lists every ref under `refs/` starting with `prefix`, merging loose
+    /// and packed refs, sorted by name. Symbolic refs are resolved.
+    pub fn list_refs(&self, prefix: &str) -> Result<Vec<(String, Oid)>, anyhow::Error> {
+        let mut refs: Vec<(String, Oid)> = Vec::new();
+
+        for name in self.loose_ref_names()? {
+            if name.starts_with(prefix) {
+                if let Some(oid) = self.read_ref(&name)? {
+                    refs.push((name, oid));
+                }
+            }
+        }
+
+        let loose: HashSet<String> = refs.iter().map(|(name, _)| name.clone()).collect();
+        for packed in self.packed.read()? {
+            if packed.name.starts_with(prefix) && !loose.contains(&packed.name) {
+                refs.push((packed.name, packed.oid));
+            }
+        }
+
+        refs.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(refs)
+    }
+
+    /// Moves loose refs into `packed-refs`. `refs` holds the entries to
+    /// pack along with their peeled values; with `prune` the loose files
+    /// are removed once packed, unless they changed in the meantime.
+    pub fn pack(&self, refs: Vec<PackedRef>, prune: bool) -> Result<(), anyhow::Error> {
+        let lock = self.packed.lock()?;
+
+        let mut packed = match self.packed.read() {
+            Ok(packed) => packed,
+            Err(e) => {
+                let mut lock = lock;
+                lock.rollback()?;
+                return Err(e);
+            }
+        };
+        let names: HashSet<&str> = refs.iter().map(|r| r.name.as_str()).collect();
+        packed.retain(|existing| !names.contains(existing.name.as_str()));
+        packed.extend(refs.iter().cloned());
+        self.packed.write(lock, &packed)?;
+
+        if !prune {
+            return Ok(());
+        }
+
+        for r in refs {
+            if self.is_symbolic(&r.name)? {
+                continue;
+            }
+
+            let mut lock = self.lock_ref(&r.name)?;
+            if self.read_ref_file(&r.name)? == Some(r.oid.to_string()) {
+                fs::remove_file(self.root.join(&r.name))?;
+            }
+            lock.rollback()?;
+            self.prune_empty_dirs(&r.name);
+        }
+
+        Ok(())
+    }
+
+    /// Loose refs holding an object ID, skipping symbolic refs.
+    pub fn loose_refs(&self) -> Result<Vec<(String, Oid)>, anyhow::Error> {
+        let mut refs = Vec::new();
+
+        for name in self.loose_ref_names()? {
+            if self.is_symbolic(&name)? {
+                continue;
+            }
+            if let Some(content) = self.read_ref_file(&name)? {
+                let oid = Oid::parse(&content)
+                    .ok_or_else(|| anyhow::anyhow!("invalid reference {}: {}", name, content))?;
+                refs.push((name, oid));
+            }
+        }
+
+        Ok(refs)
+    }
+
+    pub fn packed_ref_names(&self) -> Result<Vec<String>, anyhow::Error> {
+        Ok(self.packed.read()?.into_iter().map(|r| r.name).collect())
+    }
+
+    /// Expands a short name like `main` into the full name of an existing
+    /// ref, following Git's lookup order.
+    pub fn expand_ref(&self, name: &str) -> Result<Option<String>, anyhow::Error> {
+        let mut candidates: Vec<String> = DWIM_PREFIXES
+            .iter()
+            .map(|prefix| format!("{}{}", prefix, name))
+            .collect();
+        candidates.push(format!("refs/remotes/{}/HEAD", name));
+
+        for candidate in candidates {
+            // Only names like HEAD or ORIG_HEAD may live at the top level
+            if !candidate.starts_with("refs/")
+                && !candidate
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c == '_')
+            {
+                continue;
+            }
+
+            if self.read_ref(&candidate)?.is_some() {
+                return Ok(Some(candidate));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Points HEAD at the ref `target`, e.g. `refs/heads/main`.
+    pub fn set_symbolic_head(&self, target: &str) -> Result<(), anyhow::Error> {
+        self.write_ref("HEAD", &format!("{}{}", SYMREF_PREFIX, target))
+    }
+
+    /// Detaches HEAD at `oid`, leaving the branch it pointed at alone. The
+    /// HEAD reflog records the move.
+    pub fn detach_head(
+        &self,
+        oid: &Oid,
+        committer: &Author,
+        message: &str,
+    ) -> Result<(), anyhow::Error> {
+        let old = self.read_ref("HEAD")?;
+        self.write_ref("HEAD", &oid.to_string())?;
+        self.log_update("HEAD", old, oid, committer, message)
+    }
+
+    /// Points HEAD back at the branch `target`, recording the move in the
+    /// HEAD reflog like [`Refs::detach_head`].
+    pub fn attach_head(
+        &self,
+        target: &str,
+        committer: &Author,
+        message: &str,
+    ) -> Result<(), anyhow::Error> {
+        let old = self.read_ref("HEAD")?;
+        self.set_symbolic_head(target)?;
+        match self.read_ref(target)? {
+            Some(new) => self.log_update("HEAD", old, &new, committer, message),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Checks `name` against the main rules of `git check-ref-format`.
+pub fn check_ref_format(name: &str) -> Result<(), anyhow::Error> {
+    let valid = !name.is_empty()
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && name != "@"
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+        && name
+            .split('/')
+            .all(|part| !part.starts_with('.') && !part.ends_with(".lock"));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("invalid ref name: '{}'", name))
+    }
+}
+
+enum RefAction {
+    Update(Oid),
+    Delete,
+    Verify,
+}
+
+struct RefUpdate {
+    name: String,
+    action: RefAction,
+    /// `None` skips the check; the zero ID requires the ref to be absent
+    expected_old: Option<Oid>,
+}
+
+/// A set of ref updates applied all-or-nothing: every ref is locked and
+/// checked against its expected value before any of them is written.
+pub struct RefTransaction {
+    updates: Vec<RefUpdate>,
+    committer: Author,
+    message: String,
+}
+
+impl RefTransaction {
+    pub fn new(committer: Author, message: &str) -> Self {
+        Self {
+            updates: Vec::new(),
+            committer,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn update(&mut self, name: &str, new: Oid, expected_old: Option<Oid>) {
+        self.updates.push(RefUpdate {
+            name: name.to_string(),
+            action: RefAction::Update(new),
+            expected_old,
+        });
+    }
+
+    pub fn create(&mut self, name: &str, new: Oid) {
+        self.update(name, new, Some(Oid::zero()));
+    }
+
+    pub fn delete(&mut self, name: &str, expected_old: Option<Oid>) {
+        self.updates.push(RefUpdate {
+            name: name.to_string(),
+            action: RefAction::Delete,
+            expected_old,
+        });
+    }
+
+    /// Checks that `name` has the value `expected` without changing it.
+    pub fn verify(&mut self, name: &str, expected: Oid) {
+        self.updates.push(RefUpdate {
+            name: name.to_string(),
+            action: RefAction::Verify,
+            expected_old: Some(expected),
+        });
+    }
+
+    pub fn commit(self, refs: &Refs) -> Result<(), anyhow::Error> {
+        let mut targets: Vec<(String, &RefUpdate)> = Vec::new();
+        for update in &self.updates {
+            let target = refs.resolve_symbolic(&update.name)?;
+            if targets.iter().any(|(t, _)| *t == target) {
+                return Err(anyhow::anyhow!(
+                    "multiple updates for ref '{}' not allowed",
+                    target
+                ));
+            }
+            targets.push((target, update));
+        }
+        targets.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut locks: Vec<Lockfile> = Vec::new();
+        let mut olds: Vec<Option<Oid>> = Vec::new();
+
+        // Deleting a packed ref means rewriting packed-refs, so it is locked
+        // along with the loose refs, and only read once locked so that no
+        // concurrent update to it is lost
+        let deletes = targets
+            .iter()
+            .any(|(_, update)| matches!(update.action, RefAction::Delete));
+        let mut packed_lock = None;
+        let mut packed = Vec::new();
+
+        let prepared = (|| -> Result<(), anyhow::Error> {
+            if deletes {
+                packed_lock = Some(refs.packed.lock()?);
+                packed = refs.packed.read()?;
+            }
+
+            for (target, update) in &targets {
+                locks.push(refs.lock_ref(target)?);
+
+                let current = refs.read_ref(target)?;
+                if let Some(expected) = update.expected_old {
+                    let matches = match current {
+                        Some(current) => current == expected,
+                        None => expected.is_zero(),
+                    };
+                    if !matches {
+                        return Err(anyhow::anyhow!(
+                            "cannot lock ref '{}': is at {} but expected {}",
+                            target,
+                            current.unwrap_or_else(Oid::zero),
+                            expected
+                        ));
+                    }
+                }
+                olds.push(current);
+            }
+            Ok(())
+        })();
+
+        if let Err(e) = prepared {
+            for mut lock in locks.into_iter().chain(packed_lock) {
+                lock.rollback()?;
+            }
+            return Err(e);
+        }
+
+        if let Some(mut lock) = packed_lock {
+            let deleted: HashSet<&str> = targets
+                .iter()
+                .filter(|(_, update)| matches!(update.action, RefAction::Delete))
+                .map(|(target, _)| target.as_str())
+                .collect();
+            if packed.iter().any(|p| deleted.contains(p.name.as_str())) {
+                packed.retain(|p| !deleted.contains(p.name.as_str()));
+                refs.packed.write(lock, &packed)?;
+            } else {
+                lock.rollback()?;
+            }
+        }
+
+        let head_target = refs.current_ref()?;
+
+        for (((target, update), mut lock), old) in targets.iter().zip(locks).zip(olds) {
+            match update.action {
+                RefAction::Verify => lock.rollback()?,
+                RefAction::Update(new) => {
+                    lock.write(format!("{}\n", new).as_bytes())?;
+                    lock.commit()?;
+
+                    refs.log_update(target, old, &new, &self.committer, &self.message)?;
+                    if *target != "HEAD" && *target == head_target {
+                        refs.log_update("HEAD", old, &new, &self.committer, &self.message)?;
+                    }
+                }
+                RefAction::Delete => {
+                    match fs::remove_file(refs.root.join(target)) {
+                        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                        _ => {}
+                    }
+                    lock.rollback()?;
+                    refs.reflog.delete(target)?;
+                    refs.prune_empty_dirs(target);
+                }
+            }
+        }
+
+        Ok(())
     }
 }
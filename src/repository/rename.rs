@@ -0,0 +1,339 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::oid::Oid;
+use crate::similarity::{self, Fingerprint, MAX_SCORE};
+
+use super::db::Db;
+use super::diff_tree::{FilePair, Status};
+
+/// Candidates kept for each destination while scoring inexact renames.
+const CANDIDATES_PER_DST: usize = 4;
+
+/// What rename detection looks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Detect {
+    #[default]
+    Off,
+    Renames,
+    /// Renames, and copies from files modified in the same change.
+    Copies,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenameOptions {
+    pub detect: Detect,
+    /// The least score a pair needs to count as a rename or copy.
+    pub min_score: u64,
+    /// Pair empty files too.
+    pub rename_empty: bool,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        Self {
+            detect: Detect::Off,
+            min_score: similarity::DEFAULT_SCORE,
+            rename_empty: true,
+        }
+    }
+}
+
+/// A file that could have been renamed or copied away.
+struct Source {
+    pair: usize,
+    /// How many destinations use this file, counting a file that stays as
+    /// using itself.
+    used: usize,
+}
+
+/// A file that could have come from elsewhere.
+struct Destination {
+    pair: usize,
+    found: Option<(usize, u64)>,
+}
+
+/// A scored source for a destination, for sorting the inexact matches.
+#[derive(Clone, Copy)]
+struct Candidate {
+    score: u64,
+    same_name: bool,
+    dst: usize,
+    src: usize,
+}
+
+/// Orders candidates best first, the unused slots last.
+fn compare(a: &Option<Candidate>, b: &Option<Candidate>) -> Ordering {
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => b.score.cmp(&a.score).then(b.same_name.cmp(&a.same_name)),
+    }
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn is_regular(mode: u32) -> bool {
+    mode & 0o170000 == 0o100000
+}
+
+/// Blob sizes and fingerprints, computed once per blob.
+struct Blobs<'a> {
+    db: &'a Db,
+    sizes: HashMap<Oid, usize>,
+    fingerprints: HashMap<Oid, Fingerprint>,
+}
+
+impl<'a> Blobs<'a> {
+    fn load(&mut self, oid: &Oid) -> Result<usize, anyhow::Error> {
+        if let Some(size) = self.sizes.get(oid) {
+            return Ok(*size);
+        }
+        let (_, data) = self.db.read_raw(oid)?;
+        self.sizes.insert(*oid, data.len());
+        self.fingerprints.insert(*oid, Fingerprint::new(&data));
+        Ok(data.len())
+    }
+
+    /// Scores `dst` against `src` the way Git estimates similarity. Only
+    /// regular files are compared, and pairs too different in size to reach
+    /// `min_score` score zero.
+    fn similarity(
+        &mut self,
+        src: (Oid, u32),
+        dst: (Oid, u32),
+        min_score: u64,
+    ) -> Result<u64, anyhow::Error> {
+        if !is_regular(src.1) || !is_regular(dst.1) {
+            return Ok(0);
+        }
+        let src_size = self.load(&src.0)?;
+        let dst_size = self.load(&dst.0)?;
+        if !similarity::sizes_compatible(src_size, dst_size, min_score) {
+            return Ok(0);
+        }
+        Ok(similarity::score(
+            &self.fingerprints[&src.0],
+            src_size,
+            &self.fingerprints[&dst.0],
+            dst_size,
+        ))
+    }
+}
+
+/// Pairs up deleted and added files of `pairs` that hold the same or
+/// similar content, as Git's diffcore does. Identical files are matched
+/// first, preferring sources not used yet and with the same basename. When
+/// only renames are wanted, files whose basenames are unique on both sides
+/// are then matched if they are at least halfway from `min_score` to
+/// identical. The rest are scored against every source and matched best
+/// score first. With copies, modified and unmodified files in `pairs` are
+/// sources too, and a source can give several copies.
+///
+/// Matched additions become renames and copies, and deletions whose file
+/// went somewhere are dropped. A deleted file used more than once is
+/// copied to every destination but the last, which it is renamed to.
+/// Unmodified pairs are dropped from the result.
+pub(super) fn detect_renames(
+    db: &Db,
+    pairs: Vec<FilePair>,
+    options: &RenameOptions,
+) -> Result<Vec<FilePair>, anyhow::Error> {
+    if options.detect == Detect::Off {
+        return Ok(pairs
+            .into_iter()
+            .filter(|pair| !pair.is_unmodified())
+            .collect());
+    }
+    let copies = options.detect == Detect::Copies;
+    let empty_blob = db.hash_object(b"blob", b"", false)?;
+
+    let mut sources = Vec::new();
+    let mut dsts = Vec::new();
+    for (i, pair) in pairs.iter().enumerate() {
+        match (pair.old, pair.new) {
+            (None, Some((oid, _))) if options.rename_empty || oid != empty_blob => {
+                dsts.push(Destination {
+                    pair: i,
+                    found: None,
+                });
+            }
+            (Some(_), None) => sources.push(Source { pair: i, used: 0 }),
+            (Some(_), Some(_)) if copies => sources.push(Source { pair: i, used: 1 }),
+            _ => {}
+        }
+    }
+    let old = |src: &Source| pairs[src.pair].old.unwrap();
+    let new = |dst: &Destination| pairs[dst.pair].new.unwrap();
+    let same_name = |src: &Source, dst: &Destination| {
+        basename(&pairs[src.pair].old_path) == basename(&pairs[dst.pair].new_path)
+    };
+
+    // Identical content
+    for dst in dsts.iter_mut() {
+        let target = new(dst);
+        let mut best: Option<(usize, usize)> = None;
+        for (s, src) in sources.iter().enumerate() {
+            let source = old(src);
+            if source.0 != target.0 {
+                continue;
+            }
+            if (!is_regular(source.1) || !is_regular(target.1)) && source.1 != target.1 {
+                continue;
+            }
+            if src.used > 0 && !copies {
+                continue;
+            }
+            let score = usize::from(src.used == 0) + usize::from(same_name(src, dst));
+            if best.is_none_or(|(_, best)| score > best) {
+                best = Some((s, score));
+                if score == 2 {
+                    break;
+                }
+            }
+        }
+        if let Some((s, _)) = best {
+            dst.found = Some((s, MAX_SCORE));
+            sources[s].used += 1;
+        }
+    }
+
+    let mut blobs = Blobs {
+        db,
+        sizes: HashMap::new(),
+        fingerprints: HashMap::new(),
+    };
+
+    if options.min_score < MAX_SCORE && dsts.iter().any(|dst| dst.found.is_none()) {
+        // Unique basenames
+        if !copies {
+            let min_basename_score = options.min_score + (MAX_SCORE - options.min_score) / 2;
+            let mut src_names: HashMap<&str, Option<usize>> = HashMap::new();
+            for (s, src) in sources.iter().enumerate().filter(|(_, src)| src.used == 0) {
+                src_names
+                    .entry(basename(&pairs[src.pair].old_path))
+                    .and_modify(|unique| *unique = None)
+                    .or_insert(Some(s));
+            }
+            let mut dst_names: HashMap<&str, Option<usize>> = HashMap::new();
+            for (d, dst) in dsts
+                .iter()
+                .enumerate()
+                .filter(|(_, dst)| dst.found.is_none())
+            {
+                dst_names
+                    .entry(basename(&pairs[dst.pair].new_path))
+                    .and_modify(|unique| *unique = None)
+                    .or_insert(Some(d));
+            }
+
+            for s in 0..sources.len() {
+                if sources[s].used > 0 {
+                    continue;
+                }
+                let name = basename(&pairs[sources[s].pair].old_path);
+                let (Some(Some(s)), Some(Some(d))) = (src_names.get(name), dst_names.get(name))
+                else {
+                    continue;
+                };
+                let (s, d) = (*s, *d);
+                if dsts[d].found.is_some() {
+                    continue;
+                }
+                let score = blobs.similarity(old(&sources[s]), new(&dsts[d]), options.min_score)?;
+                if score < min_basename_score {
+                    continue;
+                }
+                dsts[d].found = Some((s, score));
+                sources[s].used += 1;
+            }
+        }
+
+        // Everything against everything
+        let mut matrix = Vec::new();
+        for (d, dst) in dsts.iter().enumerate() {
+            if dst.found.is_some() {
+                continue;
+            }
+            let mut best: [Option<Candidate>; CANDIDATES_PER_DST] = [None; CANDIDATES_PER_DST];
+            for (s, src) in sources.iter().enumerate() {
+                if src.used > 0 && !copies {
+                    continue;
+                }
+                let candidate = Some(Candidate {
+                    score: blobs.similarity(old(src), new(dst), options.min_score)?,
+                    same_name: same_name(src, dst),
+                    dst: d,
+                    src: s,
+                });
+
+                let mut worst = 0;
+                for i in 1..CANDIDATES_PER_DST {
+                    if compare(&best[i], &best[worst]) == Ordering::Greater {
+                        worst = i;
+                    }
+                }
+                if compare(&best[worst], &candidate) == Ordering::Greater {
+                    best[worst] = candidate;
+                }
+            }
+            matrix.extend(best);
+        }
+        matrix.sort_by(compare);
+
+        for allow_reuse in [false, true] {
+            if allow_reuse && !copies {
+                break;
+            }
+            for candidate in matrix.iter().map_while(|candidate| *candidate) {
+                if candidate.score < options.min_score {
+                    break;
+                }
+                let dst = &mut dsts[candidate.dst];
+                if dst.found.is_some() || (!allow_reuse && sources[candidate.src].used > 0) {
+                    continue;
+                }
+                dst.found = Some((candidate.src, candidate.score));
+                sources[candidate.src].used += 1;
+            }
+        }
+    }
+
+    let found: HashMap<usize, (usize, u64)> = dsts
+        .iter()
+        .filter_map(|dst| dst.found.map(|found| (dst.pair, found)))
+        .collect();
+    let gone: Vec<usize> = sources
+        .iter()
+        .filter(|src| src.used > 0 && pairs[src.pair].new.is_none())
+        .map(|src| src.pair)
+        .collect();
+
+    let mut result = Vec::new();
+    for (i, pair) in pairs.iter().enumerate() {
+        if let Some(&(s, score)) = found.get(&i) {
+            let src = &mut sources[s];
+            let from = &pairs[src.pair];
+            src.used -= 1;
+            result.push(FilePair {
+                status: if src.used > 0 {
+                    Status::Copied
+                } else {
+                    Status::Renamed
+                },
+                old_path: from.old_path.clone(),
+                new_path: pair.new_path.clone(),
+                old: from.old,
+                new: pair.new,
+                score,
+            });
+        } else if !gone.contains(&i) && !pair.is_unmodified() {
+            result.push(pair.clone());
+        }
+    }
+
+    Ok(result)
+}
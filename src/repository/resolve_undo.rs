@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+
+use crate::oid::Oid;
+
+/// The resolve-undo (`REUC`) index extension: for each path whose
+/// conflict was resolved, the mode and object ID of its stage 1, 2 and 3
+/// entries, so the conflict can be brought back.
+#[derive(Debug, Clone, Default)]
+pub struct ResolveUndo {
+    entries: BTreeMap<CString, [Option<(u32, Oid)>; 3]>,
+}
+
+impl ResolveUndo {
+    pub fn parse(data: &[u8]) -> Result<Self, anyhow::Error> {
+        let corrupt = || anyhow::anyhow!("corrupt resolve-undo extension");
+        let mut entries = BTreeMap::new();
+        let mut rest = data;
+
+        let next_field = |rest: &mut &[u8]| -> Result<Vec<u8>, anyhow::Error> {
+            let nul = rest.iter().position(|&b| b == 0).ok_or_else(corrupt)?;
+            let field = rest[..nul].to_vec();
+            *rest = &rest[nul + 1..];
+            Ok(field)
+        };
+
+        while !rest.is_empty() {
+            let path = CString::new(next_field(&mut rest)?)?;
+
+            let mut modes = [0; 3];
+            for mode in &mut modes {
+                let field = next_field(&mut rest)?;
+                *mode =
+                    u32::from_str_radix(std::str::from_utf8(&field)?, 8).map_err(|_| corrupt())?;
+            }
+
+            let mut stages = [None; 3];
+            for (stage, mode) in stages.iter_mut().zip(modes) {
+                if mode == 0 {
+                    continue;
+                }
+                if rest.len() < 20 {
+                    return Err(corrupt());
+                }
+                *stage = Some((mode, Oid::from(&rest[..20])));
+                rest = &rest[20..];
+            }
+
+            entries.insert(path, stages);
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        for (path, stages) in &self.entries {
+            bytes.extend_from_slice(path.as_bytes_with_nul());
+            for stage in stages {
+                let mode = stage.map_or(0, |(mode, _)| mode);
+                bytes.extend_from_slice(format!("{:o}", mode).as_bytes());
+                bytes.push(0);
+            }
+            for (_, oid) in stages.iter().flatten() {
+                bytes.extend_from_slice(oid.as_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    /// Remembers the stage 1, 2 and 3 entries of a path being resolved.
+    pub fn record(&mut self, path: CString, stages: [Option<(u32, Oid)>; 3]) {
+        self.entries.insert(path, stages);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
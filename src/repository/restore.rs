@@ -0,0 +1,126 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use super::index::IndexEntry;
+use super::pathspec::Pathspec;
+use super::{work_tree, Repository};
+
+impl Repository {
+    /// Restores `paths` in the work tree and, with `staged`, in the index,
+    /// from the tree-ish `source`. The default source is the index for the
+    /// work tree alone and HEAD otherwise. Tracked files missing from the
+    /// source are removed; untracked files are left alone. Returns whether
+    /// every path could be restored, which unmerged paths cannot be from
+    /// the index.
+    pub fn restore(
+        &mut self,
+        cwd: &Path,
+        source: Option<&str>,
+        staged: bool,
+        worktree: bool,
+        paths: &[PathBuf],
+    ) -> Result<bool, anyhow::Error> {
+        work_tree(&self.workspace)?;
+        if paths.is_empty() {
+            return Err(anyhow::anyhow!("you must specify path(s) to restore"));
+        }
+        let worktree = worktree || !staged;
+
+        let source = match source {
+            Some(source) => Some(self.read_tree_files(source)?),
+            None if staged => {
+                let head = self
+                    .refs
+                    .read_ref("HEAD")?
+                    .ok_or_else(|| anyhow::anyhow!("could not resolve HEAD"))?;
+                Some(self.commit_files(&head)?)
+            }
+            None => None,
+        };
+
+        let pathspec = Pathspec::parse(work_tree(&self.workspace)?, cwd, paths)?;
+        self.index.load()?;
+
+        let mut names: Vec<String> = self
+            .index
+            .entries
+            .values()
+            .map(|entry| Ok(entry.path.to_str()?.to_string()))
+            .collect::<Result<_, anyhow::Error>>()?;
+        if let Some(files) = &source {
+            names.extend(files.keys().cloned());
+        }
+        names.retain(|name| pathspec.matches(Path::new(name)));
+        names.sort();
+        names.dedup();
+
+        if let Some(path) = pathspec.unmatched(&names) {
+            return Err(anyhow::anyhow!(
+                "pathspec '{}' did not match any file(s) known to git",
+                path
+            ));
+        }
+
+        self.lock_index()?;
+        let workspace = work_tree(&self.workspace)?;
+        let mut restored = true;
+        for name in names {
+            let path = Path::new(&name);
+
+            let Some(files) = &source else {
+                let Some(entry) = self.index.get(&name) else {
+                    eprintln!("error: path '{}' is unmerged", name);
+                    restored = false;
+                    continue;
+                };
+                if entry.skip_worktree() || self.file_matches_entry(entry)? == Some(true) {
+                    continue;
+                }
+
+                let (oid, mode) = (entry.oid, entry.mode);
+                let (_, data) = self.db.read_raw(&oid)?;
+                workspace.write_file(path, &data, mode)?;
+                let stat = workspace.stat_file(path)?;
+                if let Some(entry) = self.index.get_mut(&name) {
+                    entry.update_stat(&stat);
+                }
+                continue;
+            };
+
+            match files.get(&name) {
+                Some(&(oid, mode)) => {
+                    if staged {
+                        self.index
+                            .insert(IndexEntry::from_object(&name, oid, mode)?);
+                    }
+                    if worktree {
+                        let (_, data) = self.db.read_raw(&oid)?;
+                        workspace.write_file(path, &data, mode)?;
+                        if staged {
+                            let stat = workspace.stat_file(path)?;
+                            if let Some(entry) = self.index.get_mut(&name) {
+                                entry.update_stat(&stat);
+                            }
+                        }
+                    }
+                }
+                None => {
+                    if staged {
+                        self.index.remove(&name);
+                    }
+                    if worktree {
+                        match workspace.remove_file(path) {
+                            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        self.index.write_updates()?;
+        Ok(restored)
+    }
+}
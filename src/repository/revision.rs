@@ -0,0 +1,188 @@
+use chrono::{DateTime, FixedOffset, Local};
+
+use crate::date::parse_approxidate;
+use crate::oid::Oid;
+
+use super::db::Db;
+use super::object::{commit::Commit, tag::Tag};
+use super::refs::Refs;
+
+/// Shortest abbreviated object ID accepted in a revision.
+const MIN_ABBREV: usize = 4;
+
+#[derive(Debug, Clone)]
+pub enum ReflogSelector {
+    /// `@{N}`: the Nth prior value, 0 being the current one
+    Index(usize),
+    /// `@{<date>}`: the value the ref had at the given time
+    Date(DateTime<FixedOffset>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Revision {
+    /// A ref name, `@` or an (abbreviated) object ID
+    Name(String),
+    /// `<ref>@{...}`; a missing ref means the current branch
+    Reflog(Option<String>, ReflogSelector),
+    /// `<rev>^{<type>}`, or `<rev>^{}` to peel tags to whatever they
+    /// point at
+    Peel(Box<Revision>, Option<String>),
+}
+
+impl Revision {
+    pub fn parse(rev: &str) -> Result<Self, anyhow::Error> {
+        if let Some(start) = rev.rfind("^{") {
+            let kind = rev[start + 2..]
+                .strip_suffix('}')
+                .ok_or_else(|| anyhow::anyhow!("invalid revision: {}", rev))?;
+            let kind = match kind {
+                "" => None,
+                "commit" | "tree" | "blob" | "tag" => Some(kind.to_string()),
+                _ => return Err(anyhow::anyhow!("invalid object type in revision: {}", rev)),
+            };
+
+            return Ok(Revision::Peel(Box::new(Self::parse(&rev[..start])?), kind));
+        }
+
+        if let Some(start) = rev.find("@{") {
+            let selector = rev[start + 2..]
+                .strip_suffix('}')
+                .ok_or_else(|| anyhow::anyhow!("invalid revision: {}", rev))?;
+
+            let name = match &rev[..start] {
+                "" => None,
+                name => Some(name.to_string()),
+            };
+
+            let selector = match selector.parse::<usize>() {
+                Ok(index) => ReflogSelector::Index(index),
+                Err(_) => ReflogSelector::Date(
+                    parse_approxidate(selector, Local::now().fixed_offset())
+                        .ok_or_else(|| anyhow::anyhow!("invalid date in revision: {}", rev))?,
+                ),
+            };
+
+            return Ok(Revision::Reflog(name, selector));
+        }
+
+        if rev.is_empty() {
+            return Err(anyhow::anyhow!("invalid revision: empty name"));
+        }
+
+        Ok(Revision::Name(rev.to_string()))
+    }
+
+    /// Resolves the ref whose reflog a `@{...}` revision refers to.
+    pub fn reflog_ref(name: Option<&str>, refs: &Refs) -> Result<String, anyhow::Error> {
+        match name {
+            None => refs.current_ref(),
+            Some("@") | Some("HEAD") => Ok("HEAD".to_string()),
+            Some(name) => refs
+                .expand_ref(name)?
+                .ok_or_else(|| anyhow::anyhow!("unknown ref: {}", name)),
+        }
+    }
+
+    pub fn resolve(&self, refs: &Refs, db: &Db) -> Result<Oid, anyhow::Error> {
+        match self {
+            Revision::Name(name) => resolve_name(name, refs, db),
+            Revision::Peel(rev, kind) => {
+                let oid = rev.resolve(refs, db)?;
+                match kind {
+                    Some(kind) => peel_to(db, oid, kind),
+                    None => db.peel(&oid),
+                }
+            }
+            Revision::Reflog(name, selector) => {
+                let name = Self::reflog_ref(name.as_deref(), refs)?;
+                let entries = refs.reflog().read(&name)?;
+
+                match selector {
+                    ReflogSelector::Index(index) => entries
+                        .iter()
+                        .rev()
+                        .nth(*index)
+                        .map(|entry| entry.new)
+                        .ok_or_else(|| {
+                            anyhow::anyhow!("log for '{}' only has {} entries", name, entries.len())
+                        }),
+                    ReflogSelector::Date(date) => {
+                        if let Some(entry) = entries
+                            .iter()
+                            .rev()
+                            .find(|entry| entry.committer.time() <= *date)
+                        {
+                            return Ok(entry.new);
+                        }
+
+                        let oldest = entries
+                            .first()
+                            .ok_or_else(|| anyhow::anyhow!("log for '{}' is empty", name))?;
+                        eprintln!(
+                            "warning: log for '{}' only goes back to {}",
+                            name,
+                            oldest.committer.time().to_rfc2822()
+                        );
+                        Ok(oldest.new)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Dereferences tags (and commits, for `tree`) until reaching an object
+/// of type `kind`.
+pub fn peel_to(db: &Db, oid: Oid, kind: &str) -> Result<Oid, anyhow::Error> {
+    let mut current = oid;
+
+    loop {
+        let (current_kind, data) = db.read_raw(&current)?;
+
+        if current_kind == kind {
+            return Ok(current);
+        }
+
+        current = match current_kind.as_str() {
+            "tag" => Tag::parse(&data)?.object(),
+            "commit" if kind == "tree" => Commit::parse(&data)?.tree(),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "{}^{{{}}}: expected {} type, but the object dereferences to {} type",
+                    oid,
+                    kind,
+                    kind,
+                    current_kind
+                ))
+            }
+        };
+    }
+}
+
+fn resolve_name(name: &str, refs: &Refs, db: &Db) -> Result<Oid, anyhow::Error> {
+    let name = if name == "@" { "HEAD" } else { name };
+
+    if let Some(full_name) = refs.expand_ref(name)? {
+        if let Some(oid) = refs.read_ref(&full_name)? {
+            return Ok(oid);
+        }
+    }
+
+    if let Some(oid) = Oid::parse(name) {
+        return Ok(oid);
+    }
+
+    if name.len() >= MIN_ABBREV {
+        let matches = db.prefix_match(name)?;
+        match matches.len() {
+            0 => {}
+            1 => return Ok(matches[0]),
+            _ => return Err(anyhow::anyhow!("short object ID {} is ambiguous", name)),
+        }
+    }
+
+    Err(anyhow::anyhow!(
+        "ambiguous argument '{}': unknown revision or path not in the working tree",
+        name
+    ))
+}
@@ -0,0 +1,567 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::oid::Oid;
+
+use super::graph::range;
+use super::merge::{conflicts_message, strip_comments};
+use super::object::commit::{Author, Commit};
+use super::read_tree::Files;
+use super::{work_tree, Repository};
+
+const CHERRY_PICK_HEAD: &str = "CHERRY_PICK_HEAD";
+const REVERT_HEAD: &str = "REVERT_HEAD";
+const MERGE_MSG: &str = "MERGE_MSG";
+const SEQUENCER: &str = "sequencer";
+
+/// What the sequencer does with each commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Applies the changes the commit made.
+    Pick,
+    /// Applies the inverse of the changes the commit made.
+    Revert,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Pick => "cherry-pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// The command naming the action in the todo list.
+    fn command(self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// The pseudo-ref recording the commit being applied during a conflict.
+    fn head(self) -> &'static str {
+        match self {
+            Action::Pick => CHERRY_PICK_HEAD,
+            Action::Revert => REVERT_HEAD,
+        }
+    }
+}
+
+/// The changes of a commit merged into HEAD, ready to be committed.
+pub(super) struct Merged {
+    /// The commit HEAD was at.
+    pub(super) head: Oid,
+    /// The merged tree, or `None` when some paths were left unresolved.
+    pub(super) tree: Option<Oid>,
+    /// Whether the merged tree is HEAD's, the commit's changes being there
+    /// already.
+    pub(super) empty: bool,
+    pub(super) unresolved: Vec<String>,
+    /// The author and message the new commit gets.
+    pub(super) author: Author,
+    pub(super) message: String,
+}
+
+/// A commit waiting in the todo list.
+struct Step {
+    action: Action,
+    commit: Oid,
+}
+
+impl Repository {
+    fn sequencer_path(&self) -> PathBuf {
+        self.git_path.join(SEQUENCER)
+    }
+
+    /// The commit a conflicted cherry-pick or revert was applying.
+    fn picked_head(&self, action: Action) -> Result<Option<Oid>, anyhow::Error> {
+        match fs::read_to_string(self.git_path.join(action.head())) {
+            Ok(content) => Oid::parse(content.trim())
+                .map(Some)
+                .ok_or_else(|| anyhow::anyhow!("invalid {}: {}", action.head(), content.trim())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The author to commit with while a conflicted cherry-pick waits to
+    /// be committed: the one of the picked commit.
+    pub(super) fn picked_author(&self) -> Result<Option<Author>, anyhow::Error> {
+        Ok(match self.picked_head(Action::Pick)? {
+            Some(oid) => Some(self.db.read_commit(&oid)?.author().clone()),
+            None => None,
+        })
+    }
+
+    /// Forgets the commit a conflicted cherry-pick or revert was applying,
+    /// once it is committed or given up.
+    pub(super) fn clear_pick_state(&self) -> Result<(), anyhow::Error> {
+        for name in [CHERRY_PICK_HEAD, REVERT_HEAD, MERGE_MSG] {
+            match fs::remove_file(self.git_path.join(name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn read_todo(&self) -> Result<Option<Vec<Step>>, anyhow::Error> {
+        let content = match fs::read_to_string(self.sequencer_path().join("todo")) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut steps = Vec::new();
+        for line in content.lines() {
+            let mut words = line.split_whitespace();
+            let action = match words.next() {
+                None => continue,
+                Some(word) if word.starts_with('#') => continue,
+                Some("pick" | "p") => Action::Pick,
+                Some("revert") => Action::Revert,
+                Some(_) => return Err(anyhow::anyhow!("invalid line in todo list: {}", line)),
+            };
+            let rev = words
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("missing commit in todo list: {}", line))?;
+            steps.push(Step {
+                action,
+                commit: self.resolve_commit(rev)?,
+            });
+        }
+        Ok(Some(steps))
+    }
+
+    /// Saves the steps left, the current one first, and the commit HEAD is
+    /// at, which `--abort` checks before going back.
+    fn write_todo(&self, steps: &[Step]) -> Result<(), anyhow::Error> {
+        let mut todo = String::new();
+        for step in steps {
+            let commit = self.db.read_commit(&step.commit)?;
+            let subject = commit.message().lines().next().unwrap_or_default();
+            todo.push_str(&format!(
+                "{} {} {}\n",
+                step.action.command(),
+                step.commit.to_short(),
+                subject
+            ));
+        }
+
+        let path = self.sequencer_path();
+        fs::write(path.join("todo"), todo)?;
+        if let Some(head) = self.refs.read_ref("HEAD")? {
+            fs::write(path.join("abort-safety"), format!("{}\n", head))?;
+        }
+        Ok(())
+    }
+
+    fn remove_sequencer(&self) -> Result<(), anyhow::Error> {
+        match fs::remove_dir_all(self.sequencer_path()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuses to start when the index has changes HEAD does not. Local
+    /// changes in the work tree are fine as long as the merge leaves them
+    /// alone; `merge_trees` refuses to touch paths that have them.
+    fn check_index_matches_head(
+        &mut self,
+        head: &Oid,
+        action: Action,
+    ) -> Result<(), anyhow::Error> {
+        self.index.load()?;
+        if self.index_files()? != self.commit_files(head)? {
+            return Err(anyhow::anyhow!(
+                "your local changes would be overwritten by {}.",
+                action.name()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Moves HEAD from `head` to `commit` along with the index and the work
+    /// tree, the way `git reset --merge` does, which the reflog records
+    /// even when HEAD stays.
+    fn reset_to(&mut self, commit: &Oid, head: &Oid) -> Result<(), anyhow::Error> {
+        self.reset_merge(commit)?;
+        self.refs.set_head(
+            commit,
+            Some(*head),
+            &self.committer(),
+            &format!("reset: moving to {}", commit),
+        )
+    }
+
+    /// Applies each of `revs` to HEAD in turn, as `action` says, committing
+    /// each with its original author and message, or Git's revert message.
+    /// A `<from>..<to>` range stands for the commits `git rev-list` lists
+    /// for it, oldest first. When more than one commit is applied, the
+    /// original HEAD and the commits left are kept in `.git/sequencer`, so
+    /// the sequence can go on after a conflict. A conflict stops the
+    /// sequence, leaving conflict stages, CHERRY_PICK_HEAD or REVERT_HEAD
+    /// and MERGE_MSG behind, and `false` is returned.
+    pub fn sequencer_start(
+        &mut self,
+        action: Action,
+        revs: &[String],
+    ) -> Result<bool, anyhow::Error> {
+        work_tree(&self.workspace)?;
+
+        let mut steps = Vec::new();
+        for rev in revs {
+            let commits = match rev.split_once("..") {
+                Some((from, to)) => {
+                    let from = self.resolve_commit(if from.is_empty() { "HEAD" } else { from })?;
+                    let to = self.resolve_commit(if to.is_empty() { "HEAD" } else { to })?;
+                    let mut commits = range(&self.db, &from, &to)?;
+                    commits.reverse();
+                    commits
+                }
+                None => vec![self.resolve_commit(rev)?],
+            };
+            steps.extend(commits.into_iter().map(|commit| Step { action, commit }));
+        }
+        if steps.is_empty() {
+            return Err(anyhow::anyhow!("empty commit set passed"));
+        }
+
+        // A single commit is applied without sequencer state, like Git does,
+        // so it can be picked while a sequence is stopped
+        if steps.len() > 1 && self.sequencer_path().exists() {
+            let running = match self.read_todo()?.as_deref() {
+                Some([step, ..]) => step.action,
+                _ => action,
+            };
+            return Err(anyhow::anyhow!("{} is already in progress", running.name()));
+        }
+
+        self.index.load()?;
+        if self.index.conflicts().next().is_some() {
+            let verb = match action {
+                Action::Pick => "Cherry-picking",
+                Action::Revert => "Reverting",
+            };
+            return Err(anyhow::anyhow!(
+                "{} is not possible because you have unmerged files.",
+                verb
+            ));
+        }
+        if self.merge_head()?.is_some() {
+            return Err(anyhow::anyhow!(
+                "You have not concluded your merge (MERGE_HEAD exists)."
+            ));
+        }
+
+        let head = self
+            .refs
+            .read_ref("HEAD")?
+            .ok_or_else(|| anyhow::anyhow!("your current branch does not have any commits yet"))?;
+        self.check_index_matches_head(&head, action)?;
+
+        if steps.len() == 1 {
+            return self.apply(action, &steps[0].commit);
+        }
+        fs::create_dir(self.sequencer_path())?;
+        fs::write(self.sequencer_path().join("head"), format!("{}\n", head))?;
+        self.run_todo(steps)
+    }
+
+    /// Applies the steps of the todo list one by one, saving what is left
+    /// before each, and removes the sequencer state once all are done.
+    fn run_todo(&mut self, mut steps: Vec<Step>) -> Result<bool, anyhow::Error> {
+        while !steps.is_empty() {
+            self.write_todo(&steps)?;
+            let step = steps.remove(0);
+            if !self.apply(step.action, &step.commit)? {
+                return Ok(false);
+            }
+        }
+        self.remove_sequencer()?;
+        Ok(true)
+    }
+
+    /// Merges the changes of `commit` into HEAD, the index and the work
+    /// tree, three ways: a pick merges the commit into HEAD against its
+    /// parent, and a revert merges the parent against the commit.
+    pub(super) fn merge_commit(
+        &mut self,
+        action: Action,
+        commit_oid: &Oid,
+    ) -> Result<Merged, anyhow::Error> {
+        let commit = self.db.read_commit(commit_oid)?;
+        let parent = match commit.parents() {
+            [] => None,
+            [parent] => Some(*parent),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "commit {} is a merge but no -m option was given.",
+                    commit_oid
+                ))
+            }
+        };
+        let subject = commit.message().lines().next().unwrap_or_default();
+        let label = format!("{} ({})", commit_oid.to_short(), subject);
+        let parent_label = format!("parent of {}", label);
+
+        let commit_files = self.commit_files(commit_oid)?;
+        let parent_files = match parent {
+            Some(parent) => self.commit_files(&parent)?,
+            None => Files::new(),
+        };
+        let (base, base_name, theirs, theirs_name, author, message) = match action {
+            Action::Pick => (
+                parent_files,
+                &parent_label,
+                commit_files,
+                &label,
+                commit.author().clone(),
+                commit.message().to_string(),
+            ),
+            Action::Revert => (
+                commit_files,
+                &label,
+                parent_files,
+                &parent_label,
+                self.author(),
+                format!(
+                    "Revert \"{}\"\n\nThis reverts commit {}.\n",
+                    subject, commit_oid
+                ),
+            ),
+        };
+
+        let head = self
+            .refs
+            .read_ref("HEAD")?
+            .ok_or_else(|| anyhow::anyhow!("your current branch does not have any commits yet"))?;
+        let trees = [base, self.commit_files(&head)?, theirs];
+
+        self.lock_index()?;
+        let unresolved = self.merge_trees(trees, [base_name, "HEAD", theirs_name])?;
+        let tree = if unresolved.is_empty() {
+            Some(self.write_tree()?)
+        } else {
+            None
+        };
+
+        Ok(Merged {
+            head,
+            empty: tree == Some(self.db.read_commit(&head)?.tree()),
+            tree,
+            unresolved,
+            author,
+            message,
+        })
+    }
+
+    /// Cherry-picks or reverts `commit` on top of HEAD and commits the
+    /// result.
+    fn apply(&mut self, action: Action, commit_oid: &Oid) -> Result<bool, anyhow::Error> {
+        let Merged {
+            head,
+            empty,
+            tree,
+            unresolved,
+            author,
+            message,
+        } = self.merge_commit(action, commit_oid)?;
+        let subject = self
+            .db
+            .read_commit(commit_oid)?
+            .message()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        if empty {
+            fs::write(
+                self.git_path.join(action.head()),
+                format!("{}\n", commit_oid),
+            )?;
+            fs::write(self.git_path.join(MERGE_MSG), &message)?;
+            eprintln!(
+                "The previous {} is now empty, possibly due to conflict resolution.",
+                action.name()
+            );
+            eprintln!("If you wish to commit it anyway, use:\n");
+            eprintln!("    git {} --continue\n", action.name());
+            eprintln!("Otherwise, please use 'git {} --skip'", action.name());
+            return Ok(false);
+        }
+
+        let Some(tree) = tree else {
+            fs::write(
+                self.git_path.join(action.head()),
+                format!("{}\n", commit_oid),
+            )?;
+            fs::write(
+                self.git_path.join(MERGE_MSG),
+                conflicts_message(&message, &unresolved),
+            )?;
+
+            let name = action.name();
+            match action {
+                Action::Pick => eprintln!(
+                    "error: could not apply {}... {}",
+                    commit_oid.to_short(),
+                    subject
+                ),
+                Action::Revert => eprintln!(
+                    "error: could not revert {}... {}",
+                    commit_oid.to_short(),
+                    subject
+                ),
+            }
+            eprintln!("hint: After resolving the conflicts, mark them with");
+            eprintln!("hint: \"git add/rm <pathspec>\", then run");
+            eprintln!("hint: \"git {} --continue\".", name);
+            eprintln!(
+                "hint: You can instead skip this commit with \"git {} --skip\".",
+                name
+            );
+            eprintln!(
+                "hint: To abort and get back to the state before \"git {}\",",
+                name
+            );
+            eprintln!("hint: run \"git {} --abort\".", name);
+            return Ok(false);
+        };
+
+        let committer = self.committer();
+        let mut new_commit = Commit::new(tree, vec![head], author, committer.clone(), message);
+        let new_oid = self.db.store_object(&mut new_commit)?;
+
+        let new_subject = new_commit.message().lines().next().unwrap_or_default();
+        self.refs.set_head(
+            &new_oid,
+            Some(head),
+            &committer,
+            &format!("{}: {}", action.name(), new_subject),
+        )?;
+        println!("[{}] {}", new_oid, new_subject);
+        Ok(true)
+    }
+
+    /// Goes on with a cherry-pick or revert stopped by a conflict: the
+    /// resolved commit is made with the message from MERGE_MSG, unless it
+    /// was committed already, and the rest of the todo list is applied.
+    /// `no_verify` skips the pre-commit and commit-msg hooks for that commit.
+    pub fn sequencer_continue(
+        &mut self,
+        action: Action,
+        no_verify: bool,
+    ) -> Result<bool, anyhow::Error> {
+        work_tree(&self.workspace)?;
+        let todo = self.read_todo()?;
+        let picked = match self.picked_head(Action::Pick)? {
+            Some(oid) => Some(oid),
+            None => self.picked_head(Action::Revert)?,
+        };
+        if todo.is_none() && picked.is_none() {
+            return Err(anyhow::anyhow!("no cherry-pick or revert in progress"));
+        }
+
+        if picked.is_some() {
+            let message = fs::read_to_string(self.git_path.join(MERGE_MSG))?;
+            self.commit_with_message(Some(strip_comments(&message)), no_verify)?;
+        } else if let Some(head) = self.refs.read_ref("HEAD")? {
+            self.check_index_matches_head(&head, action)?;
+        }
+
+        match todo {
+            // The step that stopped is done now
+            Some(mut steps) if !steps.is_empty() => {
+                steps.remove(0);
+                self.run_todo(steps)
+            }
+            Some(_) => {
+                self.remove_sequencer()?;
+                Ok(true)
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// Drops the commit a cherry-pick or revert stopped at, with the
+    /// changes it left in the index and the work tree, and applies the
+    /// rest of the todo list.
+    pub fn sequencer_skip(&mut self, action: Action) -> Result<bool, anyhow::Error> {
+        work_tree(&self.workspace)?;
+        let todo = self.read_todo()?;
+        let picked = self.picked_head(action)?;
+
+        match (&todo, picked) {
+            (None, None) => {
+                return Err(anyhow::anyhow!("no {} in progress", action.name()));
+            }
+            (Some(_), None) => {
+                return Err(anyhow::anyhow!(
+                    "have you committed already?\ntry \"git {} --continue\"",
+                    action.name()
+                ));
+            }
+            (_, Some(_)) => {
+                let head = self
+                    .refs
+                    .read_ref("HEAD")?
+                    .ok_or_else(|| anyhow::anyhow!("HEAD does not point at a commit"))?;
+                self.reset_to(&head, &head)?;
+                self.clear_pick_state()?;
+            }
+        }
+
+        match todo {
+            Some(mut steps) if !steps.is_empty() => {
+                steps.remove(0);
+                self.run_todo(steps)
+            }
+            Some(_) => {
+                self.remove_sequencer()?;
+                Ok(true)
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// Gives up a cherry-pick or revert: HEAD, the index and the work tree
+    /// go back to where they were before it started. If HEAD was moved by
+    /// hand since the sequencer last left it, only the state is removed.
+    pub fn sequencer_abort(&mut self, action: Action) -> Result<(), anyhow::Error> {
+        work_tree(&self.workspace)?;
+        let sequencer = self.sequencer_path();
+        let picked = self.picked_head(action)?;
+        let head = self
+            .refs
+            .read_ref("HEAD")?
+            .ok_or_else(|| anyhow::anyhow!("HEAD does not point at a commit"))?;
+
+        if sequencer.exists() {
+            let read = |name: &str| -> Result<Option<Oid>, anyhow::Error> {
+                match fs::read_to_string(sequencer.join(name)) {
+                    Ok(content) => Ok(Oid::parse(content.trim())),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e.into()),
+                }
+            };
+            let original = read("head")?.ok_or_else(|| {
+                anyhow::anyhow!("could not parse {}", sequencer.join("head").display())
+            })?;
+
+            if read("abort-safety")?.is_some_and(|safe| safe != head) {
+                eprintln!("warning: You seem to have moved HEAD. Not rewinding, check your HEAD!");
+            } else {
+                self.reset_to(&original, &head)?;
+            }
+        } else if picked.is_some() {
+            self.reset_to(&head, &head)?;
+        } else {
+            return Err(anyhow::anyhow!("no cherry-pick or revert in progress"));
+        }
+
+        self.clear_pick_state()?;
+        self.remove_sequencer()
+    }
+}
@@ -0,0 +1,460 @@
+use std::{collections::HashSet, io, os::unix::ffi::OsStrExt, path::Path};
+
+use crate::oid::Oid;
+
+use super::ignore::IgnoreRules;
+use super::index::IndexEntry;
+use super::object::commit::Commit;
+use super::read_tree::Files;
+use super::revision::{ReflogSelector, Revision};
+use super::{work_tree, Repository};
+
+const STASH: &str = "refs/stash";
+
+/// The commits a stash entry is made of.
+struct Stash {
+    /// The work tree commit, whose parents are the others
+    commit: Oid,
+    /// HEAD when the entry was made
+    base: Oid,
+    /// The index
+    index: Oid,
+    /// Untracked files, with `--include-untracked`
+    untracked: Option<Oid>,
+}
+
+impl Repository {
+    /// Which entry of the stash reflog `stash` names: `stash@{<n>}`, or
+    /// just `<n>`. The default is the newest entry.
+    fn stash_index(&self, stash: Option<&str>) -> Result<usize, anyhow::Error> {
+        let Some(stash) = stash else {
+            return Ok(0);
+        };
+        if let Ok(index) = stash.parse() {
+            return Ok(index);
+        }
+
+        match Revision::parse(stash)? {
+            Revision::Reflog(Some(name), ReflogSelector::Index(index))
+                if Revision::reflog_ref(Some(&name), &self.refs)? == STASH =>
+            {
+                Ok(index)
+            }
+            _ => Err(anyhow::anyhow!("'{}' is not a stash reference", stash)),
+        }
+    }
+
+    /// Reads the stash entry `stash` names, by default the newest one; any
+    /// commit that looks like a stash entry may be named.
+    fn read_stash(&self, stash: Option<&str>) -> Result<Stash, anyhow::Error> {
+        let entries = self.refs.reflog().read(STASH)?;
+        if entries.is_empty() && stash.is_none() {
+            return Err(anyhow::anyhow!("No stash entries found."));
+        }
+
+        let commit = match self.stash_index(stash) {
+            Ok(index) => {
+                entries
+                    .iter()
+                    .rev()
+                    .nth(index)
+                    .ok_or_else(|| anyhow::anyhow!("stash@{{{}}} is not a valid reference", index))?
+                    .new
+            }
+            Err(_) => self.resolve_commit(stash.unwrap_or_default())?,
+        };
+
+        match *self.db.read_commit(&commit)?.parents() {
+            [base, index] => Ok(Stash {
+                commit,
+                base,
+                index,
+                untracked: None,
+            }),
+            [base, index, untracked] => Ok(Stash {
+                commit,
+                base,
+                index,
+                untracked: Some(untracked),
+            }),
+            _ => Err(anyhow::anyhow!(
+                "'{}' is not a stash-like commit",
+                stash.unwrap_or_default()
+            )),
+        }
+    }
+
+    /// The files of the work tree for the paths the index tracks, with
+    /// changed contents stored as blobs.
+    fn work_tree_files(&self) -> Result<Files, anyhow::Error> {
+        let workspace = work_tree(&self.workspace)?;
+        let mut files = Files::new();
+
+        for entry in self.index.entries.values() {
+            let path = entry.path.to_str()?;
+            match self.file_matches_entry(entry)? {
+                Some(true) => {
+                    files.insert(path.to_string(), (entry.oid, entry.mode));
+                }
+                Some(false) => {
+                    let data = workspace.read_file(Path::new(path))?;
+                    let metadata = workspace.stat_file(Path::new(path))?;
+                    let oid = self.db.hash_object(b"blob", &data, true)?;
+                    files.insert(path.to_string(), (oid, IndexEntry::mode_for(&metadata)));
+                }
+                None => {}
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// The files in the work tree the index does not track and the ignore
+    /// rules do not match, stored as blobs.
+    fn untracked_files(&self) -> Result<Files, anyhow::Error> {
+        let workspace = work_tree(&self.workspace)?;
+        let mut rules = IgnoreRules::new(
+            &workspace.root,
+            &self.git_path,
+            self.config.get("core.excludesFile"),
+        )?;
+        let tracked: HashSet<&[u8]> = self
+            .index
+            .entries
+            .values()
+            .map(|entry| entry.path.as_bytes())
+            .collect();
+        let mut files = Files::new();
+
+        for path in workspace.list_files(None)? {
+            if tracked.contains(path.as_os_str().as_bytes())
+                || rules.is_ignored(&path.to_string_lossy(), false)?
+            {
+                continue;
+            }
+            let data = workspace.read_file(&path)?;
+            let metadata = workspace.stat_file(&path)?;
+            let oid = self.db.hash_object(b"blob", &data, true)?;
+            files.insert(
+                path.to_str()
+                    .ok_or_else(|| anyhow::anyhow!("invalid path {:?}", path))?
+                    .to_string(),
+                (oid, IndexEntry::mode_for(&metadata)),
+            );
+        }
+
+        Ok(files)
+    }
+
+    /// Makes the index and the work tree hold exactly `files`, as `git
+    /// reset --hard` does; untracked files are left alone.
+    fn reset_hard(&mut self, files: &Files) -> Result<(), anyhow::Error> {
+        self.lock_index()?;
+
+        let mut paths: Vec<String> = self
+            .index
+            .entries
+            .values()
+            .map(|entry| Ok(entry.path.to_str()?.to_string()))
+            .collect::<Result<_, anyhow::Error>>()?;
+        paths.extend(files.keys().cloned());
+        paths.sort();
+        paths.dedup();
+
+        let workspace = work_tree(&self.workspace)?;
+        for path in paths {
+            let unchanged = match (self.index.get(&path), files.get(&path)) {
+                (Some(entry), Some(&(oid, mode))) => {
+                    !self.index.is_conflicted(&path)
+                        && (entry.oid, entry.mode) == (oid, mode)
+                        && self.file_matches_entry(entry)? == Some(true)
+                }
+                _ => false,
+            };
+            if unchanged {
+                continue;
+            }
+
+            match files.get(&path) {
+                Some(&(oid, mode)) => {
+                    let (_, data) = self.db.read_raw(&oid)?;
+                    workspace.write_file(Path::new(&path), &data, mode)?;
+                    let mut entry = IndexEntry::from_object(&path, oid, mode)?;
+                    entry.update_stat(&workspace.stat_file(Path::new(&path))?);
+                    self.index.insert(entry);
+                }
+                None => {
+                    self.index.remove(&path);
+                    match workspace.remove_file(Path::new(&path)) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        self.index.write_updates()?;
+        Ok(())
+    }
+
+    /// Saves the local changes as a new stash entry and brings the index
+    /// and the work tree back to HEAD. The entry is a commit of the work
+    /// tree whose parents are HEAD, a commit of the index and, with
+    /// `include_untracked`, a commit of the untracked files, which are then
+    /// removed. `keep_index` leaves what is staged in place.
+    pub fn stash_push(
+        &mut self,
+        message: Option<&str>,
+        include_untracked: bool,
+        keep_index: bool,
+    ) -> Result<(), anyhow::Error> {
+        work_tree(&self.workspace)?;
+        let head = self
+            .refs
+            .read_ref("HEAD")?
+            .ok_or_else(|| anyhow::anyhow!("You do not have the initial commit yet"))?;
+
+        self.index.load()?;
+        if self.index.conflicts().next().is_some() {
+            return Err(anyhow::anyhow!("Cannot save the current index state"));
+        }
+        let head_files = self.commit_files(&head)?;
+        let index_files = self.index_files()?;
+        let work_files = self.work_tree_files()?;
+        let untracked = if include_untracked {
+            self.untracked_files()?
+        } else {
+            Files::new()
+        };
+
+        if index_files == head_files && work_files == index_files && untracked.is_empty() {
+            println!("No local changes to save");
+            return Ok(());
+        }
+
+        let branch = self.refs.current_ref()?;
+        let branch = branch.strip_prefix("refs/heads/").unwrap_or("(no branch)");
+        let head_commit = self.db.read_commit(&head)?;
+        let summary = format!(
+            "{} {}",
+            head.to_short(),
+            head_commit.message().lines().next().unwrap_or_default()
+        );
+        let author = self.author();
+        let committer = self.committer();
+
+        let store = |tree: Oid, parents: Vec<Oid>, message: String| {
+            let mut commit = Commit::new(tree, parents, author.clone(), committer.clone(), message);
+            self.db.store_object(&mut commit)
+        };
+        let index_tree = self.write_files_tree(&index_files)?;
+        let index_commit = store(
+            index_tree,
+            vec![head],
+            format!("index on {}: {}\n", branch, summary),
+        )?;
+        let mut parents = vec![head, index_commit];
+        if !untracked.is_empty() {
+            let tree = self.write_files_tree(&untracked)?;
+            parents.push(store(
+                tree,
+                Vec::new(),
+                format!("untracked files on {}: {}\n", branch, summary),
+            )?);
+        }
+        let message = match message {
+            Some(message) => format!("On {}: {}\n", branch, message),
+            None => format!("WIP on {}: {}\n", branch, summary),
+        };
+        let tree = self.write_files_tree(&work_files)?;
+        let stash = store(tree, parents, message.clone())?;
+
+        self.refs
+            .update_ref(STASH, &stash, None, &committer, &message)?;
+        println!(
+            "Saved working directory and index state {}",
+            message.trim_end()
+        );
+
+        self.reset_hard(if keep_index {
+            &index_files
+        } else {
+            &head_files
+        })?;
+        let workspace = work_tree(&self.workspace)?;
+        for path in untracked.keys() {
+            workspace.remove_file(Path::new(path))?;
+        }
+        Ok(())
+    }
+
+    /// Lists the stash entries, newest first.
+    pub fn stash_list(&self) -> Result<(), anyhow::Error> {
+        for (i, entry) in self.refs.reflog().read(STASH)?.iter().rev().enumerate() {
+            println!("stash@{{{}}}: {}", i, entry.message);
+        }
+        Ok(())
+    }
+
+    /// Applies the stash entry `stash` to the index and the work tree: the
+    /// work tree changes are merged with the current index against the
+    /// commit the entry was made on, with conflicts left as stages. What
+    /// the entry had staged is restaged with `index`; otherwise the index
+    /// only keeps files the entry adds. Untracked files are restored unless
+    /// they exist. Nothing is changed when files the entry touches have
+    /// local changes. Returns `false` when something could not be applied.
+    pub fn stash_apply(&mut self, stash: Option<&str>, index: bool) -> Result<bool, anyhow::Error> {
+        work_tree(&self.workspace)?;
+        let stash = self.read_stash(stash)?;
+
+        self.index.load()?;
+        if self.index.conflicts().next().is_some() {
+            return Err(anyhow::anyhow!(
+                "cannot apply a stash in the middle of a merge"
+            ));
+        }
+        let current = self.index_files()?;
+        let base = self.commit_files(&stash.base)?;
+        let staged = self.commit_files(&stash.index)?;
+
+        // The staged changes are laid over the current index first
+        let restaged = if index && staged != base && staged != current {
+            let mut restaged = current.clone();
+            let mut paths: Vec<&String> = base.keys().chain(staged.keys()).collect();
+            paths.sort();
+            paths.dedup();
+            for path in paths {
+                let (b, i, c) = (base.get(path), staged.get(path), current.get(path));
+                if i == b || i == c {
+                    continue;
+                }
+                if c != b {
+                    return Err(anyhow::anyhow!("Conflicts in index. Try without --index."));
+                }
+                match i {
+                    Some(file) => restaged.insert(path.clone(), *file),
+                    None => restaged.remove(path),
+                };
+            }
+            Some(restaged)
+        } else {
+            None
+        };
+
+        let ours = if base == current {
+            "Version stash was based on"
+        } else {
+            "Updated upstream"
+        };
+        let trees = [base, current.clone(), self.commit_files(&stash.commit)?];
+        self.lock_index()?;
+        let unresolved = self.merge_trees(trees, ["Stash base", ours, "Stashed changes"])?;
+        let mut applied = unresolved.is_empty();
+
+        if !applied {
+            if restaged.is_some() {
+                eprintln!("Index was not unstashed.");
+            }
+        } else {
+            self.lock_index()?;
+            let wanted = restaged.as_ref().unwrap_or(&current);
+            let merged = self.index_files()?;
+            for (path, file) in &merged {
+                if wanted.get(path) == Some(file) {
+                    continue;
+                }
+                match wanted.get(path) {
+                    Some(&(oid, mode)) => {
+                        self.index.insert(IndexEntry::from_object(path, oid, mode)?);
+                    }
+                    // Files the stash adds stay added
+                    None if restaged.is_none() => {}
+                    None => {
+                        self.index.remove(path);
+                    }
+                }
+            }
+            for (path, &(oid, mode)) in wanted {
+                if !merged.contains_key(path) {
+                    self.index.insert(IndexEntry::from_object(path, oid, mode)?);
+                }
+            }
+            self.index.write_updates()?;
+        }
+
+        if let Some(untracked) = stash.untracked {
+            let workspace = work_tree(&self.workspace)?;
+            let mut restored = true;
+            for (path, (oid, mode)) in self.commit_files(&untracked)? {
+                if workspace.stat_file(Path::new(&path)).is_ok() {
+                    eprintln!("{} already exists, no checkout", path);
+                    restored = false;
+                    continue;
+                }
+                let (_, data) = self.db.read_raw(&oid)?;
+                workspace.write_file(Path::new(&path), &data, mode)?;
+            }
+            if !restored {
+                eprintln!("error: could not restore untracked files from stash");
+                applied = false;
+            }
+        }
+
+        Ok(applied)
+    }
+
+    /// Applies the stash entry `stash` and drops it, unless it could not
+    /// be applied cleanly.
+    pub fn stash_pop(&mut self, stash: Option<&str>, index: bool) -> Result<bool, anyhow::Error> {
+        if !self.stash_apply(stash, index)? {
+            eprintln!("The stash entry is kept in case you need it again.");
+            return Ok(false);
+        }
+        self.stash_drop(stash)?;
+        Ok(true)
+    }
+
+    /// Removes the stash entry `stash`, the newest one by default. The
+    /// stash ref moves to the next entry, or goes away with the last one.
+    pub fn stash_drop(&mut self, stash: Option<&str>) -> Result<(), anyhow::Error> {
+        let index = self.stash_index(stash)?;
+        let mut entries = self.refs.reflog().read(STASH)?;
+        if entries.is_empty() {
+            return Err(anyhow::anyhow!("No stash entries found."));
+        }
+        if index >= entries.len() {
+            return Err(anyhow::anyhow!(
+                "stash@{{{}}} is not a valid reference",
+                index
+            ));
+        }
+
+        // The newer entry now follows the one before the dropped entry
+        let position = entries.len() - 1 - index;
+        let dropped = entries.remove(position);
+        if let Some(newer) = entries.get_mut(position) {
+            newer.old = dropped.old;
+        }
+
+        let committer = self.committer();
+        match entries.last() {
+            None => self
+                .refs
+                .delete_ref(STASH, None, &committer, "stash: drop")?,
+            Some(newest) => {
+                if index == 0 {
+                    self.refs
+                        .update_ref(STASH, &newest.new, None, &committer, "stash: drop")?;
+                }
+                self.refs.reflog().write(STASH, &entries)?;
+            }
+        }
+
+        let name = match stash {
+            Some(stash) if stash.parse::<usize>().is_err() => stash.to_string(),
+            _ => format!("refs/stash@{{{}}}", index),
+        };
+        println!("Dropped {} ({})", name, dropped.new);
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    cmp::Ordering,
+    io::{self, Read},
+};
+
+use crate::oid::Oid;
+use crate::wildmatch::wildmatch;
+
+use super::object::{commit::Commit, tag::Tag};
+use super::refs::check_ref_format;
+use super::revision::Revision;
+use super::Repository;
+
+const TAGS_PREFIX: &str = "refs/tags/";
+
+/// Compares names the way `version:refname` sorting does: runs of digits
+/// compare numerically, everything else byte by byte.
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.as_bytes();
+    let mut b = b.as_bytes();
+
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+                let b_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+
+                let a_num = std::str::from_utf8(&a[..a_len])
+                    .unwrap()
+                    .trim_start_matches('0');
+                let b_num = std::str::from_utf8(&b[..b_len])
+                    .unwrap()
+                    .trim_start_matches('0');
+
+                let ordering = a_num.len().cmp(&b_num.len()).then(a_num.cmp(b_num));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+
+                a = &a[a_len..];
+                b = &b[b_len..];
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
+/// Sorts `(name, oid)` pairs by a `--sort` key: `refname` or
+/// `version:refname` (alias `v:refname`), reversed with a leading `-`.
+pub fn sort_refs(refs: &mut [(String, Oid)], key: &str) -> Result<(), anyhow::Error> {
+    let (reverse, key) = match key.strip_prefix('-') {
+        Some(key) => (true, key),
+        None => (false, key),
+    };
+
+    match key {
+        "refname" => refs.sort_by(|a, b| a.0.cmp(&b.0)),
+        "version:refname" | "v:refname" => refs.sort_by(|a, b| version_cmp(&a.0, &b.0)),
+        _ => return Err(anyhow::anyhow!("unsupported sort key: {}", key)),
+    }
+
+    if reverse {
+        refs.reverse();
+    }
+    Ok(())
+}
+
+impl Repository {
+    fn tag_ref(name: &str) -> Result<String, anyhow::Error> {
+        let full_name = format!("{}{}", TAGS_PREFIX, name);
+        check_ref_format(&full_name)
+            .map_err(|_| anyhow::anyhow!("'{}' is not a valid tag name.", name))?;
+        Ok(full_name)
+    }
+
+    /// Creates tag `name` pointing at `target` (HEAD by default). With a
+    /// message an annotated tag object is created; `annotate` without a
+    /// message reads it from standard input.
+    pub fn tag_create(
+        &self,
+        name: &str,
+        target: Option<&str>,
+        annotate: bool,
+        message: Option<&str>,
+        force: bool,
+    ) -> Result<(), anyhow::Error> {
+        let full_name = Self::tag_ref(name)?;
+        let target = Revision::parse(target.unwrap_or("HEAD"))?.resolve(&self.refs, &self.db)?;
+
+        let existing = self.refs.read_ref(&full_name)?;
+        if existing.is_some() && !force {
+            return Err(anyhow::anyhow!("tag '{}' already exists", name));
+        }
+
+        let oid = if annotate || message.is_some() {
+            let message = match message {
+                Some(message) => format!("{}\n", message.trim_end()),
+                None => {
+                    let mut message = String::new();
+                    io::stdin().read_to_string(&mut message)?;
+                    message
+                }
+            };
+
+            let (kind, _) = self.db.read_raw(&target)?;
+            let mut tag = Tag::new(target, &kind, name, self.committer(), message);
+            self.db.store_object(&mut tag)?
+        } else {
+            target
+        };
+
+        let expected = if force { existing } else { Some(Oid::zero()) };
+        self.refs
+            .update_ref(&full_name, &oid, expected, &self.committer(), "")?;
+
+        if let Some(old) = existing.filter(|old| *old != oid) {
+            println!("Updated tag '{}' (was {})", name, old.to_short());
+        }
+
+        Ok(())
+    }
+
+    pub fn tag_delete(&self, names: &[String]) -> Result<(), anyhow::Error> {
+        for name in names {
+            let full_name = Self::tag_ref(name)?;
+            let oid = self
+                .refs
+                .read_ref(&full_name)?
+                .ok_or_else(|| anyhow::anyhow!("tag '{}' not found.", name))?;
+
+            self.refs
+                .delete_ref(&full_name, Some(oid), &self.committer(), "")?;
+            println!("Deleted tag '{}' (was {})", name, oid.to_short());
+        }
+
+        Ok(())
+    }
+
+    /// First `lines` lines of a tag's annotation, or of the tagged commit's
+    /// message for lightweight tags.
+    fn tag_annotation(&self, oid: &Oid, lines: usize) -> Result<Vec<String>, anyhow::Error> {
+        let (kind, data) = self.db.read_raw(oid)?;
+
+        let message = match kind.as_str() {
+            "tag" => Tag::parse(&data)?.message().to_string(),
+            "commit" => Commit::parse(&data)?.message().to_string(),
+            _ => String::new(),
+        };
+
+        Ok(message
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .take(lines)
+            .map(str::to_string)
+            .collect())
+    }
+
+    pub fn tag_list(
+        &self,
+        patterns: &[String],
+        sort: Option<&str>,
+        lines: Option<usize>,
+    ) -> Result<(), anyhow::Error> {
+        let mut tags: Vec<(String, Oid)> = self
+            .refs
+            .list_refs(TAGS_PREFIX)?
+            .into_iter()
+            .map(|(name, oid)| (name[TAGS_PREFIX.len()..].to_string(), oid))
+            .filter(|(name, _)| {
+                patterns.is_empty() || patterns.iter().any(|p| wildmatch(p, name, false, false))
+            })
+            .collect();
+
+        sort_refs(
+            &mut tags,
+            sort.or(self.config.get("tag.sort")).unwrap_or("refname"),
+        )?;
+
+        for (name, oid) in tags {
+            match lines {
+                None => println!("{}", name),
+                Some(lines) => {
+                    let annotation = self.tag_annotation(&oid, lines)?;
+                    println!(
+                        "{:<15} {}",
+                        name,
+                        annotation.first().map(String::as_str).unwrap_or_default()
+                    );
+                    for line in annotation.iter().skip(1) {
+                        println!("    {}", line);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
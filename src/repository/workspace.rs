@@ -1,11 +1,13 @@
 use std::{
     fs, io,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
     path::{Path, PathBuf},
 };
 
-use lexical_sort::natural_lexical_cmp;
 use walkdir::WalkDir;
 
+use super::discover::normalize;
+
 pub struct Workspace {
     pub root: PathBuf,
 }
@@ -15,7 +17,24 @@ impl Workspace {
         Self { root: path }
     }
 
+    /// Lists the files below `path`, or the whole work tree, sorted
+    /// bytewise. Nested repositories are skipped.
     pub fn list_files(&self, path: Option<&PathBuf>) -> Result<Vec<PathBuf>, anyhow::Error> {
+        self.walk(path, false)
+    }
+
+    /// Lists the directories of the work tree, sorted bytewise. Nested
+    /// repositories are listed but not entered.
+    pub fn list_dirs(&self) -> Result<Vec<PathBuf>, anyhow::Error> {
+        self.walk(None, true)
+    }
+
+    /// Whether the directory `path` holds a repository of its own.
+    pub fn is_nested_repository(&self, path: &Path) -> bool {
+        !path.as_os_str().is_empty() && self.root.join(path).join(".git").exists()
+    }
+
+    fn walk(&self, path: Option<&PathBuf>, dirs: bool) -> Result<Vec<PathBuf>, anyhow::Error> {
         let db_path = self.root.join(".git");
         let path = match path {
             Some(p) => self.root.join(p),
@@ -23,22 +42,30 @@ impl Workspace {
         };
 
         let mut list_result: Vec<PathBuf> = Vec::new();
-        for entry in WalkDir::new(path)
+        let mut entries = WalkDir::new(path)
             .follow_links(false)
             .follow_root_links(false)
             .same_file_system(true)
             .into_iter()
-            .filter_entry(|e| !e.path().starts_with(&db_path))
-        {
+            .filter_entry(|e| !e.path().starts_with(&db_path));
+        while let Some(entry) = entries.next() {
             let entry = entry?;
+            let relative_path = entry.path().strip_prefix(&self.root)?;
+            let is_dir = entry.metadata()?.is_dir();
 
-            if !entry.metadata()?.is_dir() {
-                let relative_path = entry.path().strip_prefix(&self.root)?;
+            if is_dir && self.is_nested_repository(relative_path) {
+                if dirs {
+                    list_result.push(relative_path.to_owned());
+                }
+                entries.skip_current_dir();
+                continue;
+            }
+            if is_dir == dirs && !relative_path.as_os_str().is_empty() {
                 list_result.push(relative_path.to_owned());
             }
         }
 
-        list_result.sort_by(|a, b| natural_lexical_cmp(a.to_str().unwrap(), b.to_str().unwrap()));
+        list_result.sort_by(|a, b| a.as_os_str().as_bytes().cmp(b.as_os_str().as_bytes()));
         Ok(list_result)
     }
 
@@ -47,6 +74,56 @@ impl Workspace {
     }
 
     pub fn stat_file(&self, path: &Path) -> Result<fs::Metadata, io::Error> {
-        fs::metadata(path)
+        fs::metadata(self.root.join(path))
+    }
+
+    /// Writes `data` to `path`, creating parent directories and replacing
+    /// whatever was there, with the executable bit set from `mode`.
+    pub fn write_file(&self, path: &Path, data: &[u8], mode: u32) -> Result<(), io::Error> {
+        let full_path = self.root.join(path);
+
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if full_path.is_dir() {
+            fs::remove_dir_all(&full_path)?;
+        }
+
+        fs::write(&full_path, data)?;
+        let permissions = if mode & 0o111 != 0 { 0o755 } else { 0o644 };
+        fs::set_permissions(&full_path, fs::Permissions::from_mode(permissions))
+    }
+
+    /// Removes the file at `path` along with any parent directories this
+    /// leaves empty.
+    pub fn remove_file(&self, path: &Path) -> Result<(), io::Error> {
+        match fs::remove_file(self.root.join(path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        for parent in path.ancestors().skip(1) {
+            if parent.as_os_str().is_empty() || fs::remove_dir(self.root.join(parent)).is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn relative_path(&self, cwd: &Path, path: &Path) -> Result<PathBuf, anyhow::Error> {
+        let absolute = normalize(&cwd.join(path));
+
+        absolute
+            .strip_prefix(&self.root)
+            .map(Path::to_path_buf)
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "{}: '{}' is outside repository at '{}'",
+                    path.display(),
+                    absolute.display(),
+                    self.root.display()
+                )
+            })
     }
 }
@@ -0,0 +1,126 @@
+//! Content similarity scored the way Git's diffcore does for rename and
+//! copy detection: files are cut into chunks, and a destination is as
+//! similar to a source as the share of its bytes in chunks they have in
+//! common.
+
+use std::collections::HashMap;
+
+use crate::merge_file::is_binary;
+
+/// The score of identical files. Thresholds and scores are fractions of it.
+pub const MAX_SCORE: u64 = 60000;
+/// The threshold used when none is given: half of the file.
+pub const DEFAULT_SCORE: u64 = MAX_SCORE / 2;
+
+const HASHBASE: u32 = 107927;
+/// Chunks end at a newline or after this many bytes.
+const MAX_CHUNK: usize = 64;
+
+/// Parses a threshold the way `-M` and `-C` take it: digits are a
+/// fraction, so `5` and `50` are both half, unless followed by `%`.
+/// Returns the score and what follows the number.
+pub fn parse_score(arg: &str) -> (u64, &str) {
+    let (mut num, mut scale) = (0u64, 1u64);
+    let mut dot = false;
+    let mut rest = arg;
+
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '.' if !dot => {
+                scale = 1;
+                dot = true;
+            }
+            '%' => {
+                scale = if dot { scale * 100 } else { 100 };
+                rest = &rest[1..];
+                break;
+            }
+            '0'..='9' => {
+                if scale < 100000 {
+                    scale *= 10;
+                    num = num * 10 + u64::from(c as u8 - b'0');
+                }
+            }
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+
+    let score = if num >= scale {
+        MAX_SCORE
+    } else {
+        MAX_SCORE * num / scale
+    };
+    (score, rest)
+}
+
+/// The number of bytes in each chunk hash of a file.
+#[derive(Debug)]
+pub struct Fingerprint {
+    counts: HashMap<u32, u64>,
+}
+
+impl Fingerprint {
+    /// Counts the chunks of `data`. Like Git, a last chunk cut short by
+    /// the end of the file, without a newline, is not counted.
+    pub fn new(data: &[u8]) -> Self {
+        let text = !is_binary(data);
+        let mut counts = HashMap::new();
+        let (mut accum1, mut accum2) = (0u32, 0u32);
+        let mut n = 0;
+
+        for (i, &c) in data.iter().enumerate() {
+            // The CR of a CRLF does not count in text
+            if text && c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+
+            let old1 = accum1;
+            accum1 = (accum1 << 7) ^ (accum2 >> 25);
+            accum2 = (accum2 << 7) ^ (old1 >> 25);
+            accum1 = accum1.wrapping_add(u32::from(c));
+            n += 1;
+            if n < MAX_CHUNK && c != b'\n' {
+                continue;
+            }
+
+            *counts.entry(span_hash(accum1, accum2)).or_insert(0) += n as u64;
+            n = 0;
+            accum1 = 0;
+            accum2 = 0;
+        }
+
+        Self { counts }
+    }
+
+    /// How many of this file's bytes are found in `src`.
+    pub fn copied_from(&self, src: &Fingerprint) -> u64 {
+        src.counts
+            .iter()
+            .map(|(hash, &count)| count.min(self.counts.get(hash).copied().unwrap_or(0)))
+            .sum()
+    }
+}
+
+fn span_hash(accum1: u32, accum2: u32) -> u32 {
+    accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE
+}
+
+/// Whether a file of `src_size` bytes could reach `min_score` against one
+/// of `dst_size` bytes at all. Pairs whose sizes differ too much are never
+/// compared.
+pub fn sizes_compatible(src_size: usize, dst_size: usize, min_score: u64) -> bool {
+    let max = src_size.max(dst_size) as u64;
+    let delta = max - src_size.min(dst_size) as u64;
+    max * (MAX_SCORE - min_score) >= delta * MAX_SCORE
+}
+
+/// Scores how much of `dst` comes from `src`, relative to the larger of
+/// the two sizes.
+pub fn score(src: &Fingerprint, src_size: usize, dst: &Fingerprint, dst_size: usize) -> u64 {
+    let max = src_size.max(dst_size) as u64;
+    if dst_size == 0 {
+        return 0;
+    }
+    dst.copied_from(src) * MAX_SCORE / max
+}
@@ -0,0 +1,114 @@
+/// Matches `text` against a shell glob `pattern` supporting `*`, `?`,
+/// `[...]` classes (with `!`/`^` negation and ranges) and `\` escapes.
+///
+/// With `pathname` set, wildcards do not match `/`, except for `**`
+/// which matches across directory boundaries, as in `.gitignore`.
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool, icase: bool) -> bool {
+    let (pattern, text) = if icase {
+        (pattern.to_lowercase(), text.to_lowercase())
+    } else {
+        (pattern.to_string(), text.to_string())
+    };
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text, pathname)
+}
+
+fn match_from(pattern: &[char], text: &[char], pathname: bool) -> bool {
+    let Some((&p, rest)) = pattern.split_first() else {
+        return text.is_empty();
+    };
+
+    match p {
+        '*' => {
+            let double = rest.first() == Some(&'*');
+            let rest = if double { &rest[1..] } else { rest };
+            let crosses_dirs = double || !pathname;
+
+            // `**/` also matches zero directories
+            if double && rest.first() == Some(&'/') && match_from(&rest[1..], text, pathname) {
+                return true;
+            }
+
+            for i in 0..=text.len() {
+                if match_from(rest, &text[i..], pathname) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' && !crosses_dirs {
+                    return false;
+                }
+            }
+            false
+        }
+        '?' => match text.split_first() {
+            Some((&c, text_rest)) if !(pathname && c == '/') => {
+                match_from(rest, text_rest, pathname)
+            }
+            _ => false,
+        },
+        '[' => {
+            let Some((&c, text_rest)) = text.split_first() else {
+                return false;
+            };
+            if pathname && c == '/' {
+                return false;
+            }
+            match match_class(rest, c) {
+                Some((true, after)) => match_from(after, text_rest, pathname),
+                Some((false, _)) => false,
+                // An unterminated class is matched literally
+                None => c == '[' && match_from(rest, text_rest, pathname),
+            }
+        }
+        '\\' if !rest.is_empty() => match text.split_first() {
+            Some((&c, text_rest)) if c == rest[0] => match_from(&rest[1..], text_rest, pathname),
+            _ => false,
+        },
+        _ => match text.split_first() {
+            Some((&c, text_rest)) if c == p => match_from(rest, text_rest, pathname),
+            _ => false,
+        },
+    }
+}
+
+/// Matches `c` against the class starting right after `[`, returning
+/// whether it matched and the remaining pattern after `]`.
+fn match_class(pattern: &[char], c: char) -> Option<(bool, &[char])> {
+    let (negated, mut i) = match pattern.first() {
+        Some('!') | Some('^') => (true, 1),
+        _ => (false, 0),
+    };
+
+    let mut matched = false;
+    let mut first = true;
+
+    while i < pattern.len() {
+        let p = pattern[i];
+        if p == ']' && !first {
+            return Some((matched != negated, &pattern[i + 1..]));
+        }
+        first = false;
+
+        let (start, next) = if p == '\\' && i + 1 < pattern.len() {
+            (pattern[i + 1], i + 2)
+        } else {
+            (p, i + 1)
+        };
+
+        if pattern.get(next) == Some(&'-') && pattern.get(next + 1).is_some_and(|&e| e != ']') {
+            let end = pattern[next + 1];
+            if start <= c && c <= end {
+                matched = true;
+            }
+            i = next + 2;
+        } else {
+            if start == c {
+                matched = true;
+            }
+            i = next;
+        }
+    }
+
+    None
+}